@@ -1,3 +1,5 @@
+use std::fmt::{Display, Formatter};
+
 use serde::{Deserialize, Serialize};
 use transprompt::prompt::PromptTemplate;
 use uuid::Uuid;
@@ -29,6 +31,16 @@ impl AgentName {
     }
 }
 
+impl Display for AgentName {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            AgentName::UserDefault => write!(f, "User"),
+            AgentName::AssistantDefault => write!(f, "Assistant"),
+            AgentName::Named(name) => write!(f, "{}", name),
+        }
+    }
+}
+
 
 #[non_exhaustive]
 #[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
@@ -94,11 +106,73 @@ impl AgentInstance {
     }
 }
 
+/// Which provider profile and model an agent talks to
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq, Default)]
+pub struct AgentProvider {
+    /// name of a provider profile, `None` means the profile in use
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub profile: Option<String>,
+    /// model id, `None` means the default model of the profile
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub model: Option<String>,
+}
+
+impl AgentProvider {
+    pub fn is_default(&self) -> bool {
+        self.profile.is_none() && self.model.is_none()
+    }
+
+    /// Parse `profile:model`, where either part can be left empty
+    pub fn parse(s: &str) -> Self {
+        let non_empty = |s: &str| {
+            let s = s.trim();
+            if s.is_empty() { None } else { Some(s.to_string()) }
+        };
+        match s.split_once(':') {
+            Some((profile, model)) => Self {
+                profile: non_empty(profile),
+                model: non_empty(model),
+            },
+            None => Self {
+                profile: non_empty(s),
+                model: None,
+            },
+        }
+    }
+}
+
+impl Display for AgentProvider {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match (&self.profile, &self.model) {
+            (profile, Some(model)) => write!(f, "{}:{}", profile.as_deref().unwrap_or_default(), model),
+            (profile, None) => write!(f, "{}", profile.as_deref().unwrap_or_default()),
+        }
+    }
+}
+
+/// How to cut the history of an agent down to the context window of its model
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum TruncationPolicy {
+    /// drop the oldest turns while keeping the system prompt
+    #[default]
+    DropOldest,
+    /// keep the system prompt and the most recent N turns, each of a user message and the replies to it
+    KeepRecent(usize),
+}
+
 #[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
 pub struct AgentConfig {
     pub name: AgentName,
     pub description: String,
     pub agent_type: AgentType,
+    #[serde(default, skip_serializing_if = "AgentProvider::is_default")]
+    pub provider: AgentProvider,
+    /// providers to switch to in order when the provider keeps failing
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub fallbacks: Vec<AgentProvider>,
+    #[serde(default)]
+    pub truncation: TruncationPolicy,
 }
 
 impl AgentConfig {
@@ -107,6 +181,9 @@ impl AgentConfig {
             name,
             description: description.into(),
             agent_type: AgentType::User,
+            provider: AgentProvider::default(),
+            fallbacks: vec![],
+            truncation: TruncationPolicy::default(),
         }
     }
 
@@ -118,9 +195,27 @@ impl AgentConfig {
             name,
             description: description.into(),
             agent_type: AgentType::Assistant { instructions },
+            provider: AgentProvider::default(),
+            fallbacks: vec![],
+            truncation: TruncationPolicy::default(),
         }
     }
 
+    pub fn with_provider(mut self, provider: AgentProvider) -> Self {
+        self.provider = provider;
+        self
+    }
+
+    pub fn with_fallbacks(mut self, fallbacks: Vec<AgentProvider>) -> Self {
+        self.fallbacks = fallbacks;
+        self
+    }
+
+    pub fn with_truncation(mut self, truncation: TruncationPolicy) -> Self {
+        self.truncation = truncation;
+        self
+    }
+
     pub fn simple_sys_prompt(&self) -> String {
         match &self.agent_type {
             AgentType::User => EMPTY,
@@ -0,0 +1,149 @@
+//! A sync server for chats and agents of chitchai across devices, which keeps the latest version of each in files.
+//!
+//! Run `cargo run --features sync-server --bin chitchai-sync -- --bind 127.0.0.1:8787 --data-dir chitchai_sync_data`
+//! and enter `http://127.0.0.1:8787` in the `Sync Server` section of the settings panel.
+
+use std::io::Read;
+use std::path::PathBuf;
+
+use tiny_http::{Header, Method, Request, Response, Server};
+
+use chitchai::utils::sync::protocol::{HEALTH_PATH, PROTOCOL_VERSION, SYNC_PATH, SyncRequest};
+
+use crate::store::SyncStore;
+
+mod store;
+
+const DEFAULT_BIND: &str = "127.0.0.1:8787";
+const DEFAULT_DATA_DIR: &str = "chitchai_sync_data";
+/// requests carry whole chats, which may hold images
+const MAX_BODY_BYTES: u64 = 64 * 1024 * 1024;
+
+struct Options {
+    bind: String,
+    data_dir: PathBuf,
+    /// token that requests must carry, if any
+    token: Option<String>,
+}
+
+impl Options {
+    /// Options from the arguments, or else from `$CHITCHAI_SYNC_BIND`, `$CHITCHAI_SYNC_DIR` and `$CHITCHAI_SYNC_TOKEN`
+    fn parse() -> Result<Self, String> {
+        let mut options = Options {
+            bind: std::env::var("CHITCHAI_SYNC_BIND").unwrap_or_else(|_| DEFAULT_BIND.to_string()),
+            data_dir: std::env::var_os("CHITCHAI_SYNC_DIR")
+                .map(PathBuf::from)
+                .unwrap_or_else(|| PathBuf::from(DEFAULT_DATA_DIR)),
+            token: std::env::var("CHITCHAI_SYNC_TOKEN").ok().filter(|token| !token.is_empty()),
+        };
+        let mut args = std::env::args().skip(1);
+        while let Some(arg) = args.next() {
+            let mut value = || args.next().ok_or_else(|| format!("{} takes a value", arg));
+            match arg.as_str() {
+                "--bind" => options.bind = value()?,
+                "--data-dir" => options.data_dir = PathBuf::from(value()?),
+                "--token" => options.token = Some(value()?),
+                _ => return Err(format!("Unknown argument {}\nUsage: chitchai-sync [--bind ADDRESS] [--data-dir DIR] [--token TOKEN]", arg)),
+            }
+        }
+        Ok(options)
+    }
+}
+
+fn main() {
+    let options = match Options::parse() {
+        Ok(options) => options,
+        Err(e) => {
+            eprintln!("{}", e);
+            std::process::exit(2);
+        }
+    };
+    let mut store = match SyncStore::open(options.data_dir.clone()) {
+        Ok(store) => store,
+        Err(e) => {
+            eprintln!("Error when loading {}: {}", options.data_dir.display(), e);
+            std::process::exit(1);
+        }
+    };
+    let server = match Server::http(&options.bind) {
+        Ok(server) => server,
+        Err(e) => {
+            eprintln!("Error when listening on {}: {}", options.bind, e);
+            std::process::exit(1);
+        }
+    };
+    println!("Syncing {} items in {} on http://{}", store.item_count(), options.data_dir.display(), options.bind);
+    if options.token.is_none() {
+        println!("No token is required, so anyone who can reach the server can read and change what it keeps");
+    }
+    // requests are served one by one, which keeps the store consistent without locks
+    for request in server.incoming_requests() {
+        handle(request, &mut store, options.token.as_deref());
+    }
+}
+
+fn handle(mut request: Request, store: &mut SyncStore, token: Option<&str>) {
+    let (status, body) = match (request.method(), request.url()) {
+        // preflight of browsers, since chitchai is served from another origin
+        (Method::Options, _) => (204, String::new()),
+        (Method::Get, HEALTH_PATH) => (200, format!("{{\"protocol\":{}}}", PROTOCOL_VERSION)),
+        (Method::Post, SYNC_PATH) if !is_authorized(&request, token) => (401, "Wrong or missing token".to_string()),
+        (Method::Post, SYNC_PATH) => match sync(&mut request, store) {
+            Ok(body) => (200, body),
+            Err((status, e)) => {
+                eprintln!("Error when syncing: {}", e);
+                (status, e)
+            }
+        },
+        _ => (404, "Not found".to_string()),
+    };
+    let content_type = if status == 200 { "application/json" } else { "text/plain" };
+    let response = Response::from_string(body)
+        .with_status_code(status)
+        .with_header(header("Content-Type", content_type))
+        .with_header(header("Access-Control-Allow-Origin", "*"))
+        .with_header(header("Access-Control-Allow-Methods", "GET, POST, OPTIONS"))
+        .with_header(header("Access-Control-Allow-Headers", "Authorization, Content-Type"));
+    if let Err(e) = request.respond(response) {
+        eprintln!("Error when responding: {}", e);
+    }
+}
+
+fn sync(request: &mut Request, store: &mut SyncStore) -> Result<String, (u16, String)> {
+    let mut body = String::new();
+    request
+        .as_reader()
+        .take(MAX_BODY_BYTES)
+        .read_to_string(&mut body)
+        .map_err(|e| (400, e.to_string()))?;
+    let sync_request: SyncRequest = serde_json::from_str(&body).map_err(|e| (400, format!("Invalid sync request: {}", e)))?;
+    let pushed = sync_request.items.len();
+    let response = store.sync(sync_request).map_err(|e| (500, e))?;
+    println!("Took {} items and sent {} up to revision {}", pushed, response.items.len(), response.revision);
+    serde_json::to_string(&response).map_err(|e| (500, e.to_string()))
+}
+
+fn is_authorized(request: &Request, token: Option<&str>) -> bool {
+    let Some(token) = token else {
+        return true;
+    };
+    request
+        .headers()
+        .iter()
+        .find(|header| header.field.equiv("Authorization"))
+        .and_then(|header| header.value.as_str().strip_prefix("Bearer "))
+        .is_some_and(|given| constant_time_eq(given.as_bytes(), token.as_bytes()))
+}
+
+/// Compare secrets in a time that does not tell how much of them matched
+fn constant_time_eq(given: &[u8], expected: &[u8]) -> bool {
+    given.len() == expected.len()
+        && given
+            .iter()
+            .zip(expected)
+            .fold(0_u8, |diff, (a, b)| diff | (a ^ b)) == 0
+}
+
+fn header(field: &str, value: &str) -> Header {
+    Header::from_bytes(field.as_bytes(), value.as_bytes()).expect("headers are ASCII")
+}
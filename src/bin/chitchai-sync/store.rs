@@ -0,0 +1,131 @@
+use std::collections::{HashMap, HashSet};
+use std::path::PathBuf;
+
+use futures::executor::block_on;
+
+use chitchai::utils::storage::backend::{FileSystem, StorageBackend};
+use chitchai::utils::sync::protocol::{ItemKind, SyncItem, SyncRequest, SyncResponse};
+
+/// The latest version of every item, each kept as a JSON file under `<root>/<kind>/`, deleted chats included
+pub struct SyncStore {
+    files: FileSystem,
+    items: HashMap<(ItemKind, String), SyncItem>,
+    /// the revision of the item taken last
+    revision: u64,
+}
+
+impl SyncStore {
+    pub fn open(root: PathBuf) -> Result<Self, String> {
+        let files = FileSystem::new(root);
+        let mut items = HashMap::new();
+        for kind in [ItemKind::Chat, ItemKind::AgentConfig] {
+            for (file, raw) in block_on(files.get_all(kind.as_str()))? {
+                let item: SyncItem = serde_json::from_str(&raw).map_err(|e| format!("Invalid item {}: {}", file, e))?;
+                items.insert((item.kind, item.key.clone()), item);
+            }
+        }
+        let revision = items.values().map(|item| item.revision).max().unwrap_or_default();
+        Ok(Self {
+            files,
+            items,
+            revision,
+        })
+    }
+
+    pub fn item_count(&self) -> usize {
+        self.items.len()
+    }
+
+    /// Take the pushed items that are later than what the store has, and answer what the device has yet to pull
+    pub fn sync(&mut self, request: SyncRequest) -> Result<SyncResponse, String> {
+        let mut taken = vec![];
+        let mut lost = HashSet::new();
+        let mut revision = self.revision;
+        for mut item in request.items {
+            let id = (item.kind, item.key.clone());
+            match self.items.get(&id) {
+                Some(current) if !item.wins_over(current) => {
+                    lost.insert(id);
+                }
+                _ => {
+                    revision += 1;
+                    item.revision = revision;
+                    taken.push(item);
+                }
+            }
+        }
+        let records = taken
+            .iter()
+            .map(|item| Ok((item.kind.as_str(), file_key(&item.key), serde_json::to_string(item).map_err(|e| e.to_string())?)))
+            .collect::<Result<Vec<_>, String>>()?;
+        // nothing is taken unless it is written
+        block_on(self.files.write(records, vec![]))?;
+        let mut pushed = HashSet::new();
+        for item in taken {
+            let id = (item.kind, item.key.clone());
+            pushed.insert(id.clone());
+            self.items.insert(id, item);
+        }
+        self.revision = revision;
+        let mut items: Vec<SyncItem> = self.items
+            .iter()
+            .filter(|(id, item)| (item.revision > request.since && !pushed.contains(*id)) || lost.contains(*id))
+            .map(|(_, item)| item.clone())
+            .collect();
+        items.sort_by_key(|item| item.revision);
+        Ok(SyncResponse {
+            revision: self.revision,
+            items,
+        })
+    }
+}
+
+/// Name of the file of an item, whose key may be an agent name that is not a valid file name
+fn file_key(key: &str) -> String {
+    key.bytes().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use uuid::Uuid;
+
+    use super::*;
+
+    fn chat(key: &str, updated_at: i64, device: Uuid) -> SyncItem {
+        SyncItem {
+            kind: ItemKind::Chat,
+            key: key.to_string(),
+            value: Some(serde_json::json!({ "updated_at": updated_at })),
+            updated_at,
+            device,
+            revision: 0,
+        }
+    }
+
+    #[test]
+    fn the_last_change_wins() {
+        let root = std::env::temp_dir().join(format!("chitchai_test_{}", Uuid::new_v4()));
+        let (phone, laptop) = (Uuid::new_v4(), Uuid::new_v4());
+        let mut store = SyncStore::open(root.clone()).unwrap();
+        let response = store.sync(SyncRequest { since: 0, items: vec![chat("a", 10, phone)] }).unwrap();
+        assert_eq!(response.revision, 1);
+        assert!(response.items.is_empty());
+        // an earlier change loses and comes back with the version that won
+        let response = store.sync(SyncRequest { since: 0, items: vec![chat("a", 5, laptop)] }).unwrap();
+        assert_eq!(response.revision, 1);
+        assert_eq!(response.items.len(), 1);
+        assert_eq!((response.items[0].updated_at, response.items[0].device), (10, phone));
+        // a later change is taken, and the other device pulls it
+        let response = store.sync(SyncRequest { since: 1, items: vec![chat("a", 20, laptop)] }).unwrap();
+        assert_eq!(response.revision, 2);
+        assert!(response.items.is_empty());
+        let response = store.sync(SyncRequest { since: 1, items: vec![] }).unwrap();
+        assert_eq!(response.items.len(), 1);
+        assert_eq!((response.items[0].updated_at, response.items[0].device, response.items[0].revision), (20, laptop, 2));
+        // what was taken is kept across restarts
+        let store = SyncStore::open(root.clone()).unwrap();
+        assert_eq!(store.item_count(), 1);
+        assert_eq!(store.revision, 2);
+        std::fs::remove_dir_all(root).unwrap();
+    }
+}
@@ -1,11 +1,17 @@
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
+use std::collections::hash_map::Entry;
+use std::sync::atomic::{AtomicU64, Ordering};
 
-use transprompt::utils::llm::openai::ChatMsg;
+use chrono::Utc;
+use serde::{Deserialize, Serialize};
+use transprompt::async_openai::types::{ChatCompletionMessageToolCall, ChatCompletionRequestAssistantMessage, ChatCompletionRequestFunctionMessage, ChatCompletionRequestMessage, ChatCompletionRequestMessageContentPart, ChatCompletionRequestMessageContentPartImage, ChatCompletionRequestMessageContentPartText, ChatCompletionRequestSystemMessage, ChatCompletionRequestToolMessage, ChatCompletionRequestUserMessage, ChatCompletionRequestUserMessageContent, ChatCompletionToolType, FunctionCall, ImageUrl};
 use uuid::Uuid;
 
-use crate::agents::{AgentConfig, AgentID, AgentInstance, AgentName, AgentType};
+use crate::agents::{AgentConfig, AgentID, AgentInstance, AgentName, AgentProvider, AgentType, TruncationPolicy};
 use crate::utils::{Instructions, sys_msg};
 use crate::utils::datetime::DatetimeString;
+use crate::utils::errors::ProviderError;
+use crate::utils::usage::Usage;
 
 pub type LinkedChatHistory = Vec<MessageID>;
 
@@ -18,43 +24,429 @@ impl MessageID {
     }
 }
 
+/// What chitchai knows about a message besides its content
+#[derive(Clone, Debug, PartialEq, Default, Serialize, Deserialize)]
+pub struct MessageMeta {
+    /// `None` for messages stored before metadata was kept
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub created_at: Option<DatetimeString>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub author: Option<AgentID>,
+    /// profile that produced the message
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub profile: Option<String>,
+    /// model that produced the message
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub model: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub time_to_first_token_ms: Option<i64>,
+    /// time from sending the request to the end of the reply
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub latency_ms: Option<i64>,
+    /// tokens and cost of the completions that produced the message
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub usage: Option<Usage>,
+    /// whether the message is a reply that has not finished, which was interrupted if nothing streams it
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub incomplete: bool,
+}
+
+impl MessageMeta {
+    pub fn new(author: Option<AgentID>) -> Self {
+        Self {
+            created_at: Some(DatetimeString::get_now()),
+            author,
+            ..Default::default()
+        }
+    }
+
+    /// Which provider and model produced the message
+    pub fn producer(&self) -> Option<String> {
+        match (&self.profile, &self.model) {
+            (Some(profile), Some(model)) => Some(format!("{} · {}", profile, model)),
+            (None, Some(model)) => Some(model.clone()),
+            (Some(profile), None) => Some(profile.clone()),
+            (None, None) => None,
+        }
+    }
+}
+
+/// Who a message comes from
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Role {
+    System,
+    User,
+    Assistant,
+    Tool,
+    Function,
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum ContentPart {
+    Text {
+        text: String,
+    },
+    Image {
+        url: String,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        detail: Option<String>,
+    },
+}
+
+/// A call of a tool or function that an assistant asks for
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct ToolCall {
+    /// `None` for the single function call of the older function calling API
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub id: Option<String>,
+    pub name: String,
+    pub arguments: String,
+}
+
+/// The content of a message.
+///
+/// Messages are kept in a schema of chitchai, from which the request types of providers are built when sending them.
+#[derive(Clone, Debug, PartialEq)]
+pub struct ChatMessage {
+    pub role: Role,
+    /// name of the author as sent to providers, `None` for the default user and assistant
+    pub name: Option<String>,
+    pub content: Vec<ContentPart>,
+    pub tool_calls: Vec<ToolCall>,
+    /// the call that a tool message answers
+    pub tool_call_id: Option<String>,
+}
+
+impl ChatMessage {
+    pub fn text_of(role: Role, text: String, name: Option<String>) -> Self {
+        Self {
+            role,
+            name,
+            content: vec![ContentPart::Text { text }],
+            tool_calls: vec![],
+            tool_call_id: None,
+        }
+    }
+
+    /// The content if it is nothing but text
+    pub fn plain_text(&self) -> Option<&str> {
+        match self.content.as_slice() {
+            [] => Some(""),
+            [ContentPart::Text { text }] => Some(text),
+            _ => None,
+        }
+    }
+
+    /// The content as text, into which images are put as links
+    pub fn text(&self) -> String {
+        self.content
+            .iter()
+            .map(|part| match part {
+                ContentPart::Text { text } => text.clone(),
+                ContentPart::Image { url, .. } => format!("![]({})", url),
+            })
+            .collect::<Vec<_>>()
+            .join("\n")
+    }
+
+    pub fn set_text(&mut self, text: String) {
+        self.content = vec![ContentPart::Text { text }];
+    }
+
+    /// The request type of the message to send to providers
+    pub fn to_request(&self) -> ChatCompletionRequestMessage {
+        let name = self.name.clone();
+        match self.role {
+            Role::System => ChatCompletionRequestMessage::System(ChatCompletionRequestSystemMessage {
+                content: self.text(),
+                role: Default::default(),
+                name,
+            }),
+            Role::User => {
+                // users are the only ones who can send images as they are
+                let content = match self.plain_text() {
+                    Some(text) => ChatCompletionRequestUserMessageContent::Text(text.to_string()),
+                    None => ChatCompletionRequestUserMessageContent::Array(self.content
+                        .iter()
+                        .map(|part| match part {
+                            ContentPart::Text { text } =>
+                                ChatCompletionRequestMessageContentPart::Text(ChatCompletionRequestMessageContentPartText {
+                                    r#type: "text".to_string(),
+                                    text: text.clone(),
+                                }),
+                            ContentPart::Image { url, detail } =>
+                                ChatCompletionRequestMessageContentPart::Image(ChatCompletionRequestMessageContentPartImage {
+                                    r#type: "image_url".to_string(),
+                                    image_url: ImageUrl {
+                                        url: url.clone(),
+                                        detail: detail
+                                            .clone()
+                                            .and_then(|detail| serde_json::from_value(serde_json::Value::String(detail)).ok())
+                                            .unwrap_or_default(),
+                                    },
+                                }),
+                        })
+                        .collect()),
+                };
+                ChatCompletionRequestMessage::User(ChatCompletionRequestUserMessage {
+                    content,
+                    role: Default::default(),
+                    name,
+                })
+            }
+            Role::Assistant => {
+                let calls: Vec<_> = self.tool_calls
+                    .iter()
+                    .filter_map(|call| Some(ChatCompletionMessageToolCall {
+                        id: call.id.clone()?,
+                        r#type: ChatCompletionToolType::Function,
+                        function: FunctionCall {
+                            name: call.name.clone(),
+                            arguments: call.arguments.clone(),
+                        },
+                    }))
+                    .collect();
+                ChatCompletionRequestMessage::Assistant(ChatCompletionRequestAssistantMessage {
+                    content: (!self.content.is_empty()).then(|| self.text()),
+                    role: Default::default(),
+                    name,
+                    tool_calls: (!calls.is_empty()).then_some(calls),
+                    function_call: self.tool_calls
+                        .iter()
+                        .find(|call| call.id.is_none())
+                        .map(|call| FunctionCall {
+                            name: call.name.clone(),
+                            arguments: call.arguments.clone(),
+                        }),
+                })
+            }
+            Role::Tool => ChatCompletionRequestMessage::Tool(ChatCompletionRequestToolMessage {
+                role: Default::default(),
+                content: self.text(),
+                tool_call_id: self.tool_call_id.clone().unwrap_or_default(),
+            }),
+            Role::Function => ChatCompletionRequestMessage::Function(ChatCompletionRequestFunctionMessage {
+                role: Default::default(),
+                content: (!self.content.is_empty()).then(|| self.text()),
+                name: name.unwrap_or_default(),
+            }),
+        }
+    }
+}
+
+/// A message of a chat along with its metadata
+#[derive(Clone, Debug, PartialEq)]
+pub struct Message {
+    pub chat_msg: ChatMessage,
+    pub meta: MessageMeta,
+}
+
+/// An error that stopped an agent from replying
+#[derive(Clone, Debug, PartialEq)]
+pub struct ReplyError {
+    pub agent_id: AgentID,
+    pub error: ProviderError,
+}
+
+/// A summary that stands for the oldest turns in the history of an agent, which stay in the history as they are
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct HistorySummary {
+    /// the system message holding the summary
+    pub message_id: MessageID,
+    /// number of turns after the system prompt that the summary stands for
+    pub covered: usize,
+}
+
 #[derive(Clone, Debug, PartialEq, Default)]
 pub struct MessageManager {
-    pub(crate) messages: HashMap<MessageID, ChatMsg>,
+    pub(crate) messages: HashMap<MessageID, Message>,
+    /// errors of replies in this session, which are not persisted
+    pub(crate) errors: HashMap<MessageID, ReplyError>,
+    /// replies queued behind the rate limits of their providers, which are not persisted
+    pub(crate) waiting: HashSet<MessageID>,
+    /// rolling summaries of the histories of agents
+    pub(crate) summaries: HashMap<AgentID, HistorySummary>,
 }
 
 impl MessageManager {
-    pub fn insert(&mut self, msg: ChatMsg) -> MessageID {
+    pub fn insert(&mut self, msg: ChatMessage) -> MessageID {
+        self.insert_authored(msg, None)
+    }
+
+    pub fn insert_authored(&mut self, msg: ChatMessage, author: Option<AgentID>) -> MessageID {
         let id = MessageID::new();
-        self.messages.insert(id.clone(), msg);
+        self.messages.insert(id, Message {
+            chat_msg: msg,
+            meta: MessageMeta::new(author),
+        });
         id
     }
 
-    pub fn remove(&mut self, id: &MessageID) -> Option<ChatMsg> {
+    pub fn remove(&mut self, id: &MessageID) -> Option<Message> {
         self.messages.remove(id)
     }
 
-    pub fn get(&self, id: &MessageID) -> Option<&ChatMsg> {
+    pub fn get(&self, id: &MessageID) -> Option<&ChatMessage> {
+        self.messages.get(id).map(|m| &m.chat_msg)
+    }
+
+    pub fn get_mut(&mut self, id: &MessageID) -> Option<&mut ChatMessage> {
+        self.messages.get_mut(id).map(|m| &mut m.chat_msg)
+    }
+
+    /// The content of a reply
+    pub fn reply_content(&self, id: &MessageID) -> Option<&str> {
+        self.get(id)
+            .filter(|m| m.role == Role::Assistant)
+            .and_then(ChatMessage::plain_text)
+    }
+
+    /// Replace the content of a reply
+    pub fn set_reply_content(&mut self, id: &MessageID, content: String) {
+        if let Some(reply) = self.get_mut(id).filter(|m| m.role == Role::Assistant) {
+            reply.set_text(content);
+        }
+    }
+
+    pub fn is_incomplete(&self, id: &MessageID) -> bool {
+        self.messages.get(id).is_some_and(|m| m.meta.incomplete)
+    }
+
+    pub fn set_incomplete(&mut self, id: &MessageID, incomplete: bool) {
+        if let Some(meta) = self.meta_mut(id) {
+            meta.incomplete = incomplete;
+        }
+    }
+
+    pub fn message(&self, id: &MessageID) -> Option<&Message> {
         self.messages.get(id)
     }
 
-    pub fn get_mut(&mut self, id: &MessageID) -> Option<&mut ChatMsg> {
-        self.messages.get_mut(id)
+    pub fn meta_mut(&mut self, id: &MessageID) -> Option<&mut MessageMeta> {
+        self.messages.get_mut(id).map(|m| &mut m.meta)
+    }
+
+    /// Replace the content of a message, keeping its metadata
+    pub fn update(&mut self, id: &MessageID, msg: ChatMessage) -> Option<ChatMessage> {
+        match self.messages.get_mut(id) {
+            Some(message) => Some(std::mem::replace(&mut message.chat_msg, msg)),
+            None => {
+                self.messages.insert(*id, Message {
+                    chat_msg: msg,
+                    meta: MessageMeta::new(None),
+                });
+                None
+            }
+        }
+    }
+
+    pub fn error(&self, id: &MessageID) -> Option<&ReplyError> {
+        self.errors.get(id)
+    }
+
+    pub fn set_error(&mut self, id: &MessageID, error: ReplyError) {
+        self.errors.insert(id.clone(), error);
+    }
+
+    pub fn clear_error(&mut self, id: &MessageID) -> Option<ReplyError> {
+        self.errors.remove(id)
+    }
+
+    pub fn summary(&self, agent_id: &AgentID) -> Option<&HistorySummary> {
+        self.summaries.get(agent_id)
+    }
+
+    /// Replace the summary of an agent with one that stands for the first `covered` turns after the system prompt
+    pub fn set_summary(&mut self, agent_id: &AgentID, summary: String, covered: usize) {
+        let msg = sys_msg(format!("Summary of the earlier conversation:\n{}", summary));
+        let message_id = match self.summaries.get(agent_id).copied() {
+            Some(old) => {
+                self.update(&old.message_id, msg);
+                old.message_id
+            }
+            None => self.insert(msg),
+        };
+        self.summaries.insert(*agent_id, HistorySummary { message_id, covered });
+    }
+
+    /// Discard the summary of an agent, so that it sees the turns themselves again
+    pub fn discard_summary(&mut self, agent_id: &AgentID) -> Option<HistorySummary> {
+        let summary = self.summaries.remove(agent_id)?;
+        self.remove(&summary.message_id);
+        Some(summary)
+    }
+
+    /// Number of system messages that lead a history
+    pub fn system_prompt_len(&self, history: &[MessageID]) -> usize {
+        history
+            .iter()
+            .take_while(|id| self.get(id).is_some_and(|m| m.role == Role::System))
+            .count()
+    }
+
+    /// What an agent sees of its history, where the turns its summary stands for are replaced by the summary
+    pub fn context_of(&self, agent_id: &AgentID, history: &[MessageID]) -> Vec<MessageID> {
+        let system_len = self.system_prompt_len(history);
+        match self.summaries.get(agent_id) {
+            Some(summary) if system_len + summary.covered <= history.len() => {
+                history[..system_len]
+                    .iter()
+                    .chain(std::iter::once(&summary.message_id))
+                    .chain(history[system_len + summary.covered..].iter())
+                    .copied()
+                    .collect()
+            }
+            _ => history.to_vec(),
+        }
+    }
+
+    pub fn usage(&self, id: &MessageID) -> Option<&Usage> {
+        self.messages.get(id).and_then(|m| m.meta.usage.as_ref())
     }
 
-    pub fn update(&mut self, id: &MessageID, msg: ChatMsg) -> Option<ChatMsg> {
-        self.messages.insert(id.clone(), msg)
+    /// Add the usage of a completion to a message, which adds up when a message is produced more than once
+    pub fn add_usage(&mut self, id: &MessageID, usage: Usage) {
+        if let Some(meta) = self.meta_mut(id) {
+            *meta.usage.get_or_insert_with(Usage::default) += usage;
+        }
+    }
+
+    pub fn is_waiting(&self, id: &MessageID) -> bool {
+        self.waiting.contains(id)
+    }
+
+    pub fn set_waiting(&mut self, id: &MessageID, waiting: bool) {
+        if waiting {
+            self.waiting.insert(id.clone());
+        } else {
+            self.waiting.remove(id);
+        }
     }
 }
 
 
-#[derive(Debug, PartialEq)]
+/// Source of chat revisions, unique in a session so that a chat put in place of another is never taken as saved
+static NEXT_REVISION: AtomicU64 = AtomicU64::new(1);
+
+fn next_revision() -> u64 {
+    NEXT_REVISION.fetch_add(1, Ordering::Relaxed)
+}
+
+#[derive(Clone, Debug, PartialEq)]
 pub struct Chat {
     pub(crate) id: Uuid,
     pub message_manager: MessageManager,
     pub topic: String,
     pub date: DatetimeString,
     pub agents: HashMap<AgentID, AgentInstance>,
+    /// profile that agents without one of their own talk to in this chat, `None` for the profile in use
+    pub profile: Option<String>,
+    /// changes with every change to the chat in this session, not stored
+    pub(crate) revision: u64,
+    /// when the chat last changed in milliseconds since the epoch, which decides between its copies on the sync server
+    pub(crate) updated_at: i64,
 }
 
 impl Chat {
@@ -62,6 +454,16 @@ impl Chat {
         self.id
     }
 
+    pub(crate) fn new_revision() -> u64 {
+        next_revision()
+    }
+
+    /// Mark the chat as changed so that it is saved again
+    pub fn touch(&mut self) {
+        self.revision = next_revision();
+        self.updated_at = Utc::now().timestamp_millis();
+    }
+
     pub fn default_chat_and_configs() -> (Self, HashMap<AgentName, AgentConfig>) {
         let Instructions { agent_config: configs } = toml::from_str(include_str!("../default_assistants.toml")).unwrap();
         let mut name_to_configs = HashMap::new();
@@ -73,7 +475,16 @@ impl Chat {
                 agent_name.clone(),
                 agent_instructions.instructions.clone(),
                 "",
-            );
+            ).with_provider(AgentProvider {
+                profile: agent_instructions.profile.clone(),
+                model: agent_instructions.model.clone(),
+            }).with_fallbacks(agent_instructions.fallbacks
+                .iter()
+                .map(|fallback| AgentProvider::parse(fallback))
+                .collect()
+            ).with_truncation(agent_instructions.keep_recent_turns
+                .map(TruncationPolicy::KeepRecent)
+                .unwrap_or_default());
             let sys_prompt = agent_config.simple_sys_prompt();
             let sys_prompt_id = message_manager.insert(sys_msg(sys_prompt));
             let agent = AgentInstance::new(agent_config, vec![sys_prompt_id]);
@@ -90,6 +501,9 @@ impl Chat {
             topic: "New Chat".to_string(),
             date: DatetimeString::get_now(),
             agents,
+            profile: None,
+            revision: next_revision(),
+            updated_at: Utc::now().timestamp_millis(),
         };
         (chat, name_to_configs)
     }
@@ -113,6 +527,35 @@ impl Chat {
             .collect()
     }
 
+    pub fn total_usage(&self) -> Usage {
+        self.message_manager
+            .messages
+            .values()
+            .filter_map(|m| m.meta.usage.as_ref())
+            .sum()
+    }
+
+    /// Usage of the replies and the summary of an agent
+    pub fn agent_usage(&self, agent_id: &AgentID) -> Usage {
+        let Some(agent) = self.agents.get(agent_id) else {
+            return Usage::default();
+        };
+        let agent_name = agent.get_name();
+        let message_manager = &self.message_manager;
+        let summary_id = message_manager.summary(agent_id).map(|s| s.message_id);
+        agent.history
+            .iter()
+            .filter(|id| match message_manager.message(id) {
+                Some(Message { meta: MessageMeta { author: Some(author), .. }, .. }) => author == agent_id,
+                // messages stored before authors were kept are told by the name
+                Some(Message { chat_msg, .. }) => chat_msg.role == Role::Assistant && AgentName::assistant(chat_msg.name.clone()) == agent_name,
+                None => false,
+            })
+            .chain(summary_id.iter())
+            .filter_map(|id| message_manager.usage(id))
+            .sum()
+    }
+
     pub fn assistant_agent_ids<B: FromIterator<AgentID>>(&self) -> B {
         self
             .agents
@@ -130,16 +573,34 @@ impl Chat {
     pub fn agent_ids(&self) -> Vec<AgentID> {
         self.agents.keys().cloned().collect()
     }
-}
 
-impl Clone for Chat {
-    fn clone(&self) -> Self {
-        Self {
-            id: Uuid::new_v4(),
-            message_manager: self.message_manager.clone(),
-            topic: self.topic.clone(),
-            date: DatetimeString::get_now(),
-            agents: self.agents.clone(),
+    /// Take the messages that another copy of the chat has and this one lacks, like those added in another tab,
+    /// keeping this one where both have a message.
+    ///
+    /// Returns how many messages were taken.
+    pub fn merge_messages(&mut self, other: Chat) -> usize {
+        let mut taken = 0;
+        for (id, message) in other.message_manager.messages {
+            if let Entry::Vacant(entry) = self.message_manager.messages.entry(id) {
+                entry.insert(message);
+                taken += 1;
+            }
+        }
+        for (agent_id, agent) in other.agents {
+            match self.agents.get_mut(&agent_id) {
+                Some(in_use) => {
+                    let missing: LinkedChatHistory = agent.history
+                        .into_iter()
+                        .filter(|id| !in_use.history.contains(id))
+                        .collect();
+                    in_use.history.extend(missing);
+                }
+                None => {
+                    self.agents.insert(agent_id, agent);
+                }
+            }
         }
+        self.touch();
+        taken
     }
 }
@@ -1,21 +1,73 @@
+use std::collections::HashMap;
 use std::rc::Rc;
 use std::time::Duration;
 
 use async_std::task::sleep;
 use dioxus::prelude::*;
+use uuid::Uuid;
 
 pub use message_card::*;
 
-use crate::agents::AgentID;
+use crate::agents::{AgentConfig, AgentID};
 use crate::pages::app::{AuthedClient, ChatId, StreamingReply};
-use crate::chat::Chat;
-use crate::components::chat::request_utils::{find_chat_idx_by_id, handle_request};
+use crate::chat::{Chat, MessageID, Role};
+use crate::components::chat::request_utils::{context_usage, find_chat_idx_by_id, handle_request, ReplyPipeline};
+use crate::components::chat::transcript_export::TranscriptExport;
+use crate::utils::errors::ProviderError;
+use crate::utils::scheduler::Scheduler;
 use crate::utils::storage::StoredStates;
+use crate::utils::streaming::StreamingReplies;
+use crate::utils::tabs::ChatLocks;
+use crate::utils::tokens::ContextUsage;
+use crate::utils::usage::Usage;
 
 mod request_utils;
+mod memory;
 pub mod message_card;
+mod transcript_export;
 
-struct Request(String);
+enum Request {
+    Query(String),
+    /// produce a failed or interrupted reply again
+    Retry(MessageID),
+    /// continue an interrupted reply from where it was cut off
+    Continue(MessageID),
+    /// let an agent see the turns its summary stands for again
+    DiscardSummary(AgentID),
+}
+
+/// A request that was not sent, like when another tab was replying in its chat or there was no profile to send it to
+#[derive(Debug, Clone, PartialEq)]
+pub struct Refusal {
+    chat_id: Uuid,
+    reason: String,
+    /// the query of the request, which is put back into the input so that it is not lost
+    query: Option<String>,
+    /// tells refusals of the same query apart
+    refused_at: i64,
+}
+
+/// How full the context of an agent is and what its summary stands for
+#[derive(Debug, Clone, PartialEq)]
+pub struct ContextMeter {
+    agent_id: AgentID,
+    name: String,
+    usage: ContextUsage,
+    /// number of turns summarized and the summary
+    summary: Option<(usize, String)>,
+    spent: Usage,
+}
+
+/// What the context usage of an agent depends on, so that its tokens are only counted again when it changes
+#[derive(PartialEq)]
+struct UsageKey {
+    chat_id: Uuid,
+    history_len: usize,
+    covered: Option<usize>,
+    config: AgentConfig,
+    profile: Option<String>,
+    busy: bool,
+}
 
 
 pub fn ChatContainer(cx: Scope) -> Element {
@@ -23,13 +75,24 @@ pub fn ChatContainer(cx: Scope) -> Element {
     let authed_client = use_shared_state::<AuthedClient>(cx).unwrap();
     let streaming_reply = use_shared_state::<StreamingReply>(cx).unwrap();
     let chat_id = use_shared_state::<ChatId>(cx).unwrap();
+    let scheduler = use_shared_state::<Scheduler>(cx).unwrap();
+    let streams = use_shared_state::<StreamingReplies>(cx).unwrap();
+    let chat_locks = use_shared_state::<ChatLocks>(cx).unwrap();
+    let usage_cache = use_ref(cx, HashMap::<AgentID, (UsageKey, ContextUsage)>::new);
+    let refusal = use_ref(cx, || None::<Refusal>);
     // request handler
     use_coroutine(cx, |rx|
         handle_request(rx,
                        chat_id.to_owned(),
                        stored_states.to_owned(),
                        authed_client.to_owned(),
-                       streaming_reply.to_owned()),
+                       streaming_reply.to_owned(),
+                       chat_locks.to_owned(),
+                       refusal.to_owned(),
+                       ReplyPipeline {
+                           scheduler: scheduler.read().clone(),
+                           streams: streams.read().clone(),
+                       }),
     );
     // get data
     let stored_states = stored_states.read();
@@ -39,26 +102,147 @@ pub fn ChatContainer(cx: Scope) -> Element {
     assert_eq!(user_agent_id.len(), 1, "user_agents.len() == 1");  // TODO: support multiple user agents
     let user_agent = chat.agents.get(&user_agent_id[0]).unwrap();
     let history = &user_agent.history;
+    let replying_here = streaming_reply.read().0;
+    let replying_elsewhere = chat_locks.read().is_held_elsewhere(&chat.id);
+    let busy = replying_here || replying_elsewhere;
+    let refused = refusal
+        .read()
+        .clone()
+        .filter(|refusal| refusal.chat_id == chat.id);
+    let mut context_meters: Vec<ContextMeter> = chat
+        .assistant_agent_ids::<Vec<_>>()
+        .into_iter()
+        .map(|agent_id| {
+            let agent = chat.agents.get(&agent_id).unwrap();
+            let summary = chat.message_manager
+                .summary(&agent_id)
+                .and_then(|summary| {
+                    let text = chat.message_manager
+                        .get(&summary.message_id)
+                        .filter(|msg| msg.role == Role::System)?
+                        .text();
+                    Some((summary.covered, text))
+                });
+            let key = UsageKey {
+                chat_id: chat.id,
+                history_len: agent.history.len(),
+                covered: summary.as_ref().map(|(covered, _)| *covered),
+                config: agent.config.clone(),
+                profile: stored_states.chat_profile(chat).map(str::to_string),
+                busy,
+            };
+            let cached = usage_cache
+                .read()
+                .get(&agent_id)
+                .filter(|(cached_key, _)| *cached_key == key)
+                .map(|(_, usage)| *usage);
+            let usage = cached.unwrap_or_else(|| {
+                let usage = context_usage(&stored_states, chat, agent);
+                usage_cache.write_silent().insert(agent_id, (key, usage));
+                usage
+            });
+            ContextMeter {
+                agent_id,
+                name: agent.get_name().to_string(),
+                usage,
+                summary,
+                spent: chat.agent_usage(&agent_id),
+            }
+        })
+        .collect();
+    context_meters.sort_by(|m1, m2| m1.name.cmp(&m2.name));
+    let chat_usage = chat.total_usage().summary();
+    let monthly_usage = stored_states.usage_ledger.this_month().summary();
+    let spending_cap_exceeded = stored_states.spending_cap_exceeded(chat_idx);
     render! {
         div {
             class: "flex h-full w-full flex-col relative",
             div {
                 class: "flex flex-col h-full space-y-6 bg-slate-200 text-sm leading-6 text-slate-900 shadow-sm dark:bg-slate-900 dark:text-slate-300 sm:text-base sm:leading-7",
+                p {
+                    class: "px-4 pt-2 text-xs text-slate-500 dark:text-slate-400",
+                    "This chat: {chat_usage} · This month: {monthly_usage}"
+                }
+                TranscriptExport {
+                    chat_idx: chat_idx,
+                }
+                ContextMeters {
+                    context_meters: context_meters,
+                }
                 div {
                     class: "overflow-auto max-h-[90vh] flex-grow dark:scrollbar dark:scrollbar-thumb-slate-700 dark:scrollbar-track-slate-900",
                     history
                         .iter()
                         .map(|msg_id| {
-                            let msg = chat.message_manager.get(msg_id).unwrap();
+                            let message = chat.message_manager.message(msg_id).unwrap();
+                            let error = chat.message_manager.error(msg_id).map(|e| e.error.clone());
+                            let waiting = chat.message_manager.is_waiting(msg_id);
+                            // replies are unfinished while their round runs here or in another tab, and interrupted if they still are after it
+                            let interrupted = chat.message_manager.is_incomplete(msg_id)
+                                && error.is_none()
+                                && !replying_here
+                                && !replying_elsewhere;
                             rsx! {
-                                MessageCard {
-                                    chat_msg: msg.clone()
+                                StreamedMessageCard {
+                                    id: *msg_id,
+                                    chat_msg: message.chat_msg.clone(),
+                                    meta: message.meta.clone(),
+                                }
+                                if waiting {
+                                    rsx! {
+                                        p {
+                                            class: "px-4 text-xs italic text-slate-500",
+                                            "Waiting for the rate limits of its provider..."
+                                        }
+                                    }
+                                }
+                                if interrupted {
+                                    rsx! {
+                                        InterruptedReplyCard {
+                                            reply_id: *msg_id,
+                                            disable_actions: spending_cap_exceeded.is_some(),
+                                        }
+                                    }
+                                }
+                                if let Some(error) = error {
+                                    rsx! {
+                                        ReplyErrorCard {
+                                            reply_id: *msg_id,
+                                            error: error,
+                                            disable_retry: busy || spending_cap_exceeded.is_some(),
+                                        }
+                                    }
                                 }
                             }
                         })
                 }
+                if let Some(reason) = &spending_cap_exceeded {
+                    rsx! {
+                        p {
+                            class: "px-4 text-center text-xs text-red-600",
+                            "{reason}. Change the caps in the settings panel to continue."
+                        }
+                    }
+                }
+                if replying_elsewhere {
+                    rsx! {
+                        p {
+                            class: "px-4 text-center text-xs text-slate-500",
+                            "Another tab is replying in this chat..."
+                        }
+                    }
+                }
+                if let Some(refused) = &refused {
+                    rsx! {
+                        p {
+                            class: "px-4 text-center text-xs text-red-600",
+                            "{refused.reason}."
+                        }
+                    }
+                }
                 ChatMessageInput {
-                    disable_submit: streaming_reply.read().0
+                    disable_submit: busy || spending_cap_exceeded.is_some(),
+                    refused: refused.clone(),
                 }
             }
         }
@@ -66,9 +250,85 @@ pub fn ChatContainer(cx: Scope) -> Element {
 }
 
 
+/// How full the context of each agent is
 #[inline_props]
-pub fn ChatMessageInput(cx: Scope, disable_submit: bool) -> Element {
+pub fn ContextMeters(cx: Scope, context_meters: Vec<ContextMeter>) -> Element {
+    let request_sender: &Coroutine<Request> = use_coroutine_handle(cx).unwrap();
+    render! {
+        div {
+            class: "flex flex-wrap gap-4 px-4 pt-2 text-xs text-slate-500 dark:text-slate-400",
+            context_meters.iter().map(|ContextMeter { agent_id, name, usage, summary, spent }| {
+                let spent = spent.summary();
+                let agent_id = *agent_id;
+                let percentage = usage.percentage();
+                let bar_color = match percentage {
+                    0..=74 => "bg-blue-600",
+                    75..=94 => "bg-amber-500",
+                    _ => "bg-red-600",
+                };
+                rsx! {
+                    div {
+                        class: "w-40",
+                        title: "{usage.used} / {usage.budget} tokens",
+                        div {
+                            class: "flex justify-between",
+                            span { "{name}" }
+                            span { "{percentage}%" }
+                        }
+                        div {
+                            class: "h-1.5 w-full rounded-full bg-slate-300 dark:bg-slate-700",
+                            div {
+                                class: "h-1.5 rounded-full {bar_color}",
+                                style: "width: {percentage}%",
+                            }
+                        }
+                        div {
+                            "{spent}"
+                        }
+                        if usage.dropped > 0 {
+                            rsx! {
+                                span {
+                                    "{usage.dropped} earlier messages left out"
+                                }
+                            }
+                        }
+                        if let Some((covered, text)) = summary {
+                            rsx! {
+                                div {
+                                    class: "flex justify-between",
+                                    span {
+                                        class: "cursor-help underline decoration-dotted",
+                                        title: "{text}",
+                                        "{covered} turns summarized"
+                                    }
+                                    button {
+                                        r#type: "button",
+                                        class: "hover:text-blue-600",
+                                        onclick: move |_| request_sender.send(Request::DiscardSummary(agent_id)),
+                                        "Discard"
+                                    }
+                                }
+                            }
+                        }
+                    }
+                }
+            })
+        }
+    }
+}
+
+
+#[derive(Props, PartialEq)]
+pub struct ChatMessageInputProps {
+    disable_submit: bool,
+    /// the last request that was not sent, if it is of this chat
+    #[props(! optional)]
+    refused: Option<Refusal>,
+}
+
+pub fn ChatMessageInput(cx: Scope<ChatMessageInputProps>) -> Element {
     const TEXTAREA_ID: &str = "chat-input";
+    let disable_submit = &cx.props.disable_submit;
     let customization = &use_shared_state::<StoredStates>(cx).unwrap().read().customization;
     let tick = use_state(cx, || 0_usize);
     // configure timer
@@ -106,6 +366,19 @@ pub fn ChatMessageInput(cx: Scope, disable_submit: bool) -> Element {
             }
         }
     });
+    // put the query of a refused request back, which is still the value of the input
+    use_future(cx, (&cx.props.refused,), |(refused,)| {
+        let create_eval = create_eval.to_owned();
+        async move {
+            let Some(query) = refused.and_then(|refused| refused.query) else {
+                return;
+            };
+            let restore_js = format!("document.getElementById('{}').value = {};", TEXTAREA_ID, serde_json::Value::String(query));
+            if let Err(e) = create_eval(restore_js.as_str()).unwrap().join().await {
+                log::error!("restore_textarea error: {:?}", e);
+            }
+        }
+    });
 
     render! {
         form {
@@ -113,7 +386,7 @@ pub fn ChatMessageInput(cx: Scope, disable_submit: bool) -> Element {
             id: "chat-form",
             onsubmit: move |_| {
                 log::info!("onsubmit {}", &input_value.get().value);
-                request_sender.send(Request(input_value.get().value.clone()));
+                request_sender.send(Request::Query(input_value.get().value.clone()));
                 clear_textarea.restart();
             },
             label {
@@ -150,3 +423,63 @@ pub fn ChatMessageInput(cx: Scope, disable_submit: bool) -> Element {
         }
     }
 }
+
+
+#[inline_props]
+pub fn ReplyErrorCard(cx: Scope, reply_id: MessageID, error: ProviderError, disable_retry: bool) -> Element {
+    let request_sender: &Coroutine<Request> = use_coroutine_handle(cx).unwrap();
+    render! {
+        div {
+            class: "ml-12 mr-5 -mt-3 flex items-start justify-between gap-x-4 rounded-xl border border-red-300 bg-red-50 px-4 py-3 text-sm text-red-800 dark:border-red-800 dark:bg-slate-800 dark:text-red-300 sm:max-w-md md:max-w-2xl",
+            div {
+                p {
+                    class: "font-semibold",
+                    "{error.title()}"
+                }
+                p {
+                    class: "text-xs",
+                    "{error}"
+                }
+            }
+            button {
+                r#type: "button",
+                disabled: *disable_retry,
+                class: "rounded-lg bg-red-600 px-3 py-1 text-xs font-medium text-slate-200 hover:bg-red-700 disabled:opacity-50",
+                onclick: move |_| request_sender.send(Request::Retry(*reply_id)),
+                "Retry"
+            }
+        }
+    }
+}
+
+/// Actions for a reply that was cut off, say by a reload in the middle of it
+#[inline_props]
+pub fn InterruptedReplyCard(cx: Scope, reply_id: MessageID, disable_actions: bool) -> Element {
+    let request_sender: &Coroutine<Request> = use_coroutine_handle(cx).unwrap();
+    render! {
+        div {
+            class: "ml-12 mr-5 -mt-3 flex items-center justify-between gap-x-4 rounded-xl border border-amber-300 bg-amber-50 px-4 py-3 text-sm text-amber-800 dark:border-amber-700 dark:bg-slate-800 dark:text-amber-300 sm:max-w-md md:max-w-2xl",
+            p {
+                class: "font-semibold",
+                "This reply was interrupted"
+            }
+            div {
+                class: "flex gap-x-2",
+                button {
+                    r#type: "button",
+                    disabled: *disable_actions,
+                    class: "rounded-lg bg-amber-600 px-3 py-1 text-xs font-medium text-slate-200 hover:bg-amber-700 disabled:opacity-50",
+                    onclick: move |_| request_sender.send(Request::Continue(*reply_id)),
+                    "Continue"
+                }
+                button {
+                    r#type: "button",
+                    disabled: *disable_actions,
+                    class: "rounded-lg bg-slate-500 px-3 py-1 text-xs font-medium text-slate-200 hover:bg-slate-600 disabled:opacity-50",
+                    onclick: move |_| request_sender.send(Request::Retry(*reply_id)),
+                    "Regenerate"
+                }
+            }
+        }
+    }
+}
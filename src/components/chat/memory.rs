@@ -0,0 +1,148 @@
+use dioxus::prelude::*;
+use transprompt::async_openai::types::{ChatCompletionRequestMessage, CreateChatCompletionRequestArgs};
+use uuid::Uuid;
+
+use crate::agents::{AgentID, AgentName};
+use crate::chat::{Chat, ChatMessage, Role};
+use crate::components::chat::request_utils::{inline_name, map_chat_messages, route_client, with_chat};
+use crate::pages::app::AuthedClient;
+use crate::utils::{sys_msg, user_msg};
+use crate::utils::errors::ProviderError;
+use crate::utils::models::ModelInfo;
+use crate::utils::settings::AgentRoute;
+use crate::utils::storage::StoredStates;
+use crate::utils::tokens::{count_messages, count_text, prompt_budget};
+use crate::utils::usage::Usage;
+
+const SUMMARIZER_PROMPT: &str = "You keep the memory of a group chat. \
+Summarize the conversation you are given in a few short paragraphs, keeping who said what, decisions, facts and open questions. \
+If there is an earlier summary, merge it into yours. Reply with the summary only.";
+
+/// Turns of an agent to fold into its summary
+struct SummaryJob {
+    agent_id: AgentID,
+    previous_summary: Option<ChatCompletionRequestMessage>,
+    turns: Vec<ChatCompletionRequestMessage>,
+    /// turns that the new summary stands for
+    covered: usize,
+}
+
+/// Fold the oldest turns of agents whose contexts are getting full into their summaries
+pub(super) async fn refresh_summaries(chat_id: Uuid,
+                                      authed_client: &UseSharedState<AuthedClient>,
+                                      global: &UseSharedState<StoredStates>) {
+    let (jobs, route, model) = {
+        let global = global.read();
+        if !global.summarizer.enabled {
+            return;
+        }
+        let Some(chat) = global.chats.iter().find(|chat| chat.id == chat_id) else {
+            return;
+        };
+        let jobs: Vec<SummaryJob> = chat
+            .assistant_agent_ids::<Vec<_>>()
+            .into_iter()
+            .filter_map(|agent_id| summary_job(&global, chat, agent_id))
+            .collect();
+        let route = global.summarizer_route();
+        let model = global.model_registry.info(&route.model);
+        (jobs, route, model)
+    };
+    for job in jobs {
+        match summarize(&job, &route, &model, authed_client).await {
+            Ok((summary, usage)) => {
+                log::info!("Summarized {} turns of agent {:?}", job.covered, job.agent_id);
+                with_chat(global, &chat_id, |global, chat_idx| {
+                    let message_manager = &mut global.chat_mut(chat_idx).message_manager;
+                    message_manager.set_summary(&job.agent_id, summary, job.covered);
+                    let summary_id = message_manager.summary(&job.agent_id).unwrap().message_id;
+                    global.record_usage(chat_idx, &summary_id, usage);
+                });
+            }
+            Err(e) => log::error!("Failed to summarize the history of agent {:?}: {}", job.agent_id, e),
+        }
+    }
+}
+
+fn summary_job(global: &StoredStates, chat: &Chat, agent_id: AgentID) -> Option<SummaryJob> {
+    let settings = &global.summarizer;
+    let message_manager = &chat.message_manager;
+    let agent = chat.agents.get(&agent_id)?;
+    let route = global.agent_routes(chat, &agent.config).remove(0);
+    let context = message_manager.context_of(&agent_id, &agent.history);
+    let used = count_messages(&map_chat_messages(&context, message_manager));
+    let budget = prompt_budget(route.context_window, route.parameters.max_tokens);
+    if used * 100 < budget * settings.threshold_percentage {
+        return None;
+    }
+    let turns = &agent.history[message_manager.system_prompt_len(&agent.history)..];
+    let summary = message_manager.summary(&agent_id).copied();
+    let summarized = summary
+        .map(|s| s.covered)
+        .filter(|covered| *covered <= turns.len())
+        .unwrap_or(0);
+    // a turn starts at a message of the user and runs until the next one
+    let covered = match settings.keep_recent_turns {
+        0 => turns.len(),
+        keep => turns
+            .iter()
+            .enumerate()
+            .rev()
+            .filter(|(_, id)| message_manager
+                .get(id)
+                .is_some_and(|msg| msg.role == Role::User))
+            .nth(keep - 1)
+            .map_or(0, |(idx, _)| idx),
+    };
+    if covered <= summarized {
+        return None;
+    }
+    // name every speaker, as the summarizer is none of them
+    let turns = map_chat_messages(&turns[summarized..covered], message_manager)
+        .into_iter()
+        .map(|msg| inline_name(msg, &AgentName::UserDefault))
+        .collect();
+    let previous_summary = summary
+        .and_then(|s| message_manager.get(&s.message_id))
+        .map(ChatMessage::to_request);
+    Some(SummaryJob {
+        agent_id,
+        previous_summary,
+        turns,
+        covered,
+    })
+}
+
+async fn summarize(job: &SummaryJob,
+                   route: &AgentRoute,
+                   model: &ModelInfo,
+                   authed_client: &UseSharedState<AuthedClient>) -> Result<(String, Usage), ProviderError> {
+    let (client, service) = route_client(route, authed_client)?;
+    let mut messages = vec![sys_msg(SUMMARIZER_PROMPT).to_request()];
+    messages.extend(job.previous_summary.clone());
+    messages.extend(job.turns.iter().cloned());
+    messages.push(user_msg("Summarize the conversation above.", AgentName::UserDefault).to_request());
+    let prompt_tokens = count_messages(&messages);
+    let request = CreateChatCompletionRequestArgs::default()
+        .model(route.model.clone())
+        .messages(messages)
+        .build()
+        .map_err(|e| ProviderError::Other(e.to_string()))?;
+    let response = client
+        .chat()
+        .create(request)
+        .await
+        .map_err(|e| ProviderError::classify(e, service))?;
+    let summary = response
+        .choices
+        .into_iter()
+        .next()
+        .and_then(|choice| choice.message.content)
+        .ok_or_else(|| ProviderError::Other("The summarizer replied nothing".to_string()))?;
+    // some providers report no usage, so count it locally then
+    let usage = match response.usage {
+        Some(usage) => Usage::new(usage.prompt_tokens as usize, usage.completion_tokens as usize, model, false),
+        None => Usage::new(prompt_tokens, count_text(&summary), model, true),
+    };
+    Ok((summary, usage))
+}
@@ -1,72 +1,120 @@
 use dioxus::prelude::*;
 use dioxus_markdown::Markdown;
-use transprompt::async_openai::types::{ChatCompletionRequestMessage, ChatCompletionRequestUserMessageContent};
-use transprompt::utils::llm::openai::ChatMsg;
+
+use crate::chat::{ChatMessage, MessageID, MessageMeta, Role};
+use crate::utils::streaming::StreamingReplies;
 
 #[derive(Props, PartialEq, Clone, Debug)]
 pub struct MessageCardProps {
-    chat_msg: ChatMsg,
+    chat_msg: ChatMessage,
+    meta: Option<MessageMeta>,
 }
 
 pub fn MessageCard(cx: Scope<MessageCardProps>) -> Element {
     let chat_msg = &cx.props.chat_msg;
-    match &chat_msg.msg {
-        ChatCompletionRequestMessage::System(sys_msg) => {
-            let content = sys_msg.content.as_str();
-            render! {
-                div {
-                    class: "flex flex-row-reverse items-start p-5",
-                    img {
-                        class: "ml-2 h-8 w-8 rounded-full",
-                        src: "https://dummyimage.com/128x128/354ea1/ffffff&text=S"
-                    }
-                    MarkdownTextBox {
-                        content: content,
-                    }
-                }
+    let from_assistant = matches!(chat_msg.role, Role::Assistant | Role::Tool | Role::Function);
+    let (row, avatar_margin, avatar_color, default_char) = match chat_msg.role {
+        Role::System => ("flex-row-reverse", "ml-2", "354ea1", 'S'),
+        Role::User => ("flex-row-reverse", "ml-2", "354ea1", 'U'),
+        Role::Assistant => ("", "mr-2", "363536", 'A'),
+        Role::Tool | Role::Function => ("", "mr-2", "363536", 'T'),
+    };
+    let name_char = match chat_msg.role {
+        Role::System => default_char,
+        _ => chat_msg.name
+            .as_ref()
+            .and_then(|name| name.chars().next())
+            .unwrap_or(default_char),
+    };
+    // images are shown inline and the calls an assistant asks for below its text
+    let mut content = chat_msg.text();
+    for call in chat_msg.tool_calls.iter() {
+        content.push_str(&format!("\n\nCalled `{}` with\n\n```json\n{}\n```", call.name, call.arguments));
+    }
+    render! {
+        div {
+            class: "flex {row} items-start p-5",
+            img {
+                class: "{avatar_margin} h-8 w-8 rounded-full",
+                src: "https://dummyimage.com/128x128/{avatar_color}/ffffff&text={name_char}"
+            }
+            MarkdownTextBox {
+                content: content.as_str(),
             }
         }
-        ChatCompletionRequestMessage::User(user_msg) => {
-            let content = match &user_msg.content {
-                ChatCompletionRequestUserMessageContent::Text(text) => text.as_str(),
-                ChatCompletionRequestUserMessageContent::Array(_) => todo!()
-            };
-            let name_char = user_msg.name.as_ref().map(|name| name.as_str().chars().next().unwrap()).unwrap_or('U');
-            render! {
-                div {
-                    class: "flex flex-row-reverse items-start p-5",
-                    img {
-                        class: "ml-2 h-8 w-8 rounded-full",
-                        src: "https://dummyimage.com/128x128/354ea1/ffffff&text={name_char}"
-                    }
-                    MarkdownTextBox {
-                        content: content,
-                    }
+        if let Some(meta) = &cx.props.meta {
+            rsx! {
+                MessageMetaLine {
+                    meta: meta.clone(),
+                    from_assistant: from_assistant,
                 }
             }
         }
-        ChatCompletionRequestMessage::Assistant(assistant_msg) => {
-            let content = assistant_msg.content
-                .as_ref()
-                .expect("Assistant message content is missing; Should not happen as of now")
-                .as_str();
-            let name_char = assistant_msg.name.as_ref()
-                .map(|name| name.as_str().chars().next().unwrap())
-                .unwrap_or('A');
-            render! {
-                div {
-                    class: "flex items-start p-5",
-                    img {
-                        class: "mr-2 h-8 w-8 rounded-full",
-                        src: "https://dummyimage.com/128x128/363536/ffffff&text={name_char}"
-                    }
-                    MarkdownTextBox {
-                        content: content,
+    }
+}
+
+/// A message card that shows what its reply has streamed so far, re-rendering on its own while it streams
+#[inline_props]
+pub fn StreamedMessageCard(cx: Scope, id: MessageID, chat_msg: ChatMessage, meta: MessageMeta) -> Element {
+    let streams = use_shared_state::<StreamingReplies>(cx).unwrap();
+    let streams = streams.read();
+    streams.subscribe(*id, cx.scope_id(), cx.schedule_update());
+    let replies = StreamingReplies::clone(&streams);
+    let scope = cx.scope_id();
+    use_on_destroy(cx, move || replies.unsubscribe(scope));
+    let mut chat_msg = chat_msg.clone();
+    if let Some(content) = streams.content(id).filter(|_| chat_msg.role == Role::Assistant) {
+        chat_msg.set_text(content);
+    }
+    render! {
+        MessageCard {
+            chat_msg: chat_msg,
+            meta: meta.clone(),
+        }
+    }
+}
+
+/// When, by which model and how fast a message was produced
+#[inline_props]
+fn MessageMetaLine(cx: Scope, meta: MessageMeta, from_assistant: bool) -> Element {
+    let align = if *from_assistant { "justify-start pl-16" } else { "justify-end pr-16" };
+    let created_at = meta.created_at.as_ref().map(|t| t.relative_to_now());
+    let producer = meta.producer();
+    let timing = match (meta.time_to_first_token_ms, meta.latency_ms) {
+        (Some(ttft), Some(latency)) => Some(format!("first token in {} · done in {}", format_ms(ttft), format_ms(latency))),
+        (None, Some(latency)) => Some(format!("done in {}", format_ms(latency))),
+        _ => None,
+    };
+    let usage = meta.usage.map(|u| u.summary());
+    render! {
+        div {
+            class: "-mt-4 flex flex-wrap items-center gap-2 px-5 text-xs text-slate-500 dark:text-slate-400 {align}",
+            if let Some(created_at) = created_at {
+                rsx! { span { "{created_at}" } }
+            }
+            if let Some(producer) = producer {
+                rsx! {
+                    span {
+                        class: "rounded-full bg-slate-300 px-2 dark:bg-slate-700",
+                        "{producer}"
                     }
                 }
             }
+            if let Some(timing) = timing {
+                rsx! { span { "{timing}" } }
+            }
+            if let Some(usage) = usage {
+                rsx! { span { "{usage}" } }
+            }
         }
-        ChatCompletionRequestMessage::Tool(_) | ChatCompletionRequestMessage::Function(_) => todo!(),
+    }
+}
+
+fn format_ms(ms: i64) -> String {
+    if ms < 1000 {
+        format!("{} ms", ms)
+    } else {
+        format!("{:.1} s", ms as f64 / 1000.0)
     }
 }
 
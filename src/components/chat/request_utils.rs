@@ -1,17 +1,28 @@
 use std::sync::{Arc, Mutex};
 
+use async_std::task::sleep;
+use chrono::Utc;
 use dioxus::prelude::*;
 use futures::future::join_all;
 use futures_util::StreamExt;
-use transprompt::async_openai::types::{ChatCompletionRequestMessage, CreateChatCompletionRequestArgs};
+use transprompt::async_openai::Client;
+use transprompt::async_openai::types::{ChatCompletionRequestAssistantMessage, ChatCompletionRequestMessage, ChatCompletionRequestUserMessageContent, CreateChatCompletionRequestArgs};
 use uuid::Uuid;
 
-use crate::agents::AgentID;
+use crate::agents::{AgentID, AgentInstance, AgentName, TruncationPolicy};
 use crate::pages::app::{AuthedClient, ChatId, StreamingReply};
-use crate::chat::{Chat, LinkedChatHistory, MessageID, MessageManager};
-use crate::components::chat::Request;
+use crate::chat::{Chat, LinkedChatHistory, MessageID, MessageManager, ReplyError};
+use crate::components::chat::{Refusal, Request};
+use crate::components::chat::memory::refresh_summaries;
 use crate::utils::{assistant_msg, EMPTY, user_msg};
+use crate::utils::errors::ProviderError;
+use crate::utils::scheduler::Scheduler;
+use crate::utils::settings::{AgentRoute, GPTService, NameConvention, RetryPolicies};
 use crate::utils::storage::StoredStates;
+use crate::utils::streaming::StreamingReplies;
+use crate::utils::tabs::ChatLocks;
+use crate::utils::tokens::{ContextUsage, count_messages, count_text, fit_context, prompt_budget};
+use crate::utils::usage::Usage;
 
 pub(super) fn find_chat_idx_by_id(chats: &Vec<Chat>, id: &Uuid) -> usize {
     for (idx, c) in chats.iter().enumerate() {
@@ -22,16 +33,59 @@ pub(super) fn find_chat_idx_by_id(chats: &Vec<Chat>, id: &Uuid) -> usize {
     unreachable!("Cannot find a chat, should not be since deleting is not implemented yet")
 }
 
+/// Change a chat that a round replies in, which may have been moved by a reload or a sync meanwhile,
+/// or deleted in another tab or on another device, in which case nothing is changed
+pub(super) fn with_chat<R>(global: &UseSharedState<StoredStates>,
+                           chat_id: &Uuid,
+                           f: impl FnOnce(&mut StoredStates, usize) -> R) -> Option<R> {
+    let mut global_mut = global.write();
+    let Some(chat_idx) = global_mut.chats.iter().position(|chat| chat.id == *chat_id) else {
+        log::warn!("Chat {} was deleted while replying in it", chat_id);
+        return None;
+    };
+    Some(f(&mut global_mut, chat_idx))
+}
+
 
 #[inline]
-fn map_chat_messages(chat_msgs: &LinkedChatHistory,
+pub(super) fn map_chat_messages(chat_msgs: &[MessageID],
                      message_manager: &MessageManager) -> Vec<ChatCompletionRequestMessage> {
     chat_msgs
         .iter()
-        .map(|msg_id| message_manager.get(msg_id).unwrap().msg.clone())
+        // interrupted replies that got nothing out are left out, as empty replies are rejected by some providers
+        .filter(|msg_id| !(message_manager.is_incomplete(msg_id) && message_manager.reply_content(msg_id).unwrap_or_default().is_empty()))
+        .map(|msg_id| message_manager.get(msg_id).unwrap().to_request())
         .collect()
 }
 
+/// Write the speaker into the content for providers that ignore the `name` field.
+///
+/// Replies of the agent itself stay assistant messages,
+/// while replies of other assistants become user messages, like what the agent hears from others.
+pub(super) fn inline_name(msg: ChatCompletionRequestMessage, agent_name: &AgentName) -> ChatCompletionRequestMessage {
+    match msg {
+        ChatCompletionRequestMessage::User(mut user_msg) => {
+            if let (Some(name), ChatCompletionRequestUserMessageContent::Text(text)) = (user_msg.name.take(), &mut user_msg.content) {
+                *text = format!("[{}]: {}", name, text);
+            }
+            ChatCompletionRequestMessage::User(user_msg)
+        }
+        ChatCompletionRequestMessage::Assistant(assistant_msg) => {
+            let content = assistant_msg.content.clone().unwrap_or_default();
+            if AgentName::assistant(assistant_msg.name.clone()) == *agent_name {
+                ChatCompletionRequestMessage::Assistant(ChatCompletionRequestAssistantMessage {
+                    name: None,
+                    ..assistant_msg
+                })
+            } else {
+                let speaker = assistant_msg.name.unwrap_or_else(|| "Assistant".to_string());
+                user_msg(format!("[{}]: {}", speaker, content), AgentName::UserDefault).to_request()
+            }
+        }
+        msg => msg,
+    }
+}
+
 #[inline]
 fn push_history(chat: &mut Chat,
                 agent_id: &AgentID,
@@ -53,74 +107,454 @@ fn linearize_replies(mut replies: Vec<(AgentID, MessageID, usize)>) -> LinkedCha
         .collect()
 }
 
+/// Where a stream failed, since drops in the middle of a reply follow their own retry policy
+enum StreamFailure {
+    Creation(ProviderError),
+    MidStream(ProviderError),
+}
+
+impl StreamFailure {
+    fn into_error(self) -> ProviderError {
+        match self {
+            StreamFailure::Creation(e) | StreamFailure::MidStream(e) => e,
+        }
+    }
+}
+
+/// how often what has streamed is saved, so that little of a reply is lost to a reload
+const CHECKPOINT_INTERVAL_MS: i64 = 2000;
+const CONTINUE_PROMPT: &str = "Your last reply was cut off. Continue it exactly where it stopped, without repeating any of it.";
+
+/// Shared states that replies are queued and streamed through
+#[derive(Clone)]
+pub(super) struct ReplyPipeline {
+    pub(super) scheduler: Scheduler,
+    pub(super) streams: StreamingReplies,
+}
+
+/// The message a reply streams into, along with the shared states streaming needs
+#[derive(Clone, Copy)]
+struct ReplySlot<'a> {
+    chat_id: Uuid,
+    reply_id: MessageID,
+    authed_client: &'a UseSharedState<AuthedClient>,
+    global: &'a UseSharedState<StoredStates>,
+    pipeline: &'a ReplyPipeline,
+    /// what the reply starts with, which is the part of an interrupted reply to continue
+    prefix: &'a str,
+}
+
+impl ReplySlot<'_> {
+    fn with_message_manager<R>(&self, f: impl FnOnce(&mut MessageManager) -> R) -> Option<R> {
+        with_chat(self.global, &self.chat_id, |global, chat_idx| f(&mut global.chat_mut(chat_idx).message_manager))
+    }
+
+    /// Drop what a failed stream left behind
+    fn reset(&self, agent_name: &AgentName) {
+        self.with_message_manager(|m| m.update(&self.reply_id, assistant_msg(self.prefix, agent_name.clone())));
+    }
+}
+
+/// Get the client of a route, which is its own if it names a profile or the shared one otherwise
+pub(super) fn route_client(route: &AgentRoute,
+                           authed_client: &UseSharedState<AuthedClient>) -> Result<(Client, GPTService), ProviderError> {
+    if route.locked {
+        return Err(ProviderError::Other("The API keys are locked, unlock them in the settings panel".to_string()));
+    }
+    let no_profile = || ProviderError::Other("No provider profile in use".to_string());
+    let service = route.service.ok_or_else(no_profile)?;
+    let client = match &route.auth {
+        Some(auth) => auth.build_client(),
+        None => authed_client
+            .read()
+            .as_ref()
+            .cloned()
+            .ok_or_else(no_profile)?,
+    };
+    Ok((client, service))
+}
+
+/// Stream the reply of an agent into its slot
+async fn stream_reply(slot: ReplySlot<'_>,
+                      messages_to_send: Vec<ChatCompletionRequestMessage>,
+                      route: AgentRoute) -> Result<(), StreamFailure> {
+    let (client, service) = route_client(&route, slot.authed_client).map_err(StreamFailure::Creation)?;
+    log::info!("Sending request to {:?} with model {}", service, route.model);
+    let mut request = CreateChatCompletionRequestArgs::default();
+    request
+        .model(route.model)
+        .messages(messages_to_send);
+    if let Some(max_tokens) = route.parameters.max_tokens {
+        request.max_tokens(max_tokens);
+    }
+    if let Some(temperature) = route.parameters.temperature {
+        request.temperature(temperature);
+    }
+    let request = request
+        .build()
+        .map_err(|e| StreamFailure::Creation(ProviderError::Other(e.to_string())))?;
+    let sent_at = Utc::now().timestamp_millis();
+    let mut first_token_at = None;
+    // send request, returning a stream
+    let mut stream = client
+        .chat()
+        .create_stream(request)
+        .await
+        .map_err(|e| StreamFailure::Creation(ProviderError::classify(e, service)))?;
+    // deltas go into the stream buffer, and into the chat only at checkpoints and once the reply is done
+    let streams = &slot.pipeline.streams;
+    streams.start(slot.reply_id, slot.prefix.to_string());
+    let mut checkpointed_at = sent_at;
+    while let Some(chunk) = stream.next().await {
+        let response = match chunk {
+            Ok(response) => response,
+            Err(e) => {
+                // keep what streamed before the failure, which is billed all the same
+                let content = streams.finish(&slot.reply_id).unwrap_or_default();
+                slot.with_message_manager(|m| m.set_reply_content(&slot.reply_id, content));
+                return Err(StreamFailure::MidStream(ProviderError::classify(e, service)));
+            }
+        };
+        if response.choices.is_empty() {
+            // azure openai service returns empty response on first call
+            continue;
+        }
+        first_token_at.get_or_insert_with(|| Utc::now().timestamp_millis());
+        if let Some(delta) = &response.choices[0].delta.content {
+            streams.push(&slot.reply_id, delta);
+        }
+        // save what has streamed so far, so that a reload in the middle of the reply keeps it
+        let now = Utc::now().timestamp_millis();
+        if now - checkpointed_at >= CHECKPOINT_INTERVAL_MS {
+            checkpointed_at = now;
+            if let Some(content) = streams.content(&slot.reply_id) {
+                slot.with_message_manager(|m| m.set_reply_content(&slot.reply_id, content));
+                slot.global.read().save();
+            }
+        }
+    }
+    let finished_at = Utc::now().timestamp_millis();
+    let content = streams.finish(&slot.reply_id).unwrap_or_default();
+    slot.with_message_manager(|m| {
+        m.set_reply_content(&slot.reply_id, content);
+        m.set_incomplete(&slot.reply_id, false);
+        if let Some(meta) = m.meta_mut(&slot.reply_id) {
+            meta.time_to_first_token_ms = first_token_at.map(|t| t - sent_at);
+            meta.latency_ms = Some(finished_at - sent_at);
+        }
+    });
+    Ok(())
+}
+
+/// Stream a reply through one route, retrying transient failures with backoff
+async fn stream_with_retries(slot: ReplySlot<'_>,
+                             agent_name: &AgentName,
+                             messages_to_send: &[ChatCompletionRequestMessage],
+                             route: &AgentRoute,
+                             policies: RetryPolicies) -> Result<(), ProviderError> {
+    let mut creation_attempts = 0;
+    let mut mid_stream_attempts = 0;
+    let tokens = (count_messages(messages_to_send) + route.parameters.max_tokens.unwrap_or_default() as usize) as u32;
+    let provider = route.profile.clone().unwrap_or_default();
+    loop {
+        // wait in the queue of the provider if it is busy, showing the reply as waiting meanwhile
+        let scheduler = &slot.pipeline.scheduler;
+        let permit = match scheduler.try_acquire(&provider, route.limits, tokens) {
+            Some(permit) => permit,
+            None => {
+                slot.with_message_manager(|m| m.set_waiting(&slot.reply_id, true));
+                let permit = scheduler.acquire(&provider, route.limits, tokens).await;
+                slot.with_message_manager(|m| m.set_waiting(&slot.reply_id, false));
+                permit
+            }
+        };
+        let result = stream_reply(slot, messages_to_send.to_vec(), route.clone()).await;
+        drop(permit);
+        let failure = match result {
+            Ok(()) => {
+                record_attempt_usage(slot, messages_to_send, route);
+                return Ok(());
+            }
+            Err(failure) => failure,
+        };
+        // requests refused before streaming are not billed, but those cut off midway are
+        if let StreamFailure::MidStream(_) = failure {
+            record_attempt_usage(slot, messages_to_send, route);
+        }
+        let (policy, attempt) = match &failure {
+            StreamFailure::Creation(_) => (policies.creation, &mut creation_attempts),
+            StreamFailure::MidStream(_) => (policies.mid_stream, &mut mid_stream_attempts),
+        };
+        let error = failure.into_error();
+        if !error.is_retryable() || *attempt >= policy.max_retries {
+            return Err(error);
+        }
+        let backoff = policy.backoff(*attempt, error.retry_after());
+        *attempt += 1;
+        log::warn!("Retrying reply via {} in {:?} after: {}", route.label(), backoff, error);
+        sleep(backoff).await;
+        slot.reset(agent_name);
+    }
+}
+
+/// Record the usage of an attempt at a reply, whose content is what has streamed into it
+fn record_attempt_usage(slot: ReplySlot<'_>, messages_sent: &[ChatCompletionRequestMessage], route: &AgentRoute) {
+    with_chat(slot.global, &slot.chat_id, |global, chat_idx| {
+        // streams carry no usage, so count it locally
+        let completion_tokens = global.chats[chat_idx].message_manager
+            .reply_content(&slot.reply_id)
+            .map_or(0, count_text)
+            .saturating_sub(count_text(slot.prefix));
+        let usage = Usage::new(count_messages(messages_sent), completion_tokens, &global.model_registry.info(&route.model), true);
+        global.record_usage(chat_idx, &slot.reply_id, usage);
+    });
+}
+
+/// Fit the context of an agent to a route, since name conventions and context windows differ between providers
+fn context_for_route(messages: &[ChatCompletionRequestMessage],
+                     agent_name: &AgentName,
+                     truncation: TruncationPolicy,
+                     route: &AgentRoute) -> (Vec<ChatCompletionRequestMessage>, usize) {
+    let messages = match route.name_convention() {
+        NameConvention::NameField => messages.to_vec(),
+        NameConvention::InlineName => messages
+            .iter()
+            .cloned()
+            .map(|msg| inline_name(msg, agent_name))
+            .collect(),
+    };
+    fit_context(messages, truncation, prompt_budget(route.context_window, route.parameters.max_tokens))
+}
+
+/// How full the context of an agent is with its primary route
+pub(super) fn context_usage(global: &StoredStates, chat: &Chat, agent: &AgentInstance) -> ContextUsage {
+    let message_manager = &chat.message_manager;
+    let context = message_manager.context_of(&agent.id, &agent.history);
+    let messages = map_chat_messages(&context, message_manager);
+    let route = global.agent_routes(chat, &agent.config).remove(0);
+    let (messages, dropped) = context_for_route(&messages, &agent.get_name(), agent.config.truncation, &route);
+    ContextUsage {
+        used: count_messages(&messages),
+        budget: prompt_budget(route.context_window, route.parameters.max_tokens),
+        dropped,
+    }
+}
+
+/// Stream a reply through the routes of an agent in order, recording the error on the reply if all fail
+async fn stream_reply_or_record_error(slot: ReplySlot<'_>,
+                                      assistant_id: AgentID,
+                                      agent_name: AgentName,
+                                      truncation: TruncationPolicy,
+                                      messages_to_send: Vec<ChatCompletionRequestMessage>,
+                                      routes: Vec<AgentRoute>) {
+    let policies = slot.global.read().retry_policies;
+    let mut last_error = None;
+    for route in routes {
+        let (messages, dropped) = context_for_route(&messages_to_send, &agent_name, truncation, &route);
+        if dropped > 0 {
+            log::info!("Dropped {} messages of agent {:?} to fit the context window of {}", dropped, assistant_id, route.model);
+        }
+        match stream_with_retries(slot, &agent_name, &messages, &route, policies).await {
+            Ok(()) => {
+                slot.with_message_manager(|m| if let Some(meta) = m.meta_mut(&slot.reply_id) {
+                    meta.profile = route.profile.clone();
+                    meta.model = Some(route.model.clone());
+                });
+                return;
+            }
+            Err(error) => {
+                log::error!("Reply of agent {:?} via {} failed: {:?}", assistant_id, route.label(), error);
+                slot.reset(&agent_name);
+                last_error = Some(error);
+            }
+        }
+    }
+    let error = last_error.unwrap_or_else(|| ProviderError::Other("No provider profile in use".to_string()));
+    slot.with_message_manager(|m| m.set_error(&slot.reply_id, ReplyError { agent_id: assistant_id, error }));
+}
+
 async fn post_agent_request(assistant_id: AgentID,
                             user_agent_id: AgentID,
-                            chat_idx: usize,
+                            chat_id: Uuid,
                             authed_client: UseSharedState<AuthedClient>,
                             order: Arc<Mutex<usize>>,
-                            global: UseSharedState<StoredStates>) -> (AgentID, MessageID, usize) {
+                            global: UseSharedState<StoredStates>,
+                            pipeline: ReplyPipeline) -> (AgentID, MessageID, usize) {
     let mut global_mut = global.write();
+    // the round has just put the query into the chat, which is there until the first await
+    let chat_idx = find_chat_idx_by_id(&global_mut.chats, &chat_id);
     let chat = &global_mut.chats[chat_idx];
     // get the context to send to AI
     let agent = chat.agents.get(&assistant_id).unwrap();
-    let messages_to_send = map_chat_messages(&agent.history, &chat.message_manager);
     let agent_name = agent.get_name();
+    let routes = global_mut.agent_routes(chat, &agent.config);
+    let truncation = agent.config.truncation;
+    let context = chat.message_manager.context_of(&assistant_id, &agent.history);
+    let messages_to_send = map_chat_messages(&context, &chat.message_manager);
     // update history, inserting assistant reply that is empty initially
-    let chat = &mut global_mut.chats[chat_idx];
-    let assistant_reply_id = chat.message_manager.insert(assistant_msg(EMPTY, agent_name));
+    let chat = global_mut.chat_mut(chat_idx);
+    let assistant_reply_id = chat.message_manager.insert_authored(assistant_msg(EMPTY, agent_name.clone()), Some(assistant_id));
+    chat.message_manager.set_incomplete(&assistant_reply_id, true);
     push_history(chat, &assistant_id, assistant_reply_id);
     push_history(chat, &user_agent_id, assistant_reply_id);
     // drop write lock before await point
     drop(global_mut);
-    // send request, returning a stream
-    let mut stream = authed_client
-        .read()
-        .as_ref()
-        .unwrap()
-        .chat()
-        .create_stream(CreateChatCompletionRequestArgs::default()
-            .model("gpt-3.5-turbo-0613") // TODO: use model when it's OpenAI Service
-            .messages(messages_to_send)
-            .build()
-            .expect("creating request failed"))
-        .await
-        .expect("creating stream failed");
-    while let Some(chunk) = stream.next().await {
-        match chunk {
-            Ok(response) => {
-                if response.choices.is_empty() {
-                    // azure openai service returns empty response on first call
-                    continue;
-                }
-                let mut global_mut = global.write();
-                let assistant_reply_msg = global_mut
-                    .chats[chat_idx]
-                    .message_manager
-                    .get_mut(&assistant_reply_id)
-                    .unwrap();
-                assistant_reply_msg.merge_delta(&response.choices[0].delta);
-            }
-            Err(e) => log::error!("OpenAI Error: {:?}", e),
-        }
-    }
+    let slot = ReplySlot {
+        chat_id,
+        reply_id: assistant_reply_id,
+        authed_client: &authed_client,
+        global: &global,
+        pipeline: &pipeline,
+        prefix: "",
+    };
+    stream_reply_or_record_error(slot, assistant_id, agent_name, truncation, messages_to_send, routes).await;
     let mut order = order.lock().unwrap();
     let got_order = *order;
     *order += 1;
     (assistant_id, assistant_reply_id, got_order)
 }
 
+/// Produce a reply again in the same message, from scratch or continuing from where it was cut off
+async fn redo_agent_request(reply_id: MessageID,
+                            continue_reply: bool,
+                            chat_id: Uuid,
+                            authed_client: &UseSharedState<AuthedClient>,
+                            global: &UseSharedState<StoredStates>,
+                            pipeline: &ReplyPipeline) {
+    let mut global_mut = global.write();
+    let chat_idx = find_chat_idx_by_id(&global_mut.chats, &chat_id);
+    let chat = &global_mut.chats[chat_idx];
+    // failed replies know their agent from the error, and interrupted ones from their author
+    let agent_id = chat.message_manager
+        .error(&reply_id)
+        .map(|e| e.agent_id)
+        .or_else(|| chat.message_manager.message(&reply_id).and_then(|m| m.meta.author));
+    let Some(agent) = agent_id.and_then(|agent_id| chat.agents.get(&agent_id)) else {
+        log::warn!("Reply {:?} has no agent to produce it again", reply_id);
+        return;
+    };
+    let agent_id = agent.id;
+    // the context is what the agent saw before the reply
+    let agent_name = agent.get_name();
+    let routes = global_mut.agent_routes(chat, &agent.config);
+    let truncation = agent.config.truncation;
+    let context_len = agent.history
+        .iter()
+        .position(|id| *id == reply_id)
+        .unwrap_or(agent.history.len());
+    let context = chat.message_manager.context_of(&agent_id, &agent.history[..context_len]);
+    let mut messages_to_send = map_chat_messages(&context, &chat.message_manager);
+    let prefix = match chat.message_manager.reply_content(&reply_id) {
+        Some(content) if continue_reply => content.to_string(),
+        _ => EMPTY,
+    };
+    if !prefix.is_empty() {
+        messages_to_send.push(assistant_msg(prefix.as_str(), agent_name.clone()).to_request());
+        messages_to_send.push(user_msg(CONTINUE_PROMPT, AgentName::UserDefault).to_request());
+    }
+    let message_manager = &mut global_mut.chat_mut(chat_idx).message_manager;
+    message_manager.clear_error(&reply_id);
+    message_manager.update(&reply_id, assistant_msg(prefix.as_str(), agent_name.clone()));
+    message_manager.set_incomplete(&reply_id, true);
+    drop(global_mut);
+    let slot = ReplySlot {
+        chat_id,
+        reply_id,
+        authed_client,
+        global,
+        pipeline,
+        prefix: &prefix,
+    };
+    stream_reply_or_record_error(slot, agent_id, agent_name, truncation, messages_to_send, routes).await;
+}
+
+
+/// Show why a request was not sent, giving its query back to the input
+fn refuse(refusal: &UseRef<Option<Refusal>>, chat_id: Uuid, reason: String, query: Option<String>) {
+    log::error!("{}", reason);
+    refusal.set(Some(Refusal {
+        chat_id,
+        reason,
+        query,
+        refused_at: Utc::now().timestamp_millis(),
+    }));
+}
+
+/// Claim a chat for a round, showing why not if another tab is replying in it
+fn acquire_chat(chat_locks: &UseSharedState<ChatLocks>,
+                refusal: &UseRef<Option<Refusal>>,
+                chat_id: Uuid,
+                query: Option<&str>) -> bool {
+    match chat_locks.write().acquire(chat_id) {
+        Ok(()) => {
+            if refusal.read().is_some() {
+                refusal.set(None);
+            }
+            true
+        }
+        Err(e) => {
+            let reason = format!("{}, so the request was not sent. Send it again once it is done", e);
+            refuse(refusal, chat_id, reason, query.map(str::to_string));
+            false
+        }
+    }
+}
 
 pub(super) async fn handle_request(mut rx: UnboundedReceiver<Request>,
                                    chat_id: UseSharedState<ChatId>,
                                    global: UseSharedState<StoredStates>,
                                    authed_client: UseSharedState<AuthedClient>,
-                                   streaming_reply: UseSharedState<StreamingReply>) {
-    while let Some(Request(request)) = rx.next().await {
+                                   streaming_reply: UseSharedState<StreamingReply>,
+                                   chat_locks: UseSharedState<ChatLocks>,
+                                   refusal: UseRef<Option<Refusal>>,
+                                   pipeline: ReplyPipeline) {
+    while let Some(request) = rx.next().await {
         let chat_id = chat_id.read().0;
         log::info!("chat id = {}", chat_id);
-        if authed_client.read().is_none() {
-            // TODO: handle this error and make a toast to notify user
-            log::error!("authed_client is None");
+        let request = match request {
+            Request::Query(query) => query,
+            Request::Retry(reply_id) | Request::Continue(reply_id) => {
+                let continue_reply = matches!(request, Request::Continue(_));
+                let chat_idx = find_chat_idx_by_id(&global.read().chats, &chat_id);
+                // replies produced again are billed like new ones
+                let cap_exceeded = global.read().spending_cap_exceeded(chat_idx);
+                if let Some(reason) = cap_exceeded {
+                    refuse(&refusal, chat_id, format!("{}, so the reply was not produced again", reason), None);
+                    continue;
+                }
+                if !acquire_chat(&chat_locks, &refusal, chat_id, None) {
+                    continue;
+                }
+                streaming_reply.write().0 = true;
+                redo_agent_request(reply_id, continue_reply, chat_id, &authed_client, &global, &pipeline).await;
+                global.read().save();
+                streaming_reply.write().0 = false;
+                chat_locks.write().release(chat_id);
+                continue;
+            }
+            Request::DiscardSummary(agent_id) => {
+                let mut global_mut = global.write();
+                let chat_idx = find_chat_idx_by_id(&global_mut.chats, &chat_id);
+                global_mut.chat_mut(chat_idx).message_manager.discard_summary(&agent_id);
+                global_mut.save();
+                continue;
+            }
+        };
+        {
+            let global = global.read();
+            let chat_idx = find_chat_idx_by_id(&global.chats, &chat_id);
+            // agents may talk to profiles of their own, so only a round that none of them can reply in is refused
+            if !global.has_usable_route(chat_idx) {
+                let reason = "No provider profile in use, pick one in the settings panel and send the message again".to_string();
+                refuse(&refusal, chat_id, reason, Some(request));
+                continue;
+            }
+            if let Some(reason) = global.spending_cap_exceeded(chat_idx) {
+                refuse(&refusal, chat_id, format!("{}, so the message was not sent", reason), Some(request));
+                continue;
+            }
+        }
+        if !acquire_chat(&chat_locks, &refusal, chat_id, Some(request.as_str())) {
             continue;
         }
         log::info!("request_handler {}", request);
@@ -134,7 +568,7 @@ pub(super) async fn handle_request(mut rx: UnboundedReceiver<Request>,
         let assistant_agent_ids: Vec<AgentID> = chat.assistant_agent_ids();
         // create user message and register them to chat manager
         let user_query = user_msg(request.as_str(), user_agent.get_name());
-        let user_msg_id = global_mut.chats[chat_idx].message_manager.insert(user_query.clone());
+        let user_msg_id = global_mut.chat_mut(chat_idx).message_manager.insert_authored(user_query.clone(), Some(user_agent_id));
         // update history, inserting user request
         global_mut
             .chats[chat_idx]
@@ -146,26 +580,30 @@ pub(super) async fn handle_request(mut rx: UnboundedReceiver<Request>,
         drop(global_mut);
         streaming_reply.write().0 = true;
         let order = Arc::new(Mutex::new(0_usize));
+        // all agents are posted at once, the scheduler queues them within the rate limits of their providers
         let results = join_all(
             assistant_agent_ids
                 .iter()
-                .map(|assistant_id| post_agent_request(*assistant_id, user_agent_id, chat_idx, authed_client.to_owned(), order.clone(), global.to_owned()))
+                .map(|assistant_id| post_agent_request(*assistant_id, user_agent_id, chat_id, authed_client.to_owned(), order.clone(), global.to_owned(), pipeline.clone()))
         ).await;
         let replies = linearize_replies(results);
         // add replies to history of each assistant
-        let mut global_mut = global.write();
-        let chat = &mut global_mut.chats[chat_idx];
-        assistant_agent_ids
-            .iter()
-            .for_each(|agent_id| {
-                for msg_id in replies.iter() {
-                    push_history(chat, agent_id, *msg_id);
-                }
-            });
-        drop(global_mut);
+        with_chat(&global, &chat_id, |global, chat_idx| {
+            let chat = global.chat_mut(chat_idx);
+            assistant_agent_ids
+                .iter()
+                .for_each(|agent_id| {
+                    for msg_id in replies.iter() {
+                        push_history(chat, agent_id, *msg_id);
+                    }
+                });
+        });
         // stage assistant reply into local storage
         global.read().save();
+        refresh_summaries(chat_id, &authed_client, &global).await;
+        global.read().save();
         streaming_reply.write().0 = false;
+        chat_locks.write().release(chat_id);
     }
     log::error!("request_handler exited");
 }
\ No newline at end of file
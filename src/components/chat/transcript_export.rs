@@ -0,0 +1,95 @@
+use dioxus::prelude::*;
+
+use crate::utils::data_url;
+use crate::utils::storage::StoredStates;
+use crate::utils::transcript::{Transcript, TranscriptFormat};
+
+/// Export the chat on display as a transcript
+#[inline_props]
+pub fn TranscriptExport(cx: Scope, chat_idx: usize) -> Element {
+    let global = use_shared_state::<StoredStates>(cx).unwrap();
+    let format = use_state(cx, TranscriptFormat::default);
+    let include_system_prompts = use_state(cx, || false);
+    // the transcript is rendered on a click only, since chats can be long
+    let export_url = use_state(cx, || None::<(usize, String, String)>);
+    let chat_idx = *chat_idx;
+    render! {
+        div {
+            class: "flex flex-wrap items-center gap-2 px-4 text-xs text-slate-500 dark:text-slate-400",
+            select {
+                class: "cursor-pointer rounded-lg bg-slate-300 px-2 py-1 focus:outline-none focus:ring-2 focus:ring-blue-600 dark:bg-slate-800",
+                onchange: |c| {
+                    let selected = TranscriptFormat::all()
+                        .into_iter()
+                        .find(|f| f.extension() == c.data.value);
+                    format.set(selected.unwrap_or_default());
+                    export_url.set(None);
+                },
+                TranscriptFormat::all().into_iter().map(|f| rsx! {
+                    option {
+                        value: "{f.extension()}",
+                        selected: f == *format.get(),
+                        "{f.label()}"
+                    }
+                })
+            }
+            label {
+                class: "flex cursor-pointer items-center gap-1",
+                input {
+                    r#type: "checkbox",
+                    checked: *include_system_prompts.get(),
+                    onchange: |c| {
+                        include_system_prompts.set(c.data.value.parse().unwrap_or_default());
+                        export_url.set(None);
+                    },
+                }
+                "System prompts"
+            }
+            match export_url.get() {
+                // a data URL is only downloaded on a click on a link to it, which is kept for the chat it was made of
+                Some((idx, url, file_name)) if *idx == chat_idx => rsx! {
+                    a {
+                        class: "rounded-lg bg-blue-600 px-2 py-1 text-slate-200",
+                        href: "{url}",
+                        download: "{file_name}",
+                        onclick: |_| export_url.set(None),
+                        "Download {file_name}"
+                    }
+                },
+                _ => rsx! {
+                    button {
+                        r#type: "button",
+                        class: "rounded-lg bg-slate-300 px-2 py-1 hover:bg-blue-600 hover:text-slate-200 dark:bg-slate-800 dark:hover:bg-blue-600",
+                        onclick: move |_| {
+                            let global = global.read();
+                            let Some(chat) = global.chats.get(chat_idx) else {
+                                return;
+                            };
+                            let format = *format.get();
+                            match Transcript::of(chat, *include_system_prompts.get()).render(format) {
+                                Ok(transcript) => {
+                                    let file_name = format!("{}.{}", file_stem(&chat.topic), format.extension());
+                                    export_url.set(Some((chat_idx, data_url(format.mime(), &transcript), file_name)));
+                                }
+                                Err(e) => log::error!("Error when exporting chat {}: {}", chat.topic, e),
+                            }
+                        },
+                        "Export Chat"
+                    }
+                },
+            }
+        }
+    }
+}
+
+/// A topic made safe for file names
+fn file_stem(topic: &str) -> String {
+    let stem: String = topic
+        .chars()
+        .map(|c| if c.is_alphanumeric() || c == '-' || c == '_' { c } else { '_' })
+        .collect();
+    match stem.trim_matches('_') {
+        "" => "chat".to_string(),
+        stem => stem.to_string(),
+    }
+}
@@ -92,7 +92,9 @@ async fn event_handler(mut rx: UnboundedReceiver<LeftSidebarEvent>,
                         }
                     }
                     let mut global = global.write();
-                    let new_chat = Chat::default();
+                    let mut new_chat = Chat::default();
+                    // new chats talk to the default profile, leaving the one in use to the others
+                    new_chat.profile = global.default_profile.clone();
                     let new_chat_id = new_chat.id;
                     global.chats.push(new_chat);
                     global.save();
@@ -1,41 +1,128 @@
 use dioxus::prelude::*;
 use futures_util::StreamExt;
-use transprompt::async_openai::Client;
-use transprompt::async_openai::config::{AzureConfig, OpenAIConfig};
 
-use crate::pages::app::{AppEvents, AuthedClient};
+pub use backup::*;
+pub use import::*;
+pub use sync::*;
+pub use vault::*;
+
+use crate::agents::{AgentConfig, AgentName, AgentProvider, AgentType, TruncationPolicy};
+use crate::pages::app::{AppEvents, AuthedClient, ChatId};
 use crate::utils::auth::Auth;
-use crate::utils::settings::{GPTService, OpenAIModel};
+use crate::utils::models::{fetch_models, ModelInfo};
+use crate::utils::settings::{GPTService, ProfileParameters, ProviderProfile, RateLimits, RetryPolicies, SummarizerSettings};
 use crate::utils::storage::StoredStates;
+use crate::utils::usage::SpendingCaps;
+
+pub mod backup;
+pub mod import;
+pub mod sync;
+pub mod vault;
 
 const API_KEY: &str = "api-key";
 const API_BASE: &str = "base-url";
 const ORG_ID: &str = "org-id";
 const API_VERSION: &str = "api-version";
 const DEPLOYMENT_ID: &str = "deployment-id";
+const PROFILE_NAME: &str = "profile-name";
 
 #[derive(Debug, Clone, PartialEq)]
 enum SettingEvent {
     SetGroupChat(bool),
     SelectService(Option<GPTService>),
-    SaveServiceConfig(Option<OpenAIModel>),
+    SaveServiceConfig,
+    AssignAgentProvider(AgentName, AgentProvider),
+    AssignAgentFallbacks(AgentName, Vec<AgentProvider>),
+    AssignAgentTruncation(AgentName, TruncationPolicy),
+    SelectProfile(String),
+    SetDefaultProfile(String),
+    DeleteProfile(String),
+    /// parameters of the named profile
+    SaveParameters(String, ProfileParameters),
+    SaveRetryPolicies(RetryPolicies),
+    /// rate limits of the named profile
+    SaveRateLimits(String, RateLimits),
+    SaveSummarizer(SummarizerSettings),
+    SaveSpendingCaps(SpendingCaps),
 }
 
 
 #[derive(Debug, Clone, PartialEq, Default)]
 struct ServiceSettings {
+    service: Option<GPTService>,
     api_key: Option<String>,
     api_base: Option<String>,
     org_id: Option<String>,
     api_version: Option<String>,
     deployment_id: Option<String>,
-    openai_model: Option<OpenAIModel>,
+    model: Option<String>,
+    profile_name: Option<String>,
+    fetched_models: Vec<ModelInfo>,
+    fetch_error: Option<String>,
+    connection_status: Option<ConnectionStatus>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum ConnectionStatus {
+    Testing,
+    Succeeded,
+    Failed(String),
+}
+
+impl ServiceSettings {
+    /// Check required fields and make an `Auth` of the selected service
+    fn to_auth(&self) -> Result<Auth, &'static str> {
+        let api_key = self.api_key.to_owned().ok_or("API Key is required")?;
+        match self.service.ok_or("Service is required")? {
+            GPTService::AzureOpenAI => Ok(Auth::AzureOpenAI {
+                api_version: self.api_version.to_owned().ok_or("API Version is required")?,
+                deployment_id: self.deployment_id.to_owned().ok_or("Deployment ID is required")?,
+                api_base: self.api_base.to_owned().ok_or("API Base is required")?,
+                api_key,
+            }),
+            GPTService::OpenAI => Ok(Auth::OpenAI {
+                api_key,
+                org_id: self.org_id.to_owned(),
+                api_base: self.api_base.to_owned(),
+            }),
+            GPTService::MiniMax => Ok(Auth::MiniMax {
+                api_key,
+                api_base: self.api_base.to_owned(),
+            }),
+            GPTService::Moonshot => Ok(Auth::Moonshot {
+                api_key,
+                api_base: self.api_base.to_owned(),
+            }),
+            GPTService::Zhipu => Ok(Auth::Zhipu {
+                api_key,
+                api_base: self.api_base.to_owned(),
+            }),
+        }
+    }
+
+    /// The model to make the test call with
+    fn test_model(&self) -> String {
+        self.model
+            .clone()
+            .or_else(|| self.usable_models().into_iter().next().map(|m| m.id))
+            .unwrap_or_default()
+    }
+
+    /// Models to choose from, which are fetched ones if any
+    fn usable_models(&self) -> Vec<ModelInfo> {
+        match (self.fetched_models.is_empty(), self.service) {
+            (false, _) => self.fetched_models.clone(),
+            (true, Some(service)) => ModelInfo::builtin(service),
+            (true, None) => vec![],
+        }
+    }
 }
 
 async fn setting_event_handler(mut rx: UnboundedReceiver<SettingEvent>,
                                enable_group_chat: UseState<bool>,
                                authed_client: UseSharedState<AuthedClient>,
                                service_settings: UseSharedState<ServiceSettings>,
+                               chat_id: UseSharedState<ChatId>,
                                global: UseSharedState<StoredStates>) {
     while let Some(event) = rx.next().await {
         log::info!("setting_event_handler {:?}", event);
@@ -44,84 +131,176 @@ async fn setting_event_handler(mut rx: UnboundedReceiver<SettingEvent>,
             SettingEvent::SelectService(service) => {
                 match service.as_ref() {
                     None => *service_settings.write() = ServiceSettings::default(),
-                    Some(s) => {
-                        if *s == GPTService::AzureOpenAI {
-                            service_settings.write().openai_model = None;
-                        }
+                    Some(_) => {
+                        let mut service_settings = service_settings.write();
+                        service_settings.model = None;
+                        service_settings.fetched_models.clear();
+                        service_settings.fetch_error = None;
+                        service_settings.connection_status = None;
+                        service_settings.service = service;
                     }
                 };
                 log::info!("Selected service: {:?}", service);
-                global.write().selected_service = service;
             }
-            SettingEvent::SaveServiceConfig(openai_model) => {
-                let gpt_service = global.read().selected_service.clone();
+            SettingEvent::SaveServiceConfig => {
+                let service_settings = service_settings.read();
+                let Some(gpt_service) = service_settings.service else {
+                    log::error!("Service is required");
+                    continue;
+                };
+                // keys saved while the vault is locked could not be sealed into it
+                if global.read().vault_locked() {
+                    log::error!("Unlock the API keys before saving a profile");
+                    continue;
+                }
                 log::info!("Saving service configs for {:?}", gpt_service);
-                match gpt_service {
-                    None => unreachable!(),
-                    Some(gpt_service) => {
-                        let service_settings = service_settings.read();
-                        // check fields first
-                        match gpt_service {
-                            GPTService::AzureOpenAI => {
-                                if service_settings.api_key.is_none() {
-                                    log::error!("API Key is required");
-                                    continue;
-                                }
-                                if service_settings.api_base.is_none() {
-                                    log::error!("API Base is required");
-                                    continue;
-                                }
-                                if service_settings.deployment_id.is_none() {
-                                    log::error!("Deployment ID is required");
-                                    continue;
-                                }
-                                if service_settings.api_version.is_none() {
-                                    log::error!("API Version is required");
-                                    continue;
-                                }
-                            }
-                            GPTService::OpenAI => {
-                                if service_settings.api_key.is_none() {
-                                    log::error!("API Key is required");
-                                    continue;
-                                }
-                                if openai_model.is_none() {
-                                    log::error!("Model is required");
-                                    continue;
-                                }
-                            }
+                // check fields first
+                let new_auth = match service_settings.to_auth() {
+                    Ok(auth) => auth,
+                    Err(e) => {
+                        log::error!("{}", e);
+                        continue;
+                    }
+                };
+                if gpt_service != GPTService::AzureOpenAI && service_settings.model.is_none() {
+                    log::error!("Model is required");
+                    continue;
+                }
+                // save configs
+                let new_authed_client = new_auth.build_client();
+                let profile_name = service_settings.profile_name
+                    .to_owned()
+                    .unwrap_or_else(|| gpt_service.to_string());
+                let profile_model = match gpt_service {
+                    GPTService::AzureOpenAI => None,
+                    _ => service_settings.model.to_owned(),
+                };
+                let mut global = global.write();
+                // keep the parameters and limits when updating an existing profile
+                let (parameters, limits) = global
+                    .find_profile(&profile_name)
+                    .map(|p| (p.parameters, p.limits))
+                    .unwrap_or_default();
+                global.upsert_profile(ProviderProfile {
+                    name: profile_name.clone(),
+                    auth: new_auth,
+                    model: profile_model,
+                    parameters,
+                    limits,
+                });
+                if !service_settings.fetched_models.is_empty() {
+                    global.model_registry.cache(profile_name.clone(), service_settings.fetched_models.clone());
+                }
+                // a saved profile is put into use right away
+                global.active_profile = Some(profile_name.clone());
+                if global.default_profile.is_none() {
+                    global.default_profile = Some(profile_name.clone());
+                }
+                authed_client.write().replace(new_authed_client);
+                global.save();
+                log::info!("Saved profile {}", profile_name);
+            }
+            SettingEvent::AssignAgentProvider(name, provider) => {
+                let mut global = global.write();
+                match global.name_to_configs.get(&name).cloned() {
+                    Some(config) => {
+                        global.update_agent_config(config.with_provider(provider));
+                        global.save();
+                    }
+                    None => log::error!("Cannot find AgentConfig of {:?}", name),
+                }
+            }
+            SettingEvent::AssignAgentFallbacks(name, fallbacks) => {
+                let mut global = global.write();
+                match global.name_to_configs.get(&name).cloned() {
+                    Some(config) => {
+                        global.update_agent_config(config.with_fallbacks(fallbacks));
+                        global.save();
+                    }
+                    None => log::error!("Cannot find AgentConfig of {:?}", name),
+                }
+            }
+            SettingEvent::AssignAgentTruncation(name, truncation) => {
+                let mut global = global.write();
+                match global.name_to_configs.get(&name).cloned() {
+                    Some(config) => {
+                        global.update_agent_config(config.with_truncation(truncation));
+                        global.save();
+                    }
+                    None => log::error!("Cannot find AgentConfig of {:?}", name),
+                }
+            }
+            SettingEvent::SelectProfile(name) => {
+                let mut global = global.write();
+                match global.find_profile(&name) {
+                    Some(_) => {
+                        // the chat on screen keeps talking to the picked profile, whichever is in use later
+                        let chat_id = chat_id.read().0;
+                        if let Some(chat) = global.chats.iter_mut().find(|chat| chat.id == chat_id) {
+                            chat.profile = Some(name.clone());
+                            chat.touch();
                         }
-                        // save configs
-                        let (new_auth, new_authed_client): (Auth, Client) = match gpt_service {
-                            GPTService::AzureOpenAI => {
-                                let auth = Auth::AzureOpenAI {
-                                    api_version: service_settings.api_version.to_owned().unwrap(),
-                                    deployment_id: service_settings.deployment_id.to_owned().unwrap(),
-                                    api_base: service_settings.api_base.to_owned().unwrap(),
-                                    api_key: service_settings.api_key.to_owned().unwrap(),
-                                };
-                                let client = Client::with_config::<AzureConfig>(auth.clone().into());
-                                (auth, client)
-                            }
-                            GPTService::OpenAI => {
-                                let auth = Auth::OpenAI {
-                                    api_key: service_settings.api_key.to_owned().unwrap(),
-                                    org_id: service_settings.org_id.to_owned(),
-                                    api_base: service_settings.api_base.to_owned(),
-                                };
-                                let client = Client::with_config::<OpenAIConfig>(auth.clone().into());
-                                (auth, client)
-                            }
-                        };
-                        let mut global = global.write();
-                        global.openai_model = openai_model;
-                        global.auth.replace(new_auth);
-                        authed_client.write().replace(new_authed_client);
+                        global.active_profile = Some(name);
+                        *authed_client.write() = global.active_auth().map(Auth::build_client);
                         global.save();
-                        // TODO: remove this after testing
-                        log::info!("Saved new auth: {:?}", global.auth);
+                    }
+                    None => log::error!("Cannot find profile {}", name),
+                }
+            }
+            SettingEvent::SetDefaultProfile(name) => {
+                let mut global = global.write();
+                if global.find_profile(&name).is_some() {
+                    global.default_profile = Some(name);
+                    global.save();
+                } else {
+                    log::error!("Cannot find profile {}", name);
+                }
+            }
+            SettingEvent::DeleteProfile(name) => {
+                let mut global = global.write();
+                if global.remove_profile(&name).is_some() {
+                    if global.active_profile.is_none() {
+                        *authed_client.write() = None;
+                    }
+                    global.save();
+                }
+            }
+            SettingEvent::SaveParameters(name, parameters) => {
+                let mut global = global.write();
+                match global.find_profile_mut(&name) {
+                    Some(profile) => profile.parameters = parameters,
+                    None => {
+                        log::error!("Cannot find profile {} to save parameters to", name);
+                        continue;
                     }
                 }
+                global.save();
+            }
+            SettingEvent::SaveRateLimits(name, limits) => {
+                let mut global = global.write();
+                match global.find_profile_mut(&name) {
+                    Some(profile) => profile.limits = limits,
+                    None => {
+                        log::error!("Cannot find profile {} to save rate limits to", name);
+                        continue;
+                    }
+                }
+                global.save();
+            }
+            SettingEvent::SaveSummarizer(summarizer) => {
+                let mut global = global.write();
+                global.summarizer = summarizer;
+                global.save();
+            }
+            SettingEvent::SaveSpendingCaps(spending_caps) => {
+                let mut global = global.write();
+                global.spending_caps = spending_caps;
+                global.save();
+            }
+            SettingEvent::SaveRetryPolicies(retry_policies) => {
+                let mut global = global.write();
+                global.retry_policies = retry_policies;
+                global.save();
             }
         }
     }
@@ -133,6 +312,7 @@ pub fn SettingSidebar(cx: Scope) -> Element {
     // get global states
     let global = use_shared_state::<StoredStates>(cx).unwrap();
     let authed_client = use_shared_state::<AuthedClient>(cx).unwrap();
+    let chat_id = use_shared_state::<ChatId>(cx).unwrap();
     // setup local states
     let enable_group_chat = use_state(cx, || false);
     // setup shared states
@@ -142,6 +322,7 @@ pub fn SettingSidebar(cx: Scope) -> Element {
                                                  enable_group_chat.to_owned(),
                                                  authed_client.to_owned(),
                                                  service_settings.to_owned(),
+                                                 chat_id.to_owned(),
                                                  global.to_owned()));
     render! {
         aside {
@@ -156,12 +337,22 @@ pub fn SettingSidebar(cx: Scope) -> Element {
                         "Settings"
                     }
                 }
+                VaultSettings {}
                 ToggleGroupChat {}
+                ProviderProfiles {}
                 ServiceConfigs {
-                    gpt_service: global.read().selected_service.clone(),
+                    gpt_service: service_settings.read().service,
                     enable_group_chat: *enable_group_chat.get(),
                 }
+                AgentProviders {}
                 ModelParameters {}
+                RateLimitSettings {}
+                RetrySettings {}
+                SummarizerConfigs {}
+                SpendingCapSettings {}
+                BackupSettings {}
+                ConversationImport {}
+                SyncServerSettings {}
             }
         }
     }
@@ -204,6 +395,95 @@ fn CloseSettingButton(cx: Scope) -> Element {
     }
 }
 
+fn ProviderProfiles(cx: Scope) -> Element {
+    let setting_event_handler = use_coroutine_handle::<SettingEvent>(cx).unwrap();
+    let chat_id = use_shared_state::<ChatId>(cx).unwrap().read().0;
+    let global = use_shared_state::<StoredStates>(cx).unwrap().read();
+    // the profile marked is the one the chat on screen talks to
+    let active_profile = global.chats
+        .iter()
+        .find(|chat| chat.id == chat_id)
+        .and_then(|chat| global.chat_profile(chat))
+        .or(global.active_profile.as_deref())
+        .unwrap_or_default()
+        .to_string();
+    let default_profile = global.default_profile.clone().unwrap_or_default();
+    let profiles: Vec<(String, GPTService)> = global
+        .profiles
+        .iter()
+        .map(|p| (p.name.clone(), p.service()))
+        .collect();
+    render! {
+        div {
+            class: "my-4 border-t border-slate-300 px-2 py-4 text-slate-800 dark:border-slate-700 dark:text-slate-200",
+            label {
+                class: "px-2 text-xs uppercase text-slate-500 dark:text-slate-400",
+                "Provider Profiles"
+            }
+            if profiles.is_empty() {
+                rsx! {
+                    p {
+                        class: "px-2 py-2 text-xs text-slate-500 dark:text-slate-400",
+                        "No profiles yet. Add one below."
+                    }
+                }
+            }
+            profiles.into_iter().map(|(name, service)| {
+                let is_active = name == active_profile;
+                let is_default = name == default_profile;
+                let select_name = name.clone();
+                let default_name = name.clone();
+                let delete_name = name.clone();
+                rsx! {
+                    div {
+                        class: "mt-2 flex items-center gap-x-2 rounded-lg px-2 py-2 text-xs hover:bg-slate-200 dark:hover:bg-slate-800",
+                        input {
+                            r#type: "radio",
+                            name: "active-profile",
+                            checked: is_active,
+                            onchange: move |_| setting_event_handler.send(SettingEvent::SelectProfile(select_name.clone())),
+                        }
+                        div {
+                            class: "flex-grow",
+                            p {
+                                class: "font-medium",
+                                "{name}"
+                            }
+                            p {
+                                class: "text-slate-500 dark:text-slate-400",
+                                "{service}"
+                            }
+                        }
+                        if is_default {
+                            rsx! {
+                                span {
+                                    class: "rounded-full bg-blue-600 px-2 py-1 text-slate-200",
+                                    "Default"
+                                }
+                            }
+                        } else {
+                            rsx! {
+                                button {
+                                    r#type: "button",
+                                    class: "rounded-lg px-2 py-1 hover:bg-blue-600 hover:text-slate-200",
+                                    onclick: move |_| setting_event_handler.send(SettingEvent::SetDefaultProfile(default_name.clone())),
+                                    "Set Default"
+                                }
+                            }
+                        }
+                        button {
+                            r#type: "button",
+                            class: "rounded-lg px-2 py-1 hover:bg-red-600 hover:text-slate-200",
+                            onclick: move |_| setting_event_handler.send(SettingEvent::DeleteProfile(delete_name.clone())),
+                            "Delete"
+                        }
+                    }
+                }
+            })
+        }
+    }
+}
+
 fn SelectServiceSection(cx: Scope) -> Element {
     const NULL_OPTION: &str = "Select AI Provider";
     let setting_event_handler = use_coroutine_handle::<SettingEvent>(cx).unwrap();
@@ -217,10 +497,11 @@ fn SelectServiceSection(cx: Scope) -> Element {
                 onchange: |select| {
                     let value = select.data.value.as_str();
                     match value {
-                        "AzureOpenAI" => setting_event_handler.send(SettingEvent::SelectService(Some(GPTService::AzureOpenAI))),
-                        "OpenAI" => setting_event_handler.send(SettingEvent::SelectService(Some(GPTService::OpenAI))),
-                        NULL_OPTION => setting_event_handler.send(SettingEvent::SelectService(None)),
-                        _ => log::error!("Unknown select-service value: {}", value),
+                        "" | NULL_OPTION => setting_event_handler.send(SettingEvent::SelectService(None)),
+                        _ => match value.parse::<GPTService>() {
+                            Ok(service) => setting_event_handler.send(SettingEvent::SelectService(Some(service))),
+                            Err(e) => log::error!("Unknown select-service value: {}", e),
+                        }
                     }
                 },
                 class: "mt-2 w-full cursor-pointer rounded-lg border-r-4 border-transparent bg-slate-200 py-3 pl-1 text-sm focus:outline-none focus:ring-2 focus:ring-blue-600 dark:bg-slate-800",
@@ -228,14 +509,12 @@ fn SelectServiceSection(cx: Scope) -> Element {
                     value: "",
                     "{NULL_OPTION}"
                 }
-                option {
-                    value: "AzureOpenAI",
-                    "Azure OpenAI"
-                }
-                option {
-                    value: "OpenAI",
-                    "OpenAI"
-                }
+                GPTService::all_services().iter().map(|service| rsx! {
+                    option {
+                        value: "{service.value()}",
+                        "{service}"
+                    }
+                })
             }
         }
     }
@@ -282,7 +561,30 @@ struct ServiceConfigsProps {
 
 enum ServiceEvent {
     SaveConfigs,
-    SelectOpenAIModel(Option<OpenAIModel>),
+    SelectModel(Option<String>),
+    FetchModels,
+    TestConnection,
+}
+
+/// Make a minimal call with the entered credentials, returning whether it succeeded
+async fn check_connection(service_settings: &UseSharedState<ServiceSettings>) -> bool {
+    let (auth, model) = {
+        let service_settings = service_settings.read();
+        (service_settings.to_auth(), service_settings.test_model())
+    };
+    let status = match auth {
+        Ok(auth) => {
+            service_settings.write().connection_status = Some(ConnectionStatus::Testing);
+            match auth.test_connection(&model).await {
+                Ok(()) => ConnectionStatus::Succeeded,
+                Err(e) => ConnectionStatus::Failed(e.to_string()),
+            }
+        }
+        Err(e) => ConnectionStatus::Failed(e.to_string()),
+    };
+    let succeeded = status == ConnectionStatus::Succeeded;
+    service_settings.write().connection_status = Some(status);
+    succeeded
 }
 
 
@@ -296,8 +598,47 @@ fn ServiceConfigs(cx: Scope<ServiceConfigsProps>) -> Element {
         async move {
             while let Some(event) = rx.next().await {
                 match event {
-                    ServiceEvent::SaveConfigs => setting_event_handler.send(SettingEvent::SaveServiceConfig(service_settings.read().openai_model.clone())),
-                    ServiceEvent::SelectOpenAIModel(model) => service_settings.write().openai_model = model,
+                    // validate the credentials before saving them
+                    ServiceEvent::SaveConfigs => {
+                        if check_connection(&service_settings).await {
+                            setting_event_handler.send(SettingEvent::SaveServiceConfig);
+                        }
+                    }
+                    ServiceEvent::TestConnection => {
+                        check_connection(&service_settings).await;
+                    }
+                    ServiceEvent::SelectModel(model) => service_settings.write().model = model,
+                    ServiceEvent::FetchModels => {
+                        let auth = {
+                            let service_settings = service_settings.read();
+                            match service_settings.service {
+                                // listing deployments only needs the API base and key
+                                Some(GPTService::AzureOpenAI) => ServiceSettings {
+                                    deployment_id: service_settings.deployment_id.clone().or(Some(String::new())),
+                                    api_version: service_settings.api_version.clone().or(Some(String::new())),
+                                    ..service_settings.clone()
+                                }.to_auth(),
+                                _ => service_settings.to_auth(),
+                            }
+                        };
+                        let fetched = match auth {
+                            Ok(auth) => fetch_models(&auth).await,
+                            Err(e) => Err(e.to_string()),
+                        };
+                        let mut service_settings = service_settings.write();
+                        match fetched {
+                            Ok(models) => {
+                                log::info!("Fetched {} models", models.len());
+                                service_settings.fetched_models = models;
+                                service_settings.fetch_error = None;
+                            }
+                            Err(e) => {
+                                log::error!("Fetching models failed: {}", e);
+                                service_settings.fetched_models.clear();
+                                service_settings.fetch_error = Some(e);
+                            }
+                        }
+                    }
                 }
             }
         }
@@ -307,20 +648,29 @@ fn ServiceConfigs(cx: Scope<ServiceConfigsProps>) -> Element {
             class: "my-4 border-t border-slate-300 px-2 py-4 text-slate-800 dark:border-slate-700 dark:text-slate-200",
             label {
                 class: "px-2 text-xs uppercase text-slate-500 dark:text-slate-400",
-                "Service Configurations"
+                "Add or Update a Profile"
             }
             SelectServiceSection {}
             if let Some(gpt_service) = cx.props.gpt_service {
                 rsx! {
+                    ProfileNameInput {
+                        gpt_service: gpt_service,
+                    }
                     SecretInputs {
                         gpt_service: gpt_service,
                     }
-                    if gpt_service == GPTService::OpenAI {
-                        rsx! {
-                            SelectOpenAIModel {
-                                enable_group_chat: cx.props.enable_group_chat,
-                            }
-                        }
+                    SelectModel {
+                        gpt_service: gpt_service,
+                        enable_group_chat: cx.props.enable_group_chat,
+                    }
+                    ConnectionStatusLine {}
+                    button {
+                        r#type: "button",
+                        class: "mt-4 block w-full rounded-lg bg-slate-200 p-2.5 text-xs font-semibold hover:bg-blue-600 hover:text-slate-200 focus:outline-none focus:ring-2 focus:ring-blue-600 dark:bg-slate-800 dark:hover:bg-blue-600",
+                        onclick: |_| {
+                            service_event_handler.send(ServiceEvent::TestConnection)
+                        },
+                        "Test Connection"
                     }
                     button {
                         r#type: "button",
@@ -328,7 +678,7 @@ fn ServiceConfigs(cx: Scope<ServiceConfigsProps>) -> Element {
                         onclick: |_| {
                             service_event_handler.send(ServiceEvent::SaveConfigs)
                         },
-                        "Save Configs"
+                        "Save Profile"
                     }
                 }
             }
@@ -336,6 +686,59 @@ fn ServiceConfigs(cx: Scope<ServiceConfigsProps>) -> Element {
     }
 }
 
+fn ConnectionStatusLine(cx: Scope) -> Element {
+    let service_settings = use_shared_state::<ServiceSettings>(cx).unwrap().read();
+    match service_settings.connection_status.as_ref() {
+        None => None,
+        Some(ConnectionStatus::Testing) => render! {
+            p {
+                class: "mt-4 px-2 text-xs text-slate-500 dark:text-slate-400",
+                "Testing connection..."
+            }
+        },
+        Some(ConnectionStatus::Succeeded) => render! {
+            p {
+                class: "mt-4 px-2 text-xs text-green-600",
+                "Connection succeeded"
+            }
+        },
+        Some(ConnectionStatus::Failed(reason)) => render! {
+            p {
+                class: "mt-4 px-2 text-xs text-red-600",
+                "{reason}"
+            }
+        },
+    }
+}
+
+#[inline_props]
+fn ProfileNameInput(cx: Scope, gpt_service: GPTService) -> Element {
+    let service_settings = use_shared_state::<ServiceSettings>(cx).unwrap();
+    render! {
+        div {
+            label {
+                r#for: "{PROFILE_NAME}",
+                class: "mb-2 mt-4 block px-2 text-sm font-medium",
+                "Profile Name (Optional)"
+            }
+            input {
+                r#type: "text",
+                id: "{PROFILE_NAME}",
+                class: "block w-full rounded-lg bg-slate-200 p-2.5 text-xs focus:outline-none focus:ring-2 focus:ring-blue-600 dark:bg-slate-800 dark:placeholder-slate-400 dark:focus:ring-blue-600",
+                placeholder: "{gpt_service}",
+                onchange: |c| {
+                    let value = c.data.value.trim();
+                    if value.is_empty() {
+                        service_settings.write().profile_name = None;
+                    } else {
+                        service_settings.write().profile_name = Some(value.to_string());
+                    }
+                },
+            }
+        }
+    }
+}
+
 #[derive(Props, PartialEq)]
 struct SecretInputsProps {
     gpt_service: GPTService,
@@ -460,6 +863,7 @@ fn SecretInputs(cx: Scope<SecretInputsProps>) -> Element {
                 input {
                     r#type: "text",
                     id: "{DEPLOYMENT_ID}",
+                    list: "model-list",
                     class: "{INPUT_STYLE}",
                     placeholder: "Required",
                     onchange: |c| {
@@ -492,52 +896,256 @@ fn SecretInputs(cx: Scope<SecretInputsProps>) -> Element {
                     },
                 }
             }
+        },
+        GPTService::MiniMax | GPTService::Moonshot | GPTService::Zhipu => {
+            let default_api_base = cx.props.gpt_service.default_api_base().unwrap_or_default();
+            render! {
+                div {
+                    // API Key
+                    label {
+                        r#for: "{API_KEY}",
+                        class: "{LABEL_STYLE}",
+                        "API Key"
+                    }
+                    input {
+                        r#type: "password",
+                        id: "{API_KEY}",
+                        class: "{INPUT_STYLE}",
+                        placeholder: "Required",
+                        onchange: |c| {
+                            let value = &c.data.value;
+                            if value.is_empty() {
+                                service_settings.write().api_key = None;
+                            } else {
+                                service_settings.write().api_key = Some(value.to_string());
+                            }
+                        },
+                    }
+                    // Base URL
+                    label {
+                        r#for: "{API_BASE}",
+                        class: "{LABEL_STYLE}",
+                        "Base URL / API Base (Optional)"
+                    }
+                    input {
+                        r#type: "url",
+                        id: "{API_BASE}",
+                        class: "{INPUT_STYLE}",
+                        placeholder: "{default_api_base}",
+                        onchange: |c| {
+                            let value = &c.data.value;
+                            if value.is_empty() {
+                                service_settings.write().api_base = None;
+                            } else {
+                                service_settings.write().api_base = Some(value.to_string());
+                            }
+                        },
+                    }
+                }
+            }
         }
     }
 }
 
 #[inline_props]
-fn SelectOpenAIModel(cx: Scope, enable_group_chat: bool) -> Element {
-    const NULL_OPTION: &str = "Select a model";
+fn SelectModel(cx: Scope, gpt_service: GPTService, enable_group_chat: bool) -> Element {
+    const MODEL_LIST: &str = "model-list";
     let service_event_handler = use_coroutine_handle::<ServiceEvent>(cx).unwrap();
-    let usable_models = if *enable_group_chat {
-        OpenAIModel::gpt4_models()
-    } else {
-        OpenAIModel::all_models()
+    let service_settings = use_shared_state::<ServiceSettings>(cx).unwrap().read();
+    let usable_models = service_settings.usable_models();
+    let fetch_error = service_settings.fetch_error.clone();
+    // Azure OpenAI routes by deployment, so fetched deployments fill in the deployment id instead
+    let is_azure = *gpt_service == GPTService::AzureOpenAI;
+    render! {
+        div {
+            if !is_azure {
+                rsx! {
+                    label {
+                        r#for: "select-model",
+                        class: "mb-2 mt-4 block px-2 text-sm font-medium",
+                        "Model"
+                    }
+                    input {
+                        r#type: "text",
+                        id: "select-model",
+                        list: "{MODEL_LIST}",
+                        class: "block w-full rounded-lg bg-slate-200 p-2.5 text-xs focus:outline-none focus:ring-2 focus:ring-blue-600 dark:bg-slate-800 dark:placeholder-slate-400 dark:focus:ring-blue-600",
+                        placeholder: "Select or enter a model id",
+                        onchange: |change| {
+                            let model = change.data.value.trim();
+                            if model.is_empty() {
+                                service_event_handler.send(ServiceEvent::SelectModel(None));
+                            } else {
+                                service_event_handler.send(ServiceEvent::SelectModel(Some(model.to_string())));
+                            }
+                        },
+                    }
+                }
+            }
+            datalist {
+                id: "{MODEL_LIST}",
+                usable_models.iter().map(|model| rsx! {
+                    option {
+                        value: "{model.id}",
+                        "{model.summary()}"
+                    }
+                })
+            }
+            if *enable_group_chat {
+                rsx! {
+                    p {
+                        class: "mt-2 px-2 text-xs text-slate-500 dark:text-slate-400",
+                        "Group chat works best with GPT-4 class models."
+                    }
+                }
+            }
+            if let Some(fetch_error) = fetch_error {
+                rsx! {
+                    p {
+                        class: "mt-2 px-2 text-xs text-red-600",
+                        "{fetch_error}"
+                    }
+                }
+            }
+            button {
+                r#type: "button",
+                class: "mt-2 block w-full rounded-lg bg-slate-200 p-2.5 text-xs font-semibold hover:bg-blue-600 hover:text-slate-200 focus:outline-none focus:ring-2 focus:ring-blue-600 dark:bg-slate-800 dark:hover:bg-blue-600",
+                onclick: |_| service_event_handler.send(ServiceEvent::FetchModels),
+                if is_azure {
+                    "Fetch Deployments"
+                } else {
+                    "Fetch Models"
+                }
+            }
+        }
+    }
+}
+
+fn AgentProviders(cx: Scope) -> Element {
+    let global = use_shared_state::<StoredStates>(cx).unwrap().read();
+    let mut assistant_configs: Vec<AgentConfig> = global
+        .name_to_configs
+        .values()
+        .filter(|config| matches!(config.agent_type, AgentType::Assistant { .. }))
+        .cloned()
+        .collect();
+    assistant_configs.sort_by_cached_key(|config| config.name.to_string());
+    let profile_names: Vec<String> = global.profiles.iter().map(|p| p.name.clone()).collect();
+    render! {
+        div {
+            class: "my-4 border-t border-slate-300 px-2 py-4 text-slate-800 dark:border-slate-700 dark:text-slate-200",
+            label {
+                class: "px-2 text-xs uppercase text-slate-500 dark:text-slate-400",
+                "Agent Providers"
+            }
+            assistant_configs.into_iter().map(|config| rsx! {
+                AgentProviderInputs {
+                    config: config,
+                    profile_names: profile_names.clone(),
+                }
+            })
+        }
+    }
+}
+
+#[inline_props]
+fn AgentProviderInputs(cx: Scope, config: AgentConfig, profile_names: Vec<String>) -> Element {
+    const LABEL_STYLE: &str = "mb-2 mt-4 block px-2 text-sm font-medium";
+    const INPUT_STYLE: &str = "block w-full rounded-lg bg-slate-200 p-2.5 text-xs focus:outline-none focus:ring-2 focus:ring-blue-600 dark:bg-slate-800 dark:placeholder-slate-400 dark:focus:ring-blue-600";
+    let setting_event_handler = use_coroutine_handle::<SettingEvent>(cx).unwrap();
+    let agent_name = config.name.to_string();
+    let selected_profile = config.provider.profile.clone().unwrap_or_default();
+    let model = config.provider.model.clone().unwrap_or_default();
+    let fallbacks = config.fallbacks
+        .iter()
+        .map(|provider| provider.to_string())
+        .collect::<Vec<_>>()
+        .join(", ");
+    let keep_recent_turns = match config.truncation {
+        TruncationPolicy::DropOldest => String::new(),
+        TruncationPolicy::KeepRecent(n) => n.to_string(),
     };
     render! {
         div {
             label {
-                r#for: "select-model",
-                class: "mb-2 mt-4 block px-2 text-sm font-medium",
-                "Model"
+                class: "{LABEL_STYLE}",
+                "{agent_name}"
             }
             select {
-                name: "select-model",
-                onchange: |change|{
-                    let model = change.data.value.as_str();
-                    if model == NULL_OPTION {
-                        service_event_handler.send(ServiceEvent::SelectOpenAIModel(None));
-                    } else {
-                        match usable_models.iter().find(|m| (*m).eq(model)).cloned() {
-                            Some(m) => service_event_handler.send(ServiceEvent::SelectOpenAIModel(Some(m))),
-                            None => log::error!("Unknown model: {}", model),
-                        }
-                    }
-                },
-                id: "select-model",
                 class: "block w-full cursor-pointer rounded-lg border-r-4 border-transparent bg-slate-200 py-3 pl-1 text-xs focus:outline-none focus:ring-2 focus:ring-blue-600 dark:bg-slate-800 dark:placeholder-slate-400 dark:focus:ring-blue-600",
+                onchange: move |change| {
+                    let profile = change.data.value.clone();
+                    let provider = AgentProvider {
+                        profile: if profile.is_empty() { None } else { Some(profile) },
+                        ..config.provider.clone()
+                    };
+                    setting_event_handler.send(SettingEvent::AssignAgentProvider(config.name.clone(), provider));
+                },
                 option {
                     value: "",
-                    "{NULL_OPTION}"
+                    selected: selected_profile.is_empty(),
+                    "Profile in use"
                 }
-                usable_models.iter().map(|model| rsx! {
+                profile_names.iter().map(|profile_name| rsx! {
                     option {
-                        value: "{model}",
-                        "{model}"
+                        value: "{profile_name}",
+                        selected: *profile_name == selected_profile,
+                        "{profile_name}"
                     }
                 })
             }
+            input {
+                r#type: "text",
+                class: "mt-2 {INPUT_STYLE}",
+                value: "{model}",
+                placeholder: "Default model of the profile",
+                onchange: move |c| {
+                    let model = c.data.value.trim();
+                    let provider = AgentProvider {
+                        model: if model.is_empty() { None } else { Some(model.to_string()) },
+                        ..config.provider.clone()
+                    };
+                    setting_event_handler.send(SettingEvent::AssignAgentProvider(config.name.clone(), provider));
+                },
+            }
+            input {
+                r#type: "text",
+                class: "mt-2 {INPUT_STYLE}",
+                value: "{fallbacks}",
+                placeholder: "Fallbacks, e.g. backup:gpt-4, other",
+                onchange: move |c| {
+                    let fallbacks = c.data.value
+                        .split(',')
+                        .filter(|s| !s.trim().is_empty())
+                        .map(AgentProvider::parse)
+                        .collect();
+                    setting_event_handler.send(SettingEvent::AssignAgentFallbacks(config.name.clone(), fallbacks));
+                },
+            }
+            input {
+                r#type: "number",
+                class: "mt-2 {INPUT_STYLE}",
+                min: "1",
+                value: "{keep_recent_turns}",
+                placeholder: "Recent turns to keep, dropping the oldest if empty",
+                onchange: move |c| {
+                    let value = c.data.value.trim();
+                    let truncation = if value.is_empty() {
+                        Some(TruncationPolicy::DropOldest)
+                    } else {
+                        match value.parse::<usize>() {
+                            Ok(n) => Some(TruncationPolicy::KeepRecent(n.max(1))),
+                            Err(e) => {
+                                log::error!("Invalid number of turns {}: {}", value, e);
+                                None
+                            }
+                        }
+                    };
+                    if let Some(truncation) = truncation {
+                        setting_event_handler.send(SettingEvent::AssignAgentTruncation(config.name.clone(), truncation));
+                    }
+                },
+            }
         }
     }
 }
@@ -545,6 +1153,20 @@ fn SelectOpenAIModel(cx: Scope, enable_group_chat: bool) -> Element {
 fn ModelParameters(cx: Scope) -> Element {
     const LABEL_STYLE: &str = "mb-2 mt-4 block px-2 text-sm font-medium";
     const INPUT_STYLE: &str = "block w-full rounded-lg bg-slate-200 p-2.5 text-xs focus:outline-none focus:ring-2 focus:ring-blue-600 dark:bg-slate-800 dark:placeholder-slate-400 dark:focus:ring-blue-600";
+    let setting_event_handler = use_coroutine_handle::<SettingEvent>(cx).unwrap();
+    let global = use_shared_state::<StoredStates>(cx).unwrap().read();
+    let active_profile = global.active_profile.clone();
+    let saved_parameters = global
+        .active_profile()
+        .map(|p| p.parameters)
+        .unwrap_or_default();
+    // edits belong to the profile they were made for, and start over from the saved ones when another is in use
+    let parameters = use_ref(cx, || (active_profile.clone(), saved_parameters));
+    if parameters.read().0 != active_profile {
+        *parameters.write_silent() = (active_profile, saved_parameters);
+    }
+    let max_tokens = saved_parameters.max_tokens.map(|t| t.to_string()).unwrap_or_default();
+    let temperature = saved_parameters.temperature.map(|t| t.to_string()).unwrap_or_default();
     render! {
         div {
             class: "my-4 border-t border-slate-300 px-2 py-4 text-slate-800 dark:border-slate-700 dark:text-slate-200",
@@ -562,6 +1184,18 @@ fn ModelParameters(cx: Scope) -> Element {
                 id: "max-tokens",
                 class: "{INPUT_STYLE}",
                 placeholder: "2048",
+                value: "{max_tokens}",
+                onchange: |c| {
+                    let value = c.data.value.trim();
+                    if value.is_empty() {
+                        parameters.write().1.max_tokens = None;
+                    } else {
+                        match value.parse::<u16>() {
+                            Ok(max_tokens) => parameters.write().1.max_tokens = Some(max_tokens),
+                            Err(e) => log::error!("Invalid max tokens {}: {}", value, e),
+                        }
+                    }
+                },
             }
             label {
                 r#for: "model-temperature",
@@ -573,12 +1207,410 @@ fn ModelParameters(cx: Scope) -> Element {
                 id: "model-temperature",
                 class: "{INPUT_STYLE}",
                 placeholder: "0.7",
+                step: "0.1",
+                value: "{temperature}",
+                onchange: |c| {
+                    let value = c.data.value.trim();
+                    if value.is_empty() {
+                        parameters.write().1.temperature = None;
+                    } else {
+                        match value.parse::<f32>() {
+                            Ok(temperature) => parameters.write().1.temperature = Some(temperature),
+                            Err(e) => log::error!("Invalid temperature {}: {}", value, e),
+                        }
+                    }
+                },
             }
             button {
                 r#type: "button",
                 class: "mt-4 block w-full rounded-lg bg-slate-200 p-2.5 text-xs font-semibold hover:bg-blue-600 hover:text-slate-200 focus:outline-none focus:ring-2 focus:ring-blue-600 dark:bg-slate-800 dark:hover:bg-blue-600",
+                onclick: |_| match parameters.read().clone() {
+                    (Some(name), parameters) => setting_event_handler.send(SettingEvent::SaveParameters(name, parameters)),
+                    (None, _) => log::error!("No profile in use to save parameters to"),
+                },
                 "Save Parameters"
             }
         }
     }
-}
\ No newline at end of file
+}
+
+fn RetrySettings(cx: Scope) -> Element {
+    const LABEL_STYLE: &str = "mb-2 mt-4 block px-2 text-sm font-medium";
+    const INPUT_STYLE: &str = "block w-full rounded-lg bg-slate-200 p-2.5 text-xs focus:outline-none focus:ring-2 focus:ring-blue-600 dark:bg-slate-800 dark:placeholder-slate-400 dark:focus:ring-blue-600";
+    let setting_event_handler = use_coroutine_handle::<SettingEvent>(cx).unwrap();
+    let saved_policies = use_shared_state::<StoredStates>(cx).unwrap().read().retry_policies;
+    let policies = use_ref(cx, || saved_policies);
+    let creation_retries = saved_policies.creation.max_retries;
+    let mid_stream_retries = saved_policies.mid_stream.max_retries;
+    let initial_backoff = saved_policies.creation.initial_backoff_ms;
+    let max_backoff = saved_policies.creation.max_backoff_ms;
+    let parse_or_log = |value: &str| {
+        let parsed = value.trim().parse::<u64>();
+        if let Err(e) = &parsed {
+            log::error!("Invalid retry setting {}: {}", value, e);
+        }
+        parsed.ok()
+    };
+    render! {
+        div {
+            class: "my-4 border-t border-slate-300 px-2 py-4 text-slate-800 dark:border-slate-700 dark:text-slate-200",
+            label {
+                class: "px-2 text-xs uppercase text-slate-500 dark:text-slate-400",
+                "Retries"
+            }
+            label {
+                r#for: "creation-retries",
+                class: "{LABEL_STYLE}",
+                "Retries of failed requests"
+            }
+            input {
+                r#type: "number",
+                id: "creation-retries",
+                class: "{INPUT_STYLE}",
+                value: "{creation_retries}",
+                onchange: move |c| {
+                    if let Some(retries) = parse_or_log(&c.data.value) {
+                        policies.write().creation.max_retries = retries as u32;
+                    }
+                },
+            }
+            label {
+                r#for: "mid-stream-retries",
+                class: "{LABEL_STYLE}",
+                "Retries of dropped streams"
+            }
+            input {
+                r#type: "number",
+                id: "mid-stream-retries",
+                class: "{INPUT_STYLE}",
+                value: "{mid_stream_retries}",
+                onchange: move |c| {
+                    if let Some(retries) = parse_or_log(&c.data.value) {
+                        policies.write().mid_stream.max_retries = retries as u32;
+                    }
+                },
+            }
+            label {
+                r#for: "initial-backoff",
+                class: "{LABEL_STYLE}",
+                "Initial backoff (ms)"
+            }
+            input {
+                r#type: "number",
+                id: "initial-backoff",
+                class: "{INPUT_STYLE}",
+                value: "{initial_backoff}",
+                onchange: move |c| {
+                    if let Some(backoff) = parse_or_log(&c.data.value) {
+                        let mut policies = policies.write();
+                        policies.creation.initial_backoff_ms = backoff;
+                        policies.mid_stream.initial_backoff_ms = backoff;
+                    }
+                },
+            }
+            label {
+                r#for: "max-backoff",
+                class: "{LABEL_STYLE}",
+                "Max backoff (ms)"
+            }
+            input {
+                r#type: "number",
+                id: "max-backoff",
+                class: "{INPUT_STYLE}",
+                value: "{max_backoff}",
+                onchange: move |c| {
+                    if let Some(backoff) = parse_or_log(&c.data.value) {
+                        let mut policies = policies.write();
+                        policies.creation.max_backoff_ms = backoff;
+                        policies.mid_stream.max_backoff_ms = backoff;
+                    }
+                },
+            }
+            p {
+                class: "mt-2 px-2 text-xs text-slate-500 dark:text-slate-400",
+                "Rate limits, server errors and network failures are retried, then the fallbacks of an agent are tried in order."
+            }
+            button {
+                r#type: "button",
+                class: "mt-4 block w-full rounded-lg bg-slate-200 p-2.5 text-xs font-semibold hover:bg-blue-600 hover:text-slate-200 focus:outline-none focus:ring-2 focus:ring-blue-600 dark:bg-slate-800 dark:hover:bg-blue-600",
+                onclick: |_| setting_event_handler.send(SettingEvent::SaveRetryPolicies(*policies.read())),
+                "Save Retries"
+            }
+        }
+    }
+}
+
+fn RateLimitSettings(cx: Scope) -> Element {
+    const LABEL_STYLE: &str = "mb-2 mt-4 block px-2 text-sm font-medium";
+    const INPUT_STYLE: &str = "block w-full rounded-lg bg-slate-200 p-2.5 text-xs focus:outline-none focus:ring-2 focus:ring-blue-600 dark:bg-slate-800 dark:placeholder-slate-400 dark:focus:ring-blue-600";
+    let setting_event_handler = use_coroutine_handle::<SettingEvent>(cx).unwrap();
+    let global = use_shared_state::<StoredStates>(cx).unwrap().read();
+    let active_profile = global.active_profile.clone();
+    let saved_limits = global
+        .active_profile()
+        .map(|p| p.limits)
+        .unwrap_or_default();
+    // like the parameters, edits belong to the profile they were made for
+    let limits = use_ref(cx, || (active_profile.clone(), saved_limits));
+    if limits.read().0 != active_profile {
+        *limits.write_silent() = (active_profile, saved_limits);
+    }
+    let max_concurrent = saved_limits.max_concurrent;
+    let requests_per_minute = saved_limits.requests_per_minute.map(|r| r.to_string()).unwrap_or_default();
+    let tokens_per_minute = saved_limits.tokens_per_minute.map(|t| t.to_string()).unwrap_or_default();
+    // empty means no limit
+    let parse_limit = |value: &str| -> Result<Option<u32>, ()> {
+        let value = value.trim();
+        if value.is_empty() {
+            return Ok(None);
+        }
+        value
+            .parse::<u32>()
+            .map(Some)
+            .map_err(|e| log::error!("Invalid rate limit {}: {}", value, e))
+    };
+    render! {
+        div {
+            class: "my-4 border-t border-slate-300 px-2 py-4 text-slate-800 dark:border-slate-700 dark:text-slate-200",
+            label {
+                class: "px-2 text-xs uppercase text-slate-500 dark:text-slate-400",
+                "Rate Limits"
+            }
+            label {
+                r#for: "max-concurrent",
+                class: "{LABEL_STYLE}",
+                "Concurrent requests"
+            }
+            input {
+                r#type: "number",
+                id: "max-concurrent",
+                class: "{INPUT_STYLE}",
+                min: "1",
+                value: "{max_concurrent}",
+                onchange: move |c| {
+                    if let Ok(Some(max_concurrent)) = parse_limit(&c.data.value) {
+                        limits.write().1.max_concurrent = max_concurrent.max(1);
+                    }
+                },
+            }
+            label {
+                r#for: "requests-per-minute",
+                class: "{LABEL_STYLE}",
+                "Requests per minute"
+            }
+            input {
+                r#type: "number",
+                id: "requests-per-minute",
+                class: "{INPUT_STYLE}",
+                placeholder: "No limit",
+                value: "{requests_per_minute}",
+                onchange: move |c| {
+                    if let Ok(requests_per_minute) = parse_limit(&c.data.value) {
+                        limits.write().1.requests_per_minute = requests_per_minute;
+                    }
+                },
+            }
+            label {
+                r#for: "tokens-per-minute",
+                class: "{LABEL_STYLE}",
+                "Tokens per minute"
+            }
+            input {
+                r#type: "number",
+                id: "tokens-per-minute",
+                class: "{INPUT_STYLE}",
+                placeholder: "No limit",
+                value: "{tokens_per_minute}",
+                onchange: move |c| {
+                    if let Ok(tokens_per_minute) = parse_limit(&c.data.value) {
+                        limits.write().1.tokens_per_minute = tokens_per_minute;
+                    }
+                },
+            }
+            p {
+                class: "mt-2 px-2 text-xs text-slate-500 dark:text-slate-400",
+                "Agents beyond these limits wait in a queue for the profile in use."
+            }
+            button {
+                r#type: "button",
+                class: "mt-4 block w-full rounded-lg bg-slate-200 p-2.5 text-xs font-semibold hover:bg-blue-600 hover:text-slate-200 focus:outline-none focus:ring-2 focus:ring-blue-600 dark:bg-slate-800 dark:hover:bg-blue-600",
+                onclick: |_| match limits.read().clone() {
+                    (Some(name), limits) => setting_event_handler.send(SettingEvent::SaveRateLimits(name, limits)),
+                    (None, _) => log::error!("No profile in use to save rate limits to"),
+                },
+                "Save Rate Limits"
+            }
+        }
+    }
+}
+
+fn SummarizerConfigs(cx: Scope) -> Element {
+    const LABEL_STYLE: &str = "mb-2 mt-4 block px-2 text-sm font-medium";
+    const INPUT_STYLE: &str = "block w-full rounded-lg bg-slate-200 p-2.5 text-xs focus:outline-none focus:ring-2 focus:ring-blue-600 dark:bg-slate-800 dark:placeholder-slate-400 dark:focus:ring-blue-600";
+    let setting_event_handler = use_coroutine_handle::<SettingEvent>(cx).unwrap();
+    let saved_summarizer = use_shared_state::<StoredStates>(cx).unwrap().read().summarizer.clone();
+    let summarizer = use_ref(cx, || saved_summarizer.clone());
+    let enabled = saved_summarizer.enabled;
+    let provider = if saved_summarizer.provider.is_default() {
+        String::new()
+    } else {
+        saved_summarizer.provider.to_string()
+    };
+    let threshold_percentage = saved_summarizer.threshold_percentage;
+    let keep_recent_turns = saved_summarizer.keep_recent_turns;
+    render! {
+        div {
+            class: "my-4 border-t border-slate-300 px-2 py-4 text-slate-800 dark:border-slate-700 dark:text-slate-200",
+            label {
+                class: "px-2 text-xs uppercase text-slate-500 dark:text-slate-400",
+                "Summaries"
+            }
+            label {
+                class: "mt-4 flex cursor-pointer items-center justify-between px-2",
+                span {
+                    class: "text-sm font-medium",
+                    "Summarize long conversations"
+                }
+                input {
+                    r#type: "checkbox",
+                    checked: enabled,
+                    onchange: |c| summarizer.write().enabled = c.data.value.parse().unwrap_or_default(),
+                }
+            }
+            label {
+                r#for: "summarizer-provider",
+                class: "{LABEL_STYLE}",
+                "Summarizer"
+            }
+            input {
+                r#type: "text",
+                id: "summarizer-provider",
+                class: "{INPUT_STYLE}",
+                placeholder: "profile:model, the profile in use if empty",
+                value: "{provider}",
+                onchange: |c| summarizer.write().provider = AgentProvider::parse(&c.data.value),
+            }
+            label {
+                r#for: "summary-threshold",
+                class: "{LABEL_STYLE}",
+                "Summarize when the context is fuller than (%)"
+            }
+            input {
+                r#type: "number",
+                id: "summary-threshold",
+                class: "{INPUT_STYLE}",
+                min: "1",
+                max: "100",
+                value: "{threshold_percentage}",
+                onchange: |c| {
+                    let value = c.data.value.trim();
+                    match value.parse::<usize>() {
+                        Ok(threshold) => summarizer.write().threshold_percentage = threshold.clamp(1, 100),
+                        Err(e) => log::error!("Invalid threshold {}: {}", value, e),
+                    }
+                },
+            }
+            label {
+                r#for: "summary-keep-recent",
+                class: "{LABEL_STYLE}",
+                "Recent turns left as they are"
+            }
+            input {
+                r#type: "number",
+                id: "summary-keep-recent",
+                class: "{INPUT_STYLE}",
+                min: "0",
+                value: "{keep_recent_turns}",
+                onchange: |c| {
+                    let value = c.data.value.trim();
+                    match value.parse::<usize>() {
+                        Ok(turns) => summarizer.write().keep_recent_turns = turns,
+                        Err(e) => log::error!("Invalid number of turns {}: {}", value, e),
+                    }
+                },
+            }
+            button {
+                r#type: "button",
+                class: "mt-4 block w-full rounded-lg bg-slate-200 p-2.5 text-xs font-semibold hover:bg-blue-600 hover:text-slate-200 focus:outline-none focus:ring-2 focus:ring-blue-600 dark:bg-slate-800 dark:hover:bg-blue-600",
+                onclick: |_| setting_event_handler.send(SettingEvent::SaveSummarizer(summarizer.read().clone())),
+                "Save Summaries"
+            }
+        }
+    }
+}
+
+fn SpendingCapSettings(cx: Scope) -> Element {
+    const LABEL_STYLE: &str = "mb-2 mt-4 block px-2 text-sm font-medium";
+    const INPUT_STYLE: &str = "block w-full rounded-lg bg-slate-200 p-2.5 text-xs focus:outline-none focus:ring-2 focus:ring-blue-600 dark:bg-slate-800 dark:placeholder-slate-400 dark:focus:ring-blue-600";
+    let setting_event_handler = use_coroutine_handle::<SettingEvent>(cx).unwrap();
+    let saved_caps = use_shared_state::<StoredStates>(cx).unwrap().read().spending_caps;
+    let caps = use_ref(cx, || saved_caps);
+    let per_chat = saved_caps.per_chat.map(|c| c.to_string()).unwrap_or_default();
+    let monthly = saved_caps.monthly.map(|c| c.to_string()).unwrap_or_default();
+    // empty means no cap
+    let parse_cap = |value: &str| -> Result<Option<f64>, ()> {
+        let value = value.trim();
+        if value.is_empty() {
+            return Ok(None);
+        }
+        value
+            .parse::<f64>()
+            .map(Some)
+            .map_err(|e| log::error!("Invalid spending cap {}: {}", value, e))
+    };
+    render! {
+        div {
+            class: "my-4 border-t border-slate-300 px-2 py-4 text-slate-800 dark:border-slate-700 dark:text-slate-200",
+            label {
+                class: "px-2 text-xs uppercase text-slate-500 dark:text-slate-400",
+                "Spending Caps"
+            }
+            label {
+                r#for: "chat-spending-cap",
+                class: "{LABEL_STYLE}",
+                "Per chat (USD)"
+            }
+            input {
+                r#type: "number",
+                id: "chat-spending-cap",
+                class: "{INPUT_STYLE}",
+                placeholder: "No cap",
+                step: "0.01",
+                value: "{per_chat}",
+                onchange: move |c| {
+                    if let Ok(per_chat) = parse_cap(&c.data.value) {
+                        caps.write().per_chat = per_chat;
+                    }
+                },
+            }
+            label {
+                r#for: "monthly-spending-cap",
+                class: "{LABEL_STYLE}",
+                "Per month (USD)"
+            }
+            input {
+                r#type: "number",
+                id: "monthly-spending-cap",
+                class: "{INPUT_STYLE}",
+                placeholder: "No cap",
+                step: "0.01",
+                value: "{monthly}",
+                onchange: move |c| {
+                    if let Ok(monthly) = parse_cap(&c.data.value) {
+                        caps.write().monthly = monthly;
+                    }
+                },
+            }
+            p {
+                class: "mt-2 px-2 text-xs text-slate-500 dark:text-slate-400",
+                "Costs are estimated from the token counts and the prices of known models."
+            }
+            button {
+                r#type: "button",
+                class: "mt-4 block w-full rounded-lg bg-slate-200 p-2.5 text-xs font-semibold hover:bg-blue-600 hover:text-slate-200 focus:outline-none focus:ring-2 focus:ring-blue-600 dark:bg-slate-800 dark:hover:bg-blue-600",
+                onclick: |_| setting_event_handler.send(SettingEvent::SaveSpendingCaps(*caps.read())),
+                "Save Spending Caps"
+            }
+        }
+    }
+}
@@ -0,0 +1,184 @@
+use dioxus::prelude::*;
+
+use crate::pages::app::{AuthedClient, ChatId, StreamingReply};
+use crate::utils::auth::Auth;
+use crate::utils::data_url;
+use crate::utils::storage::StoredStates;
+use crate::utils::storage::backup::{ConflictPolicy, ImportMode};
+
+pub fn BackupSettings(cx: Scope) -> Element {
+    const LABEL_STYLE: &str = "mb-2 mt-4 block px-2 text-sm font-medium";
+    const SELECT_STYLE: &str = "block w-full cursor-pointer rounded-lg border-r-4 border-transparent bg-slate-200 py-3 pl-1 text-xs focus:outline-none focus:ring-2 focus:ring-blue-600 disabled:cursor-not-allowed disabled:opacity-50 dark:bg-slate-800 dark:focus:ring-blue-600";
+    const BUTTON_STYLE: &str = "mt-4 block w-full rounded-lg bg-slate-200 p-2.5 text-center text-xs font-semibold hover:bg-blue-600 hover:text-slate-200 focus:outline-none focus:ring-2 focus:ring-blue-600 dark:bg-slate-800 dark:hover:bg-blue-600";
+    let global = use_shared_state::<StoredStates>(cx).unwrap();
+    let authed_client = use_shared_state::<AuthedClient>(cx).unwrap();
+    let chat_id = use_shared_state::<ChatId>(cx).unwrap();
+    let streaming_reply = use_shared_state::<StreamingReply>(cx).unwrap();
+    let include_secrets = use_state(cx, || false);
+    let export_url = use_state(cx, || None::<String>);
+    let import_mode = use_state(cx, ImportMode::default);
+    let conflict_policy = use_state(cx, ConflictPolicy::default);
+    let status = use_state(cx, || None::<String>);
+    let file_name = format!("chitchai_backup_{}.json", chrono::Local::now().format("%Y%m%d"));
+    render! {
+        div {
+            class: "my-4 border-t border-slate-300 px-2 py-4 text-slate-800 dark:border-slate-700 dark:text-slate-200",
+            label {
+                class: "px-2 text-xs uppercase text-slate-500 dark:text-slate-400",
+                "Backup"
+            }
+            label {
+                class: "mt-4 flex cursor-pointer items-center justify-between px-2",
+                span {
+                    class: "text-sm font-medium",
+                    "Include API keys"
+                }
+                input {
+                    r#type: "checkbox",
+                    checked: *include_secrets.get(),
+                    onchange: |c| {
+                        include_secrets.set(c.data.value.parse().unwrap_or_default());
+                        export_url.set(None);
+                    },
+                }
+            }
+            match export_url.get() {
+                // a data URL is only downloaded on a click on a link to it
+                Some(url) => rsx! {
+                    a {
+                        class: "{BUTTON_STYLE}",
+                        href: "{url}",
+                        download: "{file_name}",
+                        onclick: |_| export_url.set(None),
+                        "Download {file_name}"
+                    }
+                },
+                None => rsx! {
+                    button {
+                        r#type: "button",
+                        class: "{BUTTON_STYLE}",
+                        onclick: |_| match global.read().export_backup(*include_secrets.get()) {
+                            Ok(backup) => export_url.set(Some(data_url("application/json", &backup))),
+                            Err(e) => {
+                                log::error!("Error when exporting a backup: {}", e);
+                                status.set(Some(format!("Cannot export a backup: {}", e)));
+                            }
+                        },
+                        "Export Backup"
+                    }
+                },
+            }
+            label {
+                r#for: "import-mode",
+                class: "{LABEL_STYLE}",
+                "Import"
+            }
+            select {
+                id: "import-mode",
+                class: "{SELECT_STYLE}",
+                onchange: |c| import_mode.set(match c.data.value.as_str() {
+                    "replace" => ImportMode::Replace,
+                    _ => ImportMode::Merge,
+                }),
+                option {
+                    value: "merge",
+                    selected: *import_mode.get() == ImportMode::Merge,
+                    "Merge into the chats and agents in use"
+                }
+                option {
+                    value: "replace",
+                    selected: *import_mode.get() == ImportMode::Replace,
+                    "Replace everything"
+                }
+            }
+            label {
+                r#for: "import-conflicts",
+                class: "{LABEL_STYLE}",
+                "Chats and agents that exist already"
+            }
+            select {
+                id: "import-conflicts",
+                class: "{SELECT_STYLE}",
+                disabled: *import_mode.get() == ImportMode::Replace,
+                onchange: |c| conflict_policy.set(match c.data.value.as_str() {
+                    "overwrite" => ConflictPolicy::Overwrite,
+                    "skip" => ConflictPolicy::Skip,
+                    _ => ConflictPolicy::KeepBoth,
+                }),
+                option {
+                    value: "keep-both",
+                    selected: *conflict_policy.get() == ConflictPolicy::KeepBoth,
+                    "Keep both"
+                }
+                option {
+                    value: "overwrite",
+                    selected: *conflict_policy.get() == ConflictPolicy::Overwrite,
+                    "Overwrite with the backup"
+                }
+                option {
+                    value: "skip",
+                    selected: *conflict_policy.get() == ConflictPolicy::Skip,
+                    "Keep those in use"
+                }
+            }
+            input {
+                r#type: "file",
+                accept: ".json,application/json",
+                class: "mt-4 block w-full text-xs file:mr-2 file:rounded-lg file:border-0 file:bg-slate-200 file:p-2.5 file:text-xs file:font-semibold hover:file:bg-blue-600 hover:file:text-slate-200 dark:file:bg-slate-800 dark:file:text-slate-200",
+                onchange: move |evt| {
+                    let Some(file_engine) = evt.files.clone() else {
+                        return;
+                    };
+                    let Some(file) = file_engine.files().into_iter().next() else {
+                        return;
+                    };
+                    let global = global.to_owned();
+                    let authed_client = authed_client.to_owned();
+                    let chat_id = chat_id.to_owned();
+                    let streaming_reply = streaming_reply.to_owned();
+                    let status = status.to_owned();
+                    let mode = *import_mode.get();
+                    let policy = *conflict_policy.get();
+                    cx.spawn(async move {
+                        let Some(raw) = file_engine.read_file_to_string(&file).await else {
+                            status.set(Some(format!("Cannot read {}", file)));
+                            return;
+                        };
+                        if streaming_reply.read().0 {
+                            status.set(Some("Wait for the reply to finish before importing".to_string()));
+                            return;
+                        }
+                        let mut global = global.write();
+                        match global.import_backup(&raw, mode, policy) {
+                            Ok(summary) => {
+                                // the chat on display may be gone after replacing everything
+                                if global.chats.iter().all(|chat| chat.id != chat_id.read().0) {
+                                    chat_id.write().0 = global.chats.last().unwrap().id;
+                                }
+                                *authed_client.write() = global.active_auth().map(Auth::build_client);
+                                global.save();
+                                status.set(Some(summary.to_string()));
+                            }
+                            Err(e) => {
+                                log::error!("Error when importing {}: {}", file, e);
+                                status.set(Some(format!("Cannot import {}: {}", file, e)));
+                            }
+                        }
+                    });
+                },
+            }
+            if let Some(status) = status.get() {
+                rsx! {
+                    p {
+                        class: "mt-2 px-2 text-xs text-slate-500 dark:text-slate-400",
+                        "{status}"
+                    }
+                }
+            }
+            p {
+                class: "mt-2 px-2 text-xs text-slate-500 dark:text-slate-400",
+                "Backups without API keys keep the keys in use for profiles of the same name."
+            }
+        }
+    }
+}
@@ -0,0 +1,184 @@
+use dioxus::prelude::*;
+
+use crate::agents::{AgentName, AgentType};
+use crate::utils::conversation_import::{BranchSelection, ExternalConversation, parse_conversations};
+use crate::utils::storage::StoredStates;
+
+pub fn ConversationImport(cx: Scope) -> Element {
+    const LABEL_STYLE: &str = "mb-2 mt-4 block px-2 text-sm font-medium";
+    const SELECT_STYLE: &str = "block w-full cursor-pointer rounded-lg border-r-4 border-transparent bg-slate-200 py-3 pl-1 text-xs focus:outline-none focus:ring-2 focus:ring-blue-600 dark:bg-slate-800 dark:focus:ring-blue-600";
+    const BUTTON_STYLE: &str = "mt-4 block w-full rounded-lg bg-slate-200 p-2.5 text-xs font-semibold hover:bg-blue-600 hover:text-slate-200 focus:outline-none focus:ring-2 focus:ring-blue-600 dark:bg-slate-800 dark:hover:bg-blue-600";
+    let global = use_shared_state::<StoredStates>(cx).unwrap();
+    let mut assistant_names: Vec<AgentName> = global
+        .read()
+        .name_to_configs
+        .values()
+        .filter(|config| matches!(config.agent_type, AgentType::Assistant { .. }))
+        .map(|config| config.name.clone())
+        .collect();
+    assistant_names.sort_by_key(|name| name.to_string());
+    // conversations read from a file, which are previewed before they are imported
+    let preview = use_state(cx, || None::<Vec<ExternalConversation>>);
+    let assistant = use_state(cx, || assistant_names.first().cloned());
+    let selection = use_state(cx, BranchSelection::default);
+    let status = use_state(cx, || None::<String>);
+    render! {
+        div {
+            class: "my-4 border-t border-slate-300 px-2 py-4 text-slate-800 dark:border-slate-700 dark:text-slate-200",
+            label {
+                class: "px-2 text-xs uppercase text-slate-500 dark:text-slate-400",
+                "Import Conversations"
+            }
+            p {
+                class: "mt-2 px-2 text-xs text-slate-500 dark:text-slate-400",
+                "Pick conversations.json from a data export of ChatGPT."
+            }
+            input {
+                r#type: "file",
+                accept: ".json,application/json",
+                class: "mt-4 block w-full text-xs file:mr-2 file:rounded-lg file:border-0 file:bg-slate-200 file:p-2.5 file:text-xs file:font-semibold hover:file:bg-blue-600 hover:file:text-slate-200 dark:file:bg-slate-800 dark:file:text-slate-200",
+                onchange: move |evt| {
+                    let Some(file_engine) = evt.files.clone() else {
+                        return;
+                    };
+                    let Some(file) = file_engine.files().into_iter().next() else {
+                        return;
+                    };
+                    let preview = preview.to_owned();
+                    let status = status.to_owned();
+                    cx.spawn(async move {
+                        let Some(raw) = file_engine.read_file_to_string(&file).await else {
+                            status.set(Some(format!("Cannot read {}", file)));
+                            return;
+                        };
+                        match parse_conversations(&raw) {
+                            Ok(conversations) => {
+                                status.set(None);
+                                preview.set(Some(conversations));
+                            }
+                            Err(e) => {
+                                log::error!("Error when reading conversations from {}: {}", file, e);
+                                status.set(Some(format!("Cannot read conversations from {}: {}", file, e)));
+                                preview.set(None);
+                            }
+                        }
+                    });
+                },
+            }
+            if let Some(conversations) = preview.get() {
+                let branched = conversations.iter().filter(|c| c.branches.len() > 1).count();
+                rsx! {
+                    p {
+                        class: "mt-4 px-2 text-xs",
+                        "{conversations.len()} conversations, {branched} of them with branches"
+                    }
+                    ul {
+                        class: "mt-2 max-h-48 overflow-y-auto rounded-lg bg-slate-200 p-2 text-xs dark:bg-slate-800",
+                        conversations.iter().map(|conversation| {
+                            let branches = conversation.branches.len();
+                            rsx! {
+                                li {
+                                    class: "flex justify-between gap-2",
+                                    span {
+                                        class: "truncate",
+                                        "{conversation.title}"
+                                    }
+                                    span {
+                                        class: "shrink-0 text-slate-500 dark:text-slate-400",
+                                        "{conversation.message_count()} messages"
+                                        if branches > 1 {
+                                            rsx! { ", {branches} branches" }
+                                        }
+                                    }
+                                }
+                            }
+                        })
+                    }
+                    label {
+                        r#for: "import-assistant",
+                        class: "{LABEL_STYLE}",
+                        "Replies are from"
+                    }
+                    select {
+                        id: "import-assistant",
+                        class: "{SELECT_STYLE}",
+                        // options are keyed by the names as they are stored, which tell the default assistant apart
+                        onchange: |c| assistant.set(Some(AgentName::from(c.data.value.clone()))),
+                        assistant_names.iter().map(|name| {
+                            let key: String = name.clone().into();
+                            rsx! {
+                                option {
+                                    value: "{key}",
+                                    selected: Some(name) == assistant.get().as_ref(),
+                                    "{name}"
+                                }
+                            }
+                        })
+                    }
+                    label {
+                        r#for: "import-branches",
+                        class: "{LABEL_STYLE}",
+                        "Branches"
+                    }
+                    select {
+                        id: "import-branches",
+                        class: "{SELECT_STYLE}",
+                        onchange: |c| selection.set(match c.data.value.as_str() {
+                            "all" => BranchSelection::All,
+                            _ => BranchSelection::Current,
+                        }),
+                        option {
+                            value: "current",
+                            selected: *selection.get() == BranchSelection::Current,
+                            "Only the branch last seen"
+                        }
+                        option {
+                            value: "all",
+                            selected: *selection.get() == BranchSelection::All,
+                            "Every branch as a chat"
+                        }
+                    }
+                    button {
+                        r#type: "button",
+                        class: "{BUTTON_STYLE}",
+                        onclick: move |_| {
+                            let Some(config) = assistant.get()
+                                .as_ref()
+                                .and_then(|name| global.read().name_to_configs.get(name).cloned()) else {
+                                status.set(Some("Pick an agent for the replies".to_string()));
+                                return;
+                            };
+                            let Some(conversations) = preview.get().clone() else {
+                                return;
+                            };
+                            let chats: Vec<_> = conversations
+                                .into_iter()
+                                .flat_map(|conversation| conversation.into_chats(&config, *selection.get()))
+                                .collect();
+                            let mut global = global.write();
+                            status.set(Some(format!("Imported {} chats replied by {}", chats.len(), config.name)));
+                            global.chats.extend(chats);
+                            global.save();
+                            preview.set(None);
+                        },
+                        "Import"
+                    }
+                    button {
+                        r#type: "button",
+                        class: "{BUTTON_STYLE}",
+                        onclick: |_| preview.set(None),
+                        "Cancel"
+                    }
+                }
+            }
+            if let Some(status) = status.get() {
+                rsx! {
+                    p {
+                        class: "mt-2 px-2 text-xs text-slate-500 dark:text-slate-400",
+                        "{status}"
+                    }
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,89 @@
+use dioxus::prelude::*;
+
+use crate::pages::app::{SyncNow, SyncStatus};
+use crate::utils::storage::StoredStates;
+
+pub fn SyncServerSettings(cx: Scope) -> Element {
+    const LABEL_STYLE: &str = "mb-2 mt-4 block px-2 text-sm font-medium";
+    const INPUT_STYLE: &str = "block w-full rounded-lg bg-slate-200 p-2.5 text-xs focus:outline-none focus:ring-2 focus:ring-blue-600 dark:bg-slate-800 dark:placeholder-slate-400 dark:focus:ring-blue-600";
+    const BUTTON_STYLE: &str = "mt-4 block w-full rounded-lg bg-slate-200 p-2.5 text-xs font-semibold hover:bg-blue-600 hover:text-slate-200 focus:outline-none focus:ring-2 focus:ring-blue-600 dark:bg-slate-800 dark:hover:bg-blue-600";
+    let global = use_shared_state::<StoredStates>(cx).unwrap();
+    let sync_status = use_shared_state::<SyncStatus>(cx).unwrap();
+    let sync_now = use_coroutine_handle::<SyncNow>(cx).unwrap();
+    let server_url = use_state(cx, || global.read().sync.server_url.clone());
+    let token = use_state(cx, || global.read().sync.token.clone());
+    let enabled = global.read().sync.is_enabled();
+    let status = sync_status.read().0.clone();
+    render! {
+        div {
+            class: "my-4 border-t border-slate-300 px-2 py-4 text-slate-800 dark:border-slate-700 dark:text-slate-200",
+            label {
+                class: "px-2 text-xs uppercase text-slate-500 dark:text-slate-400",
+                "Sync Server"
+            }
+            p {
+                class: "mt-2 px-2 text-xs text-slate-500 dark:text-slate-400",
+                "Chats and agents are synced with a chitchai-sync server across devices, the latest change of each winning."
+            }
+            label {
+                r#for: "sync-server-url",
+                class: "{LABEL_STYLE}",
+                "Server URL"
+            }
+            input {
+                r#type: "url",
+                id: "sync-server-url",
+                class: "{INPUT_STYLE}",
+                placeholder: "http://localhost:8787",
+                value: "{server_url}",
+                oninput: |c| server_url.set(c.data.value.clone()),
+            }
+            label {
+                r#for: "sync-token",
+                class: "{LABEL_STYLE}",
+                "Token"
+            }
+            input {
+                r#type: "password",
+                id: "sync-token",
+                class: "{INPUT_STYLE}",
+                autocomplete: "off",
+                placeholder: "If the server is started with one",
+                value: "{token}",
+                oninput: |c| token.set(c.data.value.clone()),
+            }
+            button {
+                r#type: "button",
+                class: "{BUTTON_STYLE}",
+                onclick: move |_| {
+                    let mut global = global.write();
+                    global.sync.set_server(server_url.get().trim().to_string(), token.get().trim().to_string());
+                    global.save();
+                    sync_status.write().0 = None;
+                    if global.sync.is_enabled() {
+                        sync_now.send(SyncNow);
+                    }
+                },
+                "Save"
+            }
+            if enabled {
+                rsx! {
+                    button {
+                        r#type: "button",
+                        class: "{BUTTON_STYLE}",
+                        onclick: move |_| sync_now.send(SyncNow),
+                        "Sync Now"
+                    }
+                }
+            }
+            if let Some(status) = status {
+                rsx! {
+                    p {
+                        class: "mt-2 px-2 text-xs text-slate-500 dark:text-slate-400",
+                        "{status}"
+                    }
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,204 @@
+use dioxus::prelude::*;
+
+use crate::pages::app::AuthedClient;
+use crate::utils::auth::Auth;
+use crate::utils::storage::StoredStates;
+use crate::utils::vault::DEFAULT_AUTO_LOCK_MINUTES;
+
+pub fn VaultSettings(cx: Scope) -> Element {
+    const LABEL_STYLE: &str = "mb-2 mt-4 block px-2 text-sm font-medium";
+    const INPUT_STYLE: &str = "block w-full rounded-lg bg-slate-200 p-2.5 text-xs focus:outline-none focus:ring-2 focus:ring-blue-600 dark:bg-slate-800 dark:placeholder-slate-400 dark:focus:ring-blue-600";
+    const BUTTON_STYLE: &str = "mt-4 block w-full rounded-lg bg-slate-200 p-2.5 text-xs font-semibold hover:bg-blue-600 hover:text-slate-200 focus:outline-none focus:ring-2 focus:ring-blue-600 dark:bg-slate-800 dark:hover:bg-blue-600";
+    let global = use_shared_state::<StoredStates>(cx).unwrap();
+    let authed_client = use_shared_state::<AuthedClient>(cx).unwrap();
+    let passphrase = use_state(cx, String::new);
+    let confirmation = use_state(cx, String::new);
+    let status = use_state(cx, || None::<String>);
+    let (has_vault, locked, auto_lock_minutes) = {
+        let global = global.read();
+        let auto_lock_minutes = global.vault
+            .as_ref()
+            .map(|vault| vault.auto_lock_minutes)
+            .unwrap_or(DEFAULT_AUTO_LOCK_MINUTES);
+        (global.vault.is_some(), global.vault_locked(), auto_lock_minutes)
+    };
+    let auto_lock = use_state(cx, || auto_lock_minutes);
+    let parse_minutes = |value: &str| -> Result<u32, ()> {
+        let value = value.trim();
+        value
+            .parse::<u32>()
+            .map_err(|e| log::error!("Invalid number of minutes {}: {}", value, e))
+    };
+    render! {
+        div {
+            class: "my-4 border-t border-slate-300 px-2 py-4 text-slate-800 dark:border-slate-700 dark:text-slate-200",
+            label {
+                class: "px-2 text-xs uppercase text-slate-500 dark:text-slate-400",
+                "API Key Encryption"
+            }
+            if locked {
+                rsx! {
+                    p {
+                        class: "mt-2 px-2 text-xs text-slate-500 dark:text-slate-400",
+                        "The API keys are locked. Enter the passphrase to use them in this session."
+                    }
+                }
+            } else if has_vault {
+                rsx! {
+                    p {
+                        class: "mt-2 px-2 text-xs text-slate-500 dark:text-slate-400",
+                        "The API keys are stored encrypted and unlocked until this page is closed or locked."
+                    }
+                }
+            } else {
+                rsx! {
+                    p {
+                        class: "mt-2 px-2 text-xs text-slate-500 dark:text-slate-400",
+                        "The API keys are stored in plaintext. Encrypt them with a passphrase that is asked for once a session."
+                    }
+                }
+            }
+            if !has_vault || locked {
+                rsx! {
+                    label {
+                        r#for: "vault-passphrase",
+                        class: "{LABEL_STYLE}",
+                        "Passphrase"
+                    }
+                    input {
+                        r#type: "password",
+                        id: "vault-passphrase",
+                        class: "{INPUT_STYLE}",
+                        autocomplete: "off",
+                        value: "{passphrase}",
+                        oninput: |c| passphrase.set(c.data.value.clone()),
+                    }
+                }
+            }
+            if !has_vault {
+                rsx! {
+                    label {
+                        r#for: "vault-confirmation",
+                        class: "{LABEL_STYLE}",
+                        "Passphrase again"
+                    }
+                    input {
+                        r#type: "password",
+                        id: "vault-confirmation",
+                        class: "{INPUT_STYLE}",
+                        autocomplete: "off",
+                        value: "{confirmation}",
+                        oninput: |c| confirmation.set(c.data.value.clone()),
+                    }
+                }
+            }
+            if !locked {
+                rsx! {
+                    label {
+                        r#for: "vault-auto-lock",
+                        class: "{LABEL_STYLE}",
+                        "Lock after minutes without activity (0 for never)"
+                    }
+                    input {
+                        r#type: "number",
+                        id: "vault-auto-lock",
+                        class: "{INPUT_STYLE}",
+                        min: "0",
+                        value: "{auto_lock}",
+                        onchange: move |c| {
+                            let Ok(minutes) = parse_minutes(&c.data.value) else {
+                                return;
+                            };
+                            auto_lock.set(minutes);
+                            let mut global = global.write();
+                            if let Some(vault) = global.vault.as_mut() {
+                                vault.auto_lock_minutes = minutes;
+                                global.save();
+                            }
+                        },
+                    }
+                }
+            }
+            if !has_vault {
+                rsx! {
+                    button {
+                        r#type: "button",
+                        class: "{BUTTON_STYLE}",
+                        onclick: move |_| {
+                            if passphrase.get() != confirmation.get() {
+                                status.set(Some("The passphrases differ".to_string()));
+                                return;
+                            }
+                            let mut global = global.write();
+                            match global.enable_vault(passphrase.get(), *auto_lock.get()) {
+                                Ok(_) => {
+                                    global.save();
+                                    status.set(Some("The API keys are encrypted".to_string()));
+                                }
+                                Err(e) => status.set(Some(e)),
+                            }
+                            passphrase.set(String::new());
+                            confirmation.set(String::new());
+                        },
+                        "Encrypt API Keys"
+                    }
+                }
+            } else if locked {
+                rsx! {
+                    button {
+                        r#type: "button",
+                        class: "{BUTTON_STYLE}",
+                        onclick: move |_| {
+                            let mut global = global.write();
+                            match global.unlock_vault(passphrase.get()) {
+                                Ok(_) => {
+                                    *authed_client.write() = global.active_auth().map(Auth::build_client);
+                                    status.set(None);
+                                }
+                                Err(e) => status.set(Some(e)),
+                            }
+                            passphrase.set(String::new());
+                        },
+                        "Unlock"
+                    }
+                }
+            } else {
+                rsx! {
+                    button {
+                        r#type: "button",
+                        class: "{BUTTON_STYLE}",
+                        onclick: move |_| {
+                            global.write().lock_vault();
+                            *authed_client.write() = None;
+                            status.set(None);
+                        },
+                        "Lock Now"
+                    }
+                    button {
+                        r#type: "button",
+                        class: "{BUTTON_STYLE}",
+                        onclick: move |_| {
+                            let mut global = global.write();
+                            match global.disable_vault() {
+                                Ok(_) => {
+                                    global.save();
+                                    status.set(Some("The API keys are stored in plaintext".to_string()));
+                                }
+                                Err(e) => status.set(Some(e)),
+                            }
+                        },
+                        "Stop Encrypting"
+                    }
+                }
+            }
+            if let Some(status) = status.get() {
+                rsx! {
+                    p {
+                        class: "mt-2 px-2 text-xs text-slate-500 dark:text-slate-400",
+                        "{status}"
+                    }
+                }
+            }
+        }
+    }
+}
@@ -1,12 +1,24 @@
+use std::collections::HashSet;
+use std::time::Duration;
+
+use async_std::task::sleep;
+use chrono::Utc;
 use dioxus::prelude::*;
+use futures::future::{Either, select};
 use futures_util::StreamExt;
 use transprompt::async_openai::Client;
-use transprompt::async_openai::config::{AzureConfig, OpenAIConfig};
 use uuid::Uuid;
 
 use crate::components::{ChatContainer, LeftSidebar, SettingSidebar};
 use crate::utils::auth::Auth;
+use crate::utils::datetime::DatetimeString;
+use crate::utils::scheduler::Scheduler;
 use crate::utils::storage::StoredStates;
+use crate::utils::storage::backend::StorageHandle;
+use crate::utils::storage::reload::ChangedRecords;
+use crate::utils::streaming::StreamingReplies;
+use crate::utils::sync::push_and_pull;
+use crate::utils::tabs::{CLAIM_HEARTBEAT, ChatLocks, TabChannel, TabMessage};
 
 
 
@@ -17,15 +29,37 @@ pub struct ChatId(pub Uuid);
 
 pub struct StreamingReply(pub bool);
 
+/// How the last sync with the sync server went
+pub struct SyncStatus(pub Option<String>);
+
+/// Sync with the sync server right away
+pub struct SyncNow;
+
+/// how often to check whether the API keys should be locked for inactivity
+const AUTO_LOCK_CHECK_INTERVAL: Duration = Duration::from_secs(30);
+/// how often to sync with the sync server
+const SYNC_INTERVAL: Duration = Duration::from_secs(60);
+
 pub fn Main(cx: Scope) -> Element {
-    let mut stored_states = StoredStates::get_or_init();
-    stored_states.run_count += 1;
-    stored_states.save();
-    log::info!("This is your {} time running ChitChai!", stored_states.run_count);
-    render! {
-        App {
-            stored_states: stored_states
-        }
+    let stored_states = use_future(cx, (), |_| async {
+        let mut stored_states = StoredStates::get_or_init(StorageHandle::select().await).await;
+        stored_states.run_count += 1;
+        stored_states.save();
+        log::info!("This is your {} time running ChitChai!", stored_states.run_count);
+        stored_states
+    });
+    match stored_states.value() {
+        Some(stored_states) => render! {
+            App {
+                stored_states: stored_states.clone()
+            }
+        },
+        None => render! {
+            div {
+                class: "flex h-full w-full items-center justify-center text-slate-500",
+                "Loading..."
+            }
+        },
     }
 }
 
@@ -45,25 +79,142 @@ pub fn App(cx: Scope<AppProps>) -> Element {
     let stored_states = cx.props.stored_states.clone();
     let last_chat_id = stored_states.chats.last().unwrap().id;
     let authed_client: AuthedClient = stored_states
-        .auth
-        .as_ref()
-        .map(|auth| {
-            match auth {
-                Auth::OpenAI { .. } => Client::with_config::<OpenAIConfig>(auth.clone().into()),
-                Auth::AzureOpenAI { .. } => Client::with_config::<AzureConfig>(auth.clone().into()),
-                _ => unreachable!(),
-            }
-        });
-    let hide_settings_sidebar = stored_states.auth.is_some() && stored_states.selected_service.is_some();
+        .active_auth()
+        .map(Auth::build_client);
+    let hide_settings_sidebar = authed_client.is_some();
+    let chat_locks = ChatLocks::new(stored_states.storage.tab_channel().cloned());
     // configure share states
     use_shared_state_provider(cx, || stored_states);
     use_shared_state_provider(cx, || authed_client);
     use_shared_state_provider(cx, || ChatId(last_chat_id));
     use_shared_state_provider(cx, || StreamingReply(false));
+    use_shared_state_provider(cx, Scheduler::default);
+    use_shared_state_provider(cx, StreamingReplies::default);
+    use_shared_state_provider(cx, || chat_locks);
+    use_shared_state_provider(cx, || SyncStatus(None));
     let global = use_shared_state::<StoredStates>(cx).unwrap();
     let chat_id = use_shared_state::<ChatId>(cx).unwrap();
+    let authed_client = use_shared_state::<AuthedClient>(cx).unwrap();
+    let chat_locks = use_shared_state::<ChatLocks>(cx).unwrap();
+    let sync_status = use_shared_state::<SyncStatus>(cx).unwrap();
     // configure local states
     let hide_setting_sidebar = use_state(cx, || hide_settings_sidebar);
+    let last_active = use_ref(cx, || Utc::now().timestamp_millis());
+    // lock the API keys after a while without activity
+    use_coroutine(cx, |_: UnboundedReceiver<()>| {
+        let global = global.to_owned();
+        let authed_client = authed_client.to_owned();
+        let hide_setting_sidebar = hide_setting_sidebar.to_owned();
+        let last_active = last_active.to_owned();
+        async move {
+            loop {
+                sleep(AUTO_LOCK_CHECK_INTERVAL).await;
+                let auto_lock_minutes = match global.read().vault.as_ref() {
+                    Some(vault) if !vault.is_locked() && vault.auto_lock_minutes > 0 => vault.auto_lock_minutes,
+                    _ => continue,
+                };
+                let idle_ms = Utc::now().timestamp_millis() - *last_active.read();
+                if idle_ms >= i64::from(auto_lock_minutes) * 60_000 {
+                    global.write().lock_vault();
+                    *authed_client.write() = None;
+                    // where the keys are unlocked again
+                    hide_setting_sidebar.set(false);
+                    log::info!("Locked the API keys after {} minutes without activity", auto_lock_minutes);
+                }
+            }
+        }
+    });
+    // keep up with what other tabs save and which chats they are replying in
+    use_coroutine(cx, |_: UnboundedReceiver<()>| {
+        let global = global.to_owned();
+        let authed_client = authed_client.to_owned();
+        let chat_id = chat_id.to_owned();
+        let chat_locks = chat_locks.to_owned();
+        async move {
+            let Some(mut messages) = global.read().storage.tab_channel().map(TabChannel::listen) else {
+                return;
+            };
+            let mut pending: HashSet<(String, String)> = HashSet::new();
+            let mut heartbeat_at = Utc::now().timestamp_millis();
+            loop {
+                match select(messages.next(), Box::pin(sleep(CLAIM_HEARTBEAT))).await {
+                    Either::Left((Some((_, TabMessage::Saved { records })), _)) => pending.extend(records),
+                    Either::Left((Some((tab, message)), _)) => {
+                        // claiming a held chat again changes nothing to show
+                        if chat_locks.read().is_changed_by(tab, &message) {
+                            chat_locks.write().receive(tab, &message);
+                        } else {
+                            chat_locks.write_silent().receive(tab, &message);
+                        }
+                    }
+                    Either::Left((None, _)) => break,
+                    Either::Right(_) => {}
+                }
+                let now = Utc::now().timestamp_millis();
+                if now - heartbeat_at >= CLAIM_HEARTBEAT.as_millis() as i64 {
+                    heartbeat_at = now;
+                    chat_locks.read().heartbeat();
+                    if chat_locks.read().has_expired() {
+                        chat_locks.write().remove_expired();
+                    }
+                }
+                if pending.is_empty() {
+                    continue;
+                }
+                let records: Vec<(String, String)> = pending.drain().collect();
+                let storage = global.read().storage.clone();
+                let changed = match ChangedRecords::load(&storage, records).await {
+                    Ok(changed) => changed,
+                    Err(e) => {
+                        log::error!("Error when loading what another tab saved: {}", e);
+                        continue;
+                    }
+                };
+                let replying_in = chat_locks.read().held().clone();
+                let mut global = global.write();
+                match global.reload(changed, &replying_in) {
+                    Ok(settings_reloaded) => {
+                        if global.chats.iter().all(|chat| chat.id != chat_id.read().0) {
+                            chat_id.write().0 = global.chats.last().unwrap().id;
+                        }
+                        if settings_reloaded {
+                            *authed_client.write() = global.active_auth().map(Auth::build_client);
+                        }
+                    }
+                    Err(e) => log::error!("Error when reloading what another tab saved: {}", e),
+                }
+            }
+        }
+    });
+    // sync chats and agents with the sync server, if there is one, every while and when asked to
+    use_coroutine(cx, |mut rx: UnboundedReceiver<SyncNow>| {
+        let global = global.to_owned();
+        let chat_id = chat_id.to_owned();
+        let chat_locks = chat_locks.to_owned();
+        let sync_status = sync_status.to_owned();
+        async move {
+            loop {
+                if let Either::Left((None, _)) = select(rx.next(), Box::pin(sleep(SYNC_INTERVAL))).await {
+                    break;
+                }
+                if !global.read().sync.is_enabled() {
+                    continue;
+                }
+                let status = match sync_with_server(&global, &chat_locks).await {
+                    Ok(pulled) => format!("Synced at {}, {} changes pulled", DatetimeString::get_now().formatted(), pulled),
+                    Err(e) => {
+                        log::error!("Error when syncing: {}", e);
+                        format!("Not synced: {}", e)
+                    }
+                };
+                let global = global.read();
+                if global.chats.iter().all(|chat| chat.id != chat_id.read().0) {
+                    chat_id.write().0 = global.chats.last().unwrap().id;
+                }
+                sync_status.write().0 = Some(status);
+            }
+        }
+    });
     // configure event handler
     use_coroutine(cx, |mut rx| {
         let hide_setting_sidebar = hide_setting_sidebar.to_owned();
@@ -81,9 +232,12 @@ pub fn App(cx: Scope<AppProps>) -> Element {
     render! {
         div {
             class: "flex h-full w-full",
+            onmousemove: |_| *last_active.write_silent() = Utc::now().timestamp_millis(),
+            onkeydown: |_| *last_active.write_silent() = Utc::now().timestamp_millis(),
             LeftSidebar {}
             div {
                 class: "flex-grow overflow-auto",
+                StorageProblem {}
                 ChatContainer {}
             }
             div {
@@ -93,4 +247,42 @@ pub fn App(cx: Scope<AppProps>) -> Element {
             }
         }
     }
-}
\ No newline at end of file
+}
+
+/// A banner that stays while changes cannot be saved
+fn StorageProblem(cx: Scope) -> Element {
+    let storage = use_shared_state::<StoredStates>(cx).unwrap().read().storage.clone();
+    let storage = use_ref(cx, || {
+        storage.on_problem(cx.schedule_update());
+        storage
+    });
+    let problem = storage.read().problem()?;
+    render! {
+        p {
+            class: "bg-red-600 px-4 py-2 text-center text-xs text-slate-200",
+            "{problem}. Export a backup in the settings panel to keep your chats."
+        }
+    }
+}
+
+/// Push what changed here to the sync server and pull what changed elsewhere, returning how many items were pulled.
+///
+/// The first sync only pulls before it pushes, so that the defaults of a new device do not override agents customized
+/// on others.
+async fn sync_with_server(global: &UseSharedState<StoredStates>,
+                          chat_locks: &UseSharedState<ChatLocks>) -> Result<usize, String> {
+    let rounds: &[bool] = if global.read().sync.revision == 0 { &[true, false] } else { &[false] };
+    let mut pulled = 0;
+    for pull_only in rounds {
+        let (settings, pending) = {
+            let global = global.read();
+            (global.sync.clone(), global.sync_request(*pull_only)?)
+        };
+        let response = push_and_pull(&settings, &pending.request).await?;
+        let mut global = global.write();
+        let replying_in = chat_locks.read().held().clone();
+        pulled += global.apply_sync(pending, response, &replying_in)?;
+        global.save();
+    }
+    Ok(pulled)
+}
@@ -1,29 +1,32 @@
 use serde::Deserialize;
-use transprompt::async_openai::types::{ChatCompletionRequestAssistantMessage, ChatCompletionRequestMessage, ChatCompletionRequestSystemMessage, ChatCompletionRequestUserMessage, ChatCompletionRequestUserMessageContent};
-use transprompt::utils::llm::openai::ChatMsg;
 
 use crate::agents::AgentName;
+use crate::chat::{ChatMessage, Role};
 
 pub mod customization;
 pub mod storage;
 pub mod auth;
 pub mod settings;
 pub mod datetime;
+pub mod models;
+pub mod errors;
+pub mod scheduler;
+pub mod streaming;
+pub mod tokens;
+pub mod usage;
+pub mod transcript;
+pub mod conversation_import;
+pub mod vault;
+pub mod tabs;
+pub mod sync;
 
 pub(crate) const EMPTY: String = String::new();
 
-pub fn sys_msg(string: impl Into<String>) -> ChatMsg {
-    ChatMsg {
-        msg: ChatCompletionRequestMessage::System(ChatCompletionRequestSystemMessage {
-            content: string.into(),
-            role: Default::default(),
-            name: None,
-        }),
-        metadata: None,
-    }
+pub fn sys_msg(string: impl Into<String>) -> ChatMessage {
+    ChatMessage::text_of(Role::System, string.into(), None)
 }
 
-pub fn user_msg(string: impl Into<String>, name: AgentName) -> ChatMsg {
+pub fn user_msg(string: impl Into<String>, name: AgentName) -> ChatMessage {
     let name = match name {
         AgentName::Named(name) => Some(name),
         AgentName::UserDefault => None,
@@ -32,19 +35,10 @@ pub fn user_msg(string: impl Into<String>, name: AgentName) -> ChatMsg {
             panic!()
         }
     };
-    ChatMsg {
-        msg: ChatCompletionRequestMessage::User(
-            ChatCompletionRequestUserMessage {
-                content: ChatCompletionRequestUserMessageContent::Text(string.into()),
-                role: Default::default(),
-                name
-            }
-        ),
-        metadata: None,
-    }
+    ChatMessage::text_of(Role::User, string.into(), name)
 }
 
-pub fn assistant_msg(string: impl Into<String>, name: AgentName) -> ChatMsg {
+pub fn assistant_msg(string: impl Into<String>, name: AgentName) -> ChatMessage {
     let name = match name {
         AgentName::Named(name) => Some(name),
         AgentName::AssistantDefault => None,
@@ -53,24 +47,35 @@ pub fn assistant_msg(string: impl Into<String>, name: AgentName) -> ChatMsg {
             panic!()
         }
     };
-    ChatMsg {
-        msg: ChatCompletionRequestMessage::Assistant(
-            ChatCompletionRequestAssistantMessage{
-                content: Some(string.into()),
-                role: Default::default(),
-                name,
-                tool_calls: None,
-                function_call: None,
-            }
-        ),
-        metadata: None,
+    ChatMessage::text_of(Role::Assistant, string.into(), name)
+}
+
+/// A `data:` URL of some text, which can be the `href` of a download link
+pub fn data_url(mime: &str, content: &str) -> String {
+    let mut url = format!("data:{};charset=utf-8,", mime);
+    for byte in content.bytes() {
+        match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'_' | b'.' | b'~' => url.push(byte as char),
+            _ => url.push_str(&format!("%{:02X}", byte)),
+        }
     }
+    url
 }
 
 #[derive(Clone, Debug, PartialEq, Deserialize)]
 pub struct AgentInstructions {
     pub name: String,
     pub instructions: String,
+    #[serde(default)]
+    pub profile: Option<String>,
+    #[serde(default)]
+    pub model: Option<String>,
+    /// `profile:model` entries to try when the provider of the agent fails
+    #[serde(default)]
+    pub fallbacks: Vec<String>,
+    /// keep only the most recent turns in the context instead of dropping the oldest ones when it overflows
+    #[serde(default)]
+    pub keep_recent_turns: Option<usize>,
 }
 
 #[derive(Clone, Debug, PartialEq, Deserialize)]
@@ -1,5 +1,12 @@
 use serde::{Deserialize, Serialize};
+use transprompt::async_openai::Client;
 use transprompt::async_openai::config::{AzureConfig, OpenAIConfig};
+use transprompt::async_openai::types::CreateChatCompletionRequestArgs;
+
+use crate::agents::AgentName;
+use crate::utils::errors::ProviderError;
+use crate::utils::settings::GPTService;
+use crate::utils::user_msg;
 
 #[non_exhaustive]
 #[derive(Clone, Debug, Deserialize, Serialize, PartialEq)]
@@ -17,6 +24,93 @@ pub enum Auth {
         api_base: String,
         api_key: String,
     },
+    MiniMax {
+        api_key: String,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        api_base: Option<String>,
+    },
+    Moonshot {
+        api_key: String,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        api_base: Option<String>,
+    },
+    Zhipu {
+        api_key: String,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        api_base: Option<String>,
+    },
+}
+
+impl Auth {
+    pub fn service(&self) -> GPTService {
+        match self {
+            Auth::OpenAI { .. } => GPTService::OpenAI,
+            Auth::AzureOpenAI { .. } => GPTService::AzureOpenAI,
+            Auth::MiniMax { .. } => GPTService::MiniMax,
+            Auth::Moonshot { .. } => GPTService::Moonshot,
+            Auth::Zhipu { .. } => GPTService::Zhipu,
+        }
+    }
+
+    pub fn build_client(&self) -> Client {
+        match self {
+            Auth::AzureOpenAI { .. } => Client::with_config::<AzureConfig>(self.clone().into()),
+            _ => Client::with_config::<OpenAIConfig>(self.clone().into()),
+        }
+    }
+
+    /// Clear the API key, keeping the rest like the base URL
+    pub fn clear_secret(&mut self) {
+        match self {
+            Auth::OpenAI { api_key, .. }
+            | Auth::AzureOpenAI { api_key, .. }
+            | Auth::MiniMax { api_key, .. }
+            | Auth::Moonshot { api_key, .. }
+            | Auth::Zhipu { api_key, .. } => api_key.clear(),
+        }
+    }
+
+    /// Whether the API key was cleared
+    pub fn lacks_secret(&self) -> bool {
+        match self {
+            Auth::OpenAI { api_key, .. }
+            | Auth::AzureOpenAI { api_key, .. }
+            | Auth::MiniMax { api_key, .. }
+            | Auth::Moonshot { api_key, .. }
+            | Auth::Zhipu { api_key, .. } => api_key.is_empty(),
+        }
+    }
+
+    fn api_base(&self) -> Option<&str> {
+        match self {
+            Auth::AzureOpenAI { api_base, .. } => Some(api_base.as_str()),
+            Auth::OpenAI { api_base, .. }
+            | Auth::MiniMax { api_base, .. }
+            | Auth::Moonshot { api_base, .. }
+            | Auth::Zhipu { api_base, .. } => api_base.as_deref(),
+        }
+    }
+
+    /// Make a minimal authenticated call to check the credentials
+    pub async fn test_connection(&self, model: &str) -> Result<(), ProviderError> {
+        if let Some(api_base) = self.api_base() {
+            if !(api_base.starts_with("https://") || api_base.starts_with("http://")) {
+                return Err(ProviderError::BadBaseUrl(format!("{} is not an HTTP(S) URL", api_base)));
+            }
+        }
+        let request = CreateChatCompletionRequestArgs::default()
+            .model(model)
+            .max_tokens(1_u16)
+            .messages(vec![user_msg("Hi", AgentName::UserDefault).to_request()])
+            .build()
+            .map_err(|e| ProviderError::Other(e.to_string()))?;
+        self.build_client()
+            .chat()
+            .create(request)
+            .await
+            .map(|_| ())
+            .map_err(|e| ProviderError::classify(e, self.service()))
+    }
 }
 
 
@@ -40,6 +134,7 @@ impl Into<AzureConfig> for Auth {
 
 impl Into<OpenAIConfig> for Auth {
     fn into(self) -> OpenAIConfig {
+        let service = self.service();
         match self {
             Auth::OpenAI {
                 api_key,
@@ -58,6 +153,15 @@ impl Into<OpenAIConfig> for Auth {
                     config
                 }
             }
+            // these providers expose OpenAI-compatible chat completion APIs
+            Auth::MiniMax { api_key, api_base }
+            | Auth::Moonshot { api_key, api_base }
+            | Auth::Zhipu { api_key, api_base } => {
+                let api_base = api_base.unwrap_or_else(|| service.default_api_base().unwrap().to_string());
+                OpenAIConfig::default()
+                    .with_api_key(api_key)
+                    .with_api_base(api_base)
+            }
             _ => panic!("Cannot convert Auth to OpenAIConfig, Got {:?}", self),
         }
     }
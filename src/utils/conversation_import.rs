@@ -0,0 +1,106 @@
+use std::collections::HashMap;
+
+use chrono::Utc;
+use uuid::Uuid;
+
+use crate::agents::{AgentConfig, AgentInstance};
+use crate::chat::{Chat, MessageManager};
+use crate::utils::{assistant_msg, sys_msg, user_msg};
+use crate::utils::datetime::DatetimeString;
+
+mod chatgpt;
+
+/// A conversation read from the data export of another chat tool
+#[derive(Clone, Debug, PartialEq)]
+pub struct ExternalConversation {
+    pub title: String,
+    pub created_at: Option<DatetimeString>,
+    /// every path from the start of the conversation to the end of a branch, the one last seen first
+    pub branches: Vec<Vec<ExternalMessage>>,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct ExternalMessage {
+    pub from_user: bool,
+    pub text: String,
+    pub created_at: Option<DatetimeString>,
+    /// model that wrote a reply
+    pub model: Option<String>,
+}
+
+/// Which branches of conversations to import
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Default)]
+pub enum BranchSelection {
+    /// only the branch that was last seen
+    #[default]
+    Current,
+    /// every branch, each into a chat of its own
+    All,
+}
+
+/// Read the conversations of a data export, which is the `conversations.json` of ChatGPT for now
+pub fn parse_conversations(raw: &str) -> Result<Vec<ExternalConversation>, String> {
+    chatgpt::parse(raw)
+}
+
+impl ExternalConversation {
+    pub fn message_count(&self) -> usize {
+        self.branches.first().map(|branch| branch.len()).unwrap_or_default()
+    }
+
+    /// Chats of the selected branches, where the user talks to an assistant of `config`
+    pub fn into_chats(self, config: &AgentConfig, selection: BranchSelection) -> Vec<Chat> {
+        let branch_count = self.branches.len();
+        let branches = match selection {
+            BranchSelection::Current => 1,
+            BranchSelection::All => branch_count,
+        };
+        self.branches
+            .into_iter()
+            .take(branches)
+            .enumerate()
+            .map(|(i, branch)| {
+                let topic = match i {
+                    0 => self.title.clone(),
+                    i => format!("{} (branch {})", self.title, i + 1),
+                };
+                let date = self.created_at
+                    .clone()
+                    .or_else(|| branch.first().and_then(|msg| msg.created_at.clone()))
+                    .unwrap_or_else(DatetimeString::get_now);
+                branch_into_chat(branch, topic, date, config)
+            })
+            .collect()
+    }
+}
+
+/// A chat laid out like one held in chitchai, with every message in the histories of both the user and the assistant
+fn branch_into_chat(branch: Vec<ExternalMessage>, topic: String, date: DatetimeString, config: &AgentConfig) -> Chat {
+    let mut message_manager = MessageManager::default();
+    let mut user = AgentInstance::default_user();
+    let sys_prompt_id = message_manager.insert(sys_msg(config.simple_sys_prompt()));
+    let mut assistant = AgentInstance::new(config.clone(), vec![sys_prompt_id]);
+    for message in branch {
+        let id = if message.from_user {
+            message_manager.insert_authored(user_msg(message.text, user.get_name()), Some(user.id))
+        } else {
+            message_manager.insert_authored(assistant_msg(message.text, assistant.get_name()), Some(assistant.id))
+        };
+        if let Some(meta) = message_manager.meta_mut(&id) {
+            meta.created_at = message.created_at;
+            meta.model = message.model;
+        }
+        user.history.push(id);
+        assistant.history.push(id);
+    }
+    Chat {
+        id: Uuid::new_v4(),
+        message_manager,
+        topic,
+        date,
+        agents: HashMap::from([(user.id, user), (assistant.id, assistant)]),
+        profile: None,
+        revision: Chat::new_revision(),
+        updated_at: Utc::now().timestamp_millis(),
+    }
+}
@@ -0,0 +1,239 @@
+use std::collections::{HashMap, HashSet};
+
+use chrono::{Local, TimeZone, Utc};
+use serde::Deserialize;
+use serde_json::Value;
+
+use crate::utils::datetime::DatetimeString;
+
+use super::{ExternalConversation, ExternalMessage};
+
+/// A conversation in `conversations.json`, whose messages are nodes of a tree that branches where a message was edited or a reply regenerated
+#[derive(Deserialize)]
+struct RawConversation {
+    #[serde(default)]
+    title: Option<String>,
+    #[serde(default)]
+    create_time: Option<f64>,
+    mapping: HashMap<String, RawNode>,
+    /// the node that was last seen, which ends the current branch
+    #[serde(default)]
+    current_node: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct RawNode {
+    #[serde(default)]
+    message: Option<RawMessage>,
+    #[serde(default)]
+    parent: Option<String>,
+    #[serde(default)]
+    children: Vec<String>,
+}
+
+#[derive(Deserialize)]
+struct RawMessage {
+    author: RawAuthor,
+    #[serde(default)]
+    create_time: Option<f64>,
+    content: RawContent,
+    #[serde(default)]
+    metadata: Value,
+}
+
+#[derive(Deserialize)]
+struct RawAuthor {
+    role: String,
+}
+
+#[derive(Deserialize)]
+struct RawContent {
+    content_type: String,
+    #[serde(default)]
+    parts: Vec<Value>,
+    /// content of `code` messages
+    #[serde(default)]
+    text: Option<String>,
+    #[serde(default)]
+    language: Option<String>,
+}
+
+pub(super) fn parse(raw: &str) -> Result<Vec<ExternalConversation>, String> {
+    let conversations: Vec<RawConversation> = serde_json::from_str(raw)
+        .map_err(|e| format!("not a conversations.json of ChatGPT: {}", e))?;
+    Ok(conversations
+        .into_iter()
+        .map(RawConversation::into_external)
+        .filter(|conversation| !conversation.branches.is_empty())
+        .collect())
+}
+
+impl RawConversation {
+    fn into_external(self) -> ExternalConversation {
+        let mut leaves: Vec<&String> = self.mapping
+            .iter()
+            .filter(|(_, node)| node.children.iter().all(|child| !self.mapping.contains_key(child)))
+            .map(|(id, _)| id)
+            .collect();
+        // the current branch first, then the others from the latest
+        let latest = |id: &String| self.mapping[id].message.as_ref().and_then(|m| m.create_time).unwrap_or_default();
+        leaves.sort_by(|l1, l2| latest(l2).total_cmp(&latest(l1)));
+        if let Some(current) = self.current_node.as_ref().filter(|id| self.mapping.contains_key(*id)) {
+            leaves.retain(|leaf| *leaf != current);
+            leaves.insert(0, current);
+        }
+        let mut seen = HashSet::new();
+        let branches = leaves
+            .into_iter()
+            .map(|leaf| self.branch_to(leaf))
+            // branches that differ only in hidden messages are the same to users
+            .filter(|branch| !branch.is_empty() && seen.insert(branch.iter().map(|(id, _)| id.clone()).collect::<Vec<_>>()))
+            .map(|branch| branch.into_iter().map(|(_, message)| message).collect())
+            .collect();
+        ExternalConversation {
+            title: self.title.clone().unwrap_or_else(|| "Imported Chat".to_string()),
+            created_at: self.create_time.and_then(to_datetime),
+            branches,
+        }
+    }
+
+    /// Messages shown from the start of the conversation to a leaf, along with their node IDs
+    fn branch_to(&self, leaf: &String) -> Vec<(String, ExternalMessage)> {
+        let mut branch = vec![];
+        let mut visited = HashSet::new();
+        let mut next = Some(leaf);
+        while let Some(id) = next {
+            // a malformed export may link nodes in a cycle
+            if !visited.insert(id) {
+                break;
+            }
+            let Some(node) = self.mapping.get(id) else {
+                break;
+            };
+            if let Some(message) = node.message.as_ref().and_then(RawMessage::to_external) {
+                branch.push((id.clone(), message));
+            }
+            next = node.parent.as_ref();
+        }
+        branch.reverse();
+        branch
+    }
+}
+
+impl RawMessage {
+    /// The message as users saw it, or `None` for system prompts, tool calls and other hidden messages
+    fn to_external(&self) -> Option<ExternalMessage> {
+        let from_user = match self.author.role.as_str() {
+            "user" => true,
+            "assistant" => false,
+            _ => return None,
+        };
+        if self.metadata.get("is_visually_hidden_from_conversation").and_then(Value::as_bool) == Some(true) {
+            return None;
+        }
+        let text = match self.content.content_type.as_str() {
+            "text" | "multimodal_text" => self.content.parts
+                .iter()
+                .filter_map(|part| match part {
+                    Value::String(text) => Some(text.clone()),
+                    // images and files are not in `conversations.json` but in files beside it
+                    Value::Object(part) => Some(match part.get("content_type").and_then(Value::as_str) {
+                        Some("image_asset_pointer") => "*[image]*".to_string(),
+                        _ => "*[attachment]*".to_string(),
+                    }),
+                    _ => None,
+                })
+                .filter(|text| !text.is_empty())
+                .collect::<Vec<_>>()
+                .join("\n\n"),
+            "code" => format!("```{}\n{}\n```",
+                              self.content.language.as_deref().filter(|l| *l != "unknown").unwrap_or_default(),
+                              self.content.text.as_deref().unwrap_or_default()),
+            _ => return None,
+        };
+        if text.trim().is_empty() {
+            return None;
+        }
+        Some(ExternalMessage {
+            from_user,
+            text,
+            created_at: self.create_time.and_then(to_datetime),
+            model: self.metadata
+                .get("model_slug")
+                .and_then(Value::as_str)
+                .map(str::to_string),
+        })
+    }
+}
+
+/// Seconds since the epoch into local time
+fn to_datetime(seconds: f64) -> Option<DatetimeString> {
+    let datetime = Utc
+        .timestamp_opt(seconds.trunc() as i64, (seconds.fract() * 1e9) as u32)
+        .single()?;
+    Some(datetime.with_timezone(&Local).into())
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    fn node(role: &str, text: &str, create_time: f64, parent: Option<&str>, children: &[&str]) -> Value {
+        json!({
+            "message": {
+                "author": { "role": role },
+                "create_time": create_time,
+                "content": { "content_type": "text", "parts": [text] },
+                "metadata": { "model_slug": "gpt-4" },
+            },
+            "parent": parent,
+            "children": children,
+        })
+    }
+
+    fn texts(branch: &[ExternalMessage]) -> Vec<&str> {
+        branch.iter().map(|message| message.text.as_str()).collect()
+    }
+
+    #[test]
+    fn puts_the_current_branch_first() {
+        let raw = json!([{
+            "title": "Edited",
+            "create_time": 1.0,
+            "current_node": "a1",
+            "mapping": {
+                "root": node("system", "Be brief", 1.0, None, &["q"]),
+                "q": node("user", "Hi", 1.0, Some("root"), &["a1", "a2"]),
+                "a1": node("assistant", "Hello", 2.0, Some("q"), &[]),
+                "a2": node("assistant", "Hey", 3.0, Some("q"), &[]),
+            },
+        }]);
+        let conversations = parse(&raw.to_string()).unwrap();
+        assert_eq!(conversations.len(), 1);
+        let conversation = &conversations[0];
+        assert_eq!(conversation.title, "Edited");
+        assert_eq!(conversation.branches.len(), 2);
+        assert_eq!(texts(&conversation.branches[0]), ["Hi", "Hello"]);
+        assert_eq!(texts(&conversation.branches[1]), ["Hi", "Hey"]);
+        assert!(conversation.branches[0][0].from_user);
+        assert_eq!(conversation.branches[0][1].model.as_deref(), Some("gpt-4"));
+    }
+
+    #[test]
+    fn stops_at_cycles() {
+        let raw = json!([{
+            "mapping": {
+                "a": node("user", "Hi", 1.0, Some("b"), &["b"]),
+                "b": node("assistant", "Hello", 2.0, Some("a"), &["a", "c"]),
+                "c": node("user", "Bye", 3.0, Some("b"), &[]),
+            },
+        }]);
+        let conversations = parse(&raw.to_string()).unwrap();
+        assert_eq!(conversations.len(), 1);
+        assert_eq!(conversations[0].title, "Imported Chat");
+        assert_eq!(conversations[0].branches.len(), 1);
+        assert_eq!(texts(&conversations[0].branches[0]), ["Hi", "Hello", "Bye"]);
+    }
+}
@@ -8,6 +8,29 @@ impl DatetimeString {
     pub fn get_now() -> Self {
         Self(Local::now().to_rfc3339())
     }
+
+    /// How long ago from now, like "5 min ago"
+    pub fn relative_to_now(&self) -> String {
+        let Ok(datetime) = DateTime::parse_from_rfc3339(&self.0) else {
+            return self.0.clone();
+        };
+        let elapsed = Local::now().signed_duration_since(datetime);
+        match elapsed.num_seconds() {
+            s if s < 60 => "just now".to_string(),
+            s if s < 3600 => format!("{} min ago", s / 60),
+            s if s < 86400 => format!("{} h ago", s / 3600),
+            s if s < 86400 * 30 => format!("{} d ago", s / 86400),
+            _ => datetime.with_timezone(&Local).format("%Y-%m-%d").to_string(),
+        }
+    }
+
+    /// Local date and time down to minutes, like "2023-11-05 14:03"
+    pub fn formatted(&self) -> String {
+        match DateTime::parse_from_rfc3339(&self.0) {
+            Ok(datetime) => datetime.with_timezone(&Local).format("%Y-%m-%d %H:%M").to_string(),
+            Err(_) => self.0.clone(),
+        }
+    }
 }
 
 impl From<DateTime<Local>> for DatetimeString {
@@ -0,0 +1,140 @@
+use std::fmt::{Display, Formatter};
+use std::time::Duration;
+
+use transprompt::async_openai::error::OpenAIError;
+
+use crate::utils::settings::GPTService;
+
+/// Why talking to a provider failed
+#[derive(Debug, Clone, PartialEq)]
+pub enum ProviderError {
+    /// The API key is rejected
+    BadKey(String),
+    /// The base URL does not point to an API of the provider
+    BadBaseUrl(String),
+    /// Azure OpenAI does not know the api-version
+    WrongApiVersion(String),
+    /// Azure OpenAI does not have the deployment
+    MissingDeployment(String),
+    /// The provider cannot be reached
+    Network(String),
+    /// Too many requests or tokens in a period
+    RateLimited(String),
+    /// The messages are longer than what the model can take
+    ContextLengthExceeded(String),
+    /// The provider fails on its own side
+    ServerError(String),
+    Other(String),
+}
+
+impl ProviderError {
+    pub fn classify(error: OpenAIError, service: GPTService) -> Self {
+        match error {
+            OpenAIError::ApiError(api_error) => {
+                let code = api_error.code
+                    .as_ref()
+                    .map(|c| c.to_string().to_lowercase())
+                    .unwrap_or_default();
+                let message = api_error.message;
+                let lowercase_message = message.to_lowercase();
+                if code.contains("rate_limit") || code.contains("429") || lowercase_message.contains("rate limit") {
+                    ProviderError::RateLimited(message)
+                } else if code.contains("context_length_exceeded") || lowercase_message.contains("maximum context length") {
+                    ProviderError::ContextLengthExceeded(message)
+                } else if code.contains("server_error") || code.starts_with("\"5") || code.starts_with('5') {
+                    ProviderError::ServerError(message)
+                } else if code.contains("invalid_api_key")
+                    || code.contains("401")
+                    || lowercase_message.contains("api key")
+                    || lowercase_message.contains("subscription key") {
+                    ProviderError::BadKey(message)
+                } else if code.contains("deploymentnotfound") {
+                    ProviderError::MissingDeployment(message)
+                } else if service == GPTService::AzureOpenAI
+                    && (lowercase_message.contains("api version") || lowercase_message.contains("api-version")) {
+                    ProviderError::WrongApiVersion(message)
+                } else if code.contains("404") {
+                    // a resource that is not there says nothing of the API version, but the endpoint is likely wrong
+                    ProviderError::BadBaseUrl(message)
+                } else {
+                    ProviderError::Other(message)
+                }
+            }
+            // a page that is not an API, which is what a wrong base URL often leads to
+            OpenAIError::JSONDeserialize(e) => ProviderError::BadBaseUrl(e.to_string()),
+            OpenAIError::Reqwest(e) => ProviderError::Network(e.to_string()),
+            // errors in the middle of a stream only come with a message
+            OpenAIError::StreamError(message) => {
+                let lowercase_message = message.to_lowercase();
+                if lowercase_message.contains("rate limit") || lowercase_message.contains("429") {
+                    ProviderError::RateLimited(message)
+                } else if lowercase_message.contains("context length") {
+                    ProviderError::ContextLengthExceeded(message)
+                } else {
+                    ProviderError::Network(message)
+                }
+            }
+            e => ProviderError::Other(e.to_string()),
+        }
+    }
+
+    /// Whether trying again later may succeed
+    pub const fn is_retryable(&self) -> bool {
+        matches!(self, ProviderError::RateLimited(_) | ProviderError::ServerError(_) | ProviderError::Network(_))
+    }
+
+    /// How long the provider asks to wait before retrying.
+    ///
+    /// The client does not expose the `Retry-After` header,
+    /// but providers repeat it in the message, like "Please retry after 6 seconds" or "Please try again in 20s"
+    pub fn retry_after(&self) -> Option<Duration> {
+        let ProviderError::RateLimited(message) = self else {
+            return None;
+        };
+        let message = message.to_lowercase();
+        let (_, rest) = message
+            .split_once("retry after ")
+            .or_else(|| message.split_once("try again in "))?;
+        let digits_end = rest
+            .find(|c: char| !(c.is_ascii_digit() || c == '.'))
+            .unwrap_or(rest.len());
+        let amount: f64 = rest[..digits_end].parse().ok()?;
+        let unit = rest[digits_end..].trim_start();
+        if unit.starts_with("ms") {
+            Some(Duration::from_secs_f64(amount / 1000.0))
+        } else {
+            Some(Duration::from_secs_f64(amount))
+        }
+    }
+
+    /// Short name of the kind of error to show in UIs
+    pub const fn title(&self) -> &'static str {
+        match self {
+            ProviderError::BadKey(_) => "Authentication failed",
+            ProviderError::BadBaseUrl(_) => "Bad base URL",
+            ProviderError::WrongApiVersion(_) => "Wrong API version",
+            ProviderError::MissingDeployment(_) => "Deployment not found",
+            ProviderError::Network(_) => "Network error",
+            ProviderError::RateLimited(_) => "Rate limited",
+            ProviderError::ContextLengthExceeded(_) => "Context too long",
+            ProviderError::ServerError(_) => "Provider error",
+            ProviderError::Other(_) => "Error",
+        }
+    }
+}
+
+impl Display for ProviderError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ProviderError::BadKey(msg) => write!(f, "The API key is rejected: {}", msg),
+            ProviderError::BadBaseUrl(msg) => write!(f, "The base URL does not seem to be an API: {}", msg),
+            ProviderError::WrongApiVersion(msg) => write!(f, "The API version is not supported: {}", msg),
+            ProviderError::MissingDeployment(msg) => write!(f, "The deployment is not found: {}", msg),
+            ProviderError::Network(msg) => write!(f, "Cannot reach the provider, check your network and the base URL: {}", msg),
+            ProviderError::RateLimited(msg) => write!(f, "The provider limits the rate of requests, try again later: {}", msg),
+            ProviderError::ContextLengthExceeded(msg) => write!(f, "The chat is too long for the model: {}", msg),
+            ProviderError::ServerError(msg) => write!(f, "The provider failed to reply: {}", msg),
+            ProviderError::Other(msg) => write!(f, "{}", msg),
+        }
+    }
+}
@@ -0,0 +1,230 @@
+use std::collections::HashMap;
+
+use gloo_net::http::Request;
+use serde::{Deserialize, Serialize};
+
+use crate::utils::auth::Auth;
+use crate::utils::datetime::DatetimeString;
+use crate::utils::settings::GPTService;
+
+/// What chitchai knows about a model
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ModelInfo {
+    pub id: String,
+    /// max number of tokens of prompt and completion together
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub context_window: Option<u32>,
+    /// USD per 1K prompt tokens
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub prompt_price: Option<f64>,
+    /// USD per 1K completion tokens
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub completion_price: Option<f64>,
+    #[serde(default)]
+    pub supports_tools: bool,
+}
+
+struct BuiltinModel {
+    service: GPTService,
+    id: &'static str,
+    context_window: u32,
+    prompt_price: f64,
+    completion_price: f64,
+    supports_tools: bool,
+}
+
+// Prices of Chinese providers are converted from CNY at about 7.2 CNY per USD
+const BUILTIN_MODELS: &[BuiltinModel] = &[
+    BuiltinModel { service: GPTService::OpenAI, id: "gpt-3.5-turbo", context_window: 16385, prompt_price: 0.001, completion_price: 0.002, supports_tools: true },
+    BuiltinModel { service: GPTService::OpenAI, id: "gpt-3.5-turbo-0125", context_window: 16385, prompt_price: 0.0005, completion_price: 0.0015, supports_tools: true },
+    BuiltinModel { service: GPTService::OpenAI, id: "gpt-3.5-turbo-1106", context_window: 16385, prompt_price: 0.001, completion_price: 0.002, supports_tools: true },
+    BuiltinModel { service: GPTService::OpenAI, id: "gpt-3.5-turbo-0613", context_window: 4096, prompt_price: 0.0015, completion_price: 0.002, supports_tools: true },
+    BuiltinModel { service: GPTService::OpenAI, id: "gpt-3.5-turbo-16k", context_window: 16385, prompt_price: 0.003, completion_price: 0.004, supports_tools: true },
+    BuiltinModel { service: GPTService::OpenAI, id: "gpt-4", context_window: 8192, prompt_price: 0.03, completion_price: 0.06, supports_tools: true },
+    BuiltinModel { service: GPTService::OpenAI, id: "gpt-4-32k", context_window: 32768, prompt_price: 0.06, completion_price: 0.12, supports_tools: true },
+    BuiltinModel { service: GPTService::OpenAI, id: "gpt-4-turbo", context_window: 128000, prompt_price: 0.01, completion_price: 0.03, supports_tools: true },
+    BuiltinModel { service: GPTService::OpenAI, id: "gpt-4-turbo-2024-04-09", context_window: 128000, prompt_price: 0.01, completion_price: 0.03, supports_tools: true },
+    BuiltinModel { service: GPTService::OpenAI, id: "gpt-4-turbo-preview", context_window: 128000, prompt_price: 0.01, completion_price: 0.03, supports_tools: true },
+    BuiltinModel { service: GPTService::OpenAI, id: "gpt-4-0125-preview", context_window: 128000, prompt_price: 0.01, completion_price: 0.03, supports_tools: true },
+    BuiltinModel { service: GPTService::OpenAI, id: "gpt-4-1106-preview", context_window: 128000, prompt_price: 0.01, completion_price: 0.03, supports_tools: true },
+    BuiltinModel { service: GPTService::MiniMax, id: "abab6-chat", context_window: 32768, prompt_price: 0.014, completion_price: 0.014, supports_tools: true },
+    BuiltinModel { service: GPTService::MiniMax, id: "abab5.5-chat", context_window: 16384, prompt_price: 0.0021, completion_price: 0.0021, supports_tools: true },
+    BuiltinModel { service: GPTService::MiniMax, id: "abab5.5s-chat", context_window: 8192, prompt_price: 0.0007, completion_price: 0.0007, supports_tools: false },
+    BuiltinModel { service: GPTService::Moonshot, id: "moonshot-v1-8k", context_window: 8192, prompt_price: 0.0017, completion_price: 0.0017, supports_tools: false },
+    BuiltinModel { service: GPTService::Moonshot, id: "moonshot-v1-32k", context_window: 32768, prompt_price: 0.0033, completion_price: 0.0033, supports_tools: false },
+    BuiltinModel { service: GPTService::Moonshot, id: "moonshot-v1-128k", context_window: 131072, prompt_price: 0.0083, completion_price: 0.0083, supports_tools: false },
+    BuiltinModel { service: GPTService::Zhipu, id: "glm-4", context_window: 128000, prompt_price: 0.014, completion_price: 0.014, supports_tools: true },
+    BuiltinModel { service: GPTService::Zhipu, id: "glm-3-turbo", context_window: 128000, prompt_price: 0.0007, completion_price: 0.0007, supports_tools: true },
+];
+
+impl From<&BuiltinModel> for ModelInfo {
+    fn from(model: &BuiltinModel) -> Self {
+        Self {
+            id: model.id.to_string(),
+            context_window: Some(model.context_window),
+            prompt_price: Some(model.prompt_price),
+            completion_price: Some(model.completion_price),
+            supports_tools: model.supports_tools,
+        }
+    }
+}
+
+impl ModelInfo {
+    /// Models that chitchai knows of a service without asking the provider
+    pub fn builtin(service: GPTService) -> Vec<ModelInfo> {
+        // Azure OpenAI serves OpenAI models
+        let service = match service {
+            GPTService::AzureOpenAI => GPTService::OpenAI,
+            service => service,
+        };
+        BUILTIN_MODELS
+            .iter()
+            .filter(|m| m.service == service)
+            .map(ModelInfo::from)
+            .collect()
+    }
+
+    /// Find the builtin metadata of a model, also matching dated snapshots like `gpt-4-0613`
+    ///
+    /// Only a date may follow a known id, as variants like `gpt-4-turbo` have windows and prices of their own.
+    pub fn lookup(id: &str) -> Option<ModelInfo> {
+        let id = id.trim().to_lowercase();
+        BUILTIN_MODELS
+            .iter()
+            .find(|m| id == m.id)
+            .or_else(|| BUILTIN_MODELS
+                .iter()
+                .find(|m| id
+                    .strip_prefix(m.id)
+                    .and_then(|suffix| suffix.strip_prefix('-'))
+                    .is_some_and(is_snapshot_date)))
+            .map(ModelInfo::from)
+    }
+
+    /// Metadata of a model, which is empty if the model is unknown
+    pub fn from_id(id: impl Into<String>) -> ModelInfo {
+        let id = id.into();
+        match Self::lookup(&id) {
+            Some(info) => ModelInfo { id, ..info },
+            None => ModelInfo {
+                id,
+                context_window: None,
+                prompt_price: None,
+                completion_price: None,
+                supports_tools: false,
+            },
+        }
+    }
+
+    pub fn summary(&self) -> String {
+        let mut summary = self.id.clone();
+        if let Some(context_window) = self.context_window {
+            summary.push_str(&format!(" · {}K context", context_window / 1024));
+        }
+        if let (Some(prompt_price), Some(completion_price)) = (self.prompt_price, self.completion_price) {
+            summary.push_str(&format!(" · ${}/${} per 1K tokens", prompt_price, completion_price));
+        }
+        if self.supports_tools {
+            summary.push_str(" · tools");
+        }
+        summary
+    }
+}
+
+/// Whether a suffix dates a snapshot, either as `0613` or as `2024-04-09`
+fn is_snapshot_date(suffix: &str) -> bool {
+    let is_digits = |part: &str, len: usize| part.len() == len && part.chars().all(|c| c.is_ascii_digit());
+    match suffix.split('-').collect::<Vec<_>>().as_slice() {
+        [month_day] => is_digits(month_day, 4),
+        [year, month, day] => is_digits(year, 4) && is_digits(month, 2) && is_digits(day, 2),
+        _ => false,
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct FetchedModels {
+    pub fetched_at: DatetimeString,
+    pub models: Vec<ModelInfo>,
+}
+
+/// Models available to each profile, fetched from providers or taken from the builtin table
+#[derive(Debug, Clone, PartialEq, Default, Serialize, Deserialize)]
+pub struct ModelRegistry {
+    /// keyed by profile name
+    #[serde(default)]
+    fetched: HashMap<String, FetchedModels>,
+}
+
+impl ModelRegistry {
+    pub fn cache(&mut self, profile_name: impl Into<String>, models: Vec<ModelInfo>) {
+        self.fetched.insert(profile_name.into(), FetchedModels {
+            fetched_at: DatetimeString::get_now(),
+            models,
+        });
+    }
+
+    pub fn remove(&mut self, profile_name: &str) {
+        self.fetched.remove(profile_name);
+    }
+
+    /// Metadata of a model, preferring what providers told us
+    pub fn info(&self, model_id: &str) -> ModelInfo {
+        self.fetched
+            .values()
+            .flat_map(|fetched| fetched.models.iter())
+            .find(|m| m.id == model_id && m.context_window.is_some())
+            .cloned()
+            .unwrap_or_else(|| ModelInfo::from_id(model_id))
+    }
+}
+
+#[derive(Deserialize)]
+struct AzureDeployments {
+    data: Vec<AzureDeployment>,
+}
+
+#[derive(Deserialize)]
+struct AzureDeployment {
+    id: String,
+    model: String,
+}
+
+/// Ask the provider which models are available.
+///
+/// For Azure OpenAI, these are deployments, whose ids are used as model ids
+pub async fn fetch_models(auth: &Auth) -> Result<Vec<ModelInfo>, String> {
+    match auth {
+        Auth::AzureOpenAI { api_base, api_key, .. } => {
+            let url = format!("{}/openai/deployments?api-version=2022-12-01", api_base.trim_end_matches('/'));
+            let response = Request::get(&url)
+                .header("api-key", api_key)
+                .send()
+                .await
+                .map_err(|e| e.to_string())?;
+            if !response.ok() {
+                return Err(format!("Listing deployments failed with status {}", response.status()));
+            }
+            let deployments: AzureDeployments = response.json().await.map_err(|e| e.to_string())?;
+            Ok(deployments
+                .data
+                .into_iter()
+                .map(|d| ModelInfo { id: d.id, ..ModelInfo::from_id(d.model) })
+                .collect())
+        }
+        _ => {
+            let response = auth
+                .build_client()
+                .models()
+                .list()
+                .await
+                .map_err(|e| e.to_string())?;
+            let mut models: Vec<ModelInfo> = response
+                .data
+                .into_iter()
+                .map(|m| ModelInfo::from_id(m.id))
+                .collect();
+            models.sort_by(|a, b| a.id.cmp(&b.id));
+            Ok(models)
+        }
+    }
+}
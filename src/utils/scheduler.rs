@@ -0,0 +1,128 @@
+use std::cell::RefCell;
+use std::collections::{HashMap, VecDeque};
+use std::rc::Rc;
+use std::time::Duration;
+
+use async_std::task::sleep;
+use chrono::Utc;
+
+use crate::utils::settings::RateLimits;
+
+const WINDOW_MS: i64 = 60_000;
+const POLL_INTERVAL: Duration = Duration::from_millis(250);
+
+/// Queues requests to each provider so that they stay within its rate limits.
+///
+/// Requests to the same provider are served first come, first served.
+#[derive(Clone, Default)]
+pub struct Scheduler {
+    queues: Rc<RefCell<HashMap<String, ProviderQueue>>>,
+}
+
+#[derive(Default)]
+struct ProviderQueue {
+    in_flight: u32,
+    next_ticket: u64,
+    waiting: VecDeque<u64>,
+    /// (timestamp in ms, tokens) of requests sent in the last minute
+    sent: VecDeque<(i64, u32)>,
+}
+
+impl ProviderQueue {
+    fn has_room(&mut self, limits: &RateLimits, tokens: u32, now: i64) -> bool {
+        while self.sent.front().is_some_and(|(sent_at, _)| now - sent_at >= WINDOW_MS) {
+            self.sent.pop_front();
+        }
+        let within_concurrency = self.in_flight < limits.max_concurrent.max(1);
+        let within_rpm = limits.requests_per_minute
+            .map_or(true, |rpm| (self.sent.len() as u32) < rpm);
+        // a request larger than the whole budget goes alone instead of waiting forever
+        let within_tpm = limits.tokens_per_minute
+            .map_or(true, |tpm| {
+                let used: u32 = self.sent.iter().map(|(_, t)| *t).sum();
+                self.sent.is_empty() || used.saturating_add(tokens) <= tpm
+            });
+        within_concurrency && within_rpm && within_tpm
+    }
+}
+
+/// A slot to send a request, which is released when dropped
+pub struct Permit {
+    provider: String,
+    queues: Rc<RefCell<HashMap<String, ProviderQueue>>>,
+}
+
+impl Drop for Permit {
+    fn drop(&mut self) {
+        if let Some(queue) = self.queues.borrow_mut().get_mut(&self.provider) {
+            queue.in_flight = queue.in_flight.saturating_sub(1);
+        }
+    }
+}
+
+/// A place in the queue of a provider, which is given up when dropped before being served
+struct Ticket {
+    provider: String,
+    number: u64,
+    queues: Rc<RefCell<HashMap<String, ProviderQueue>>>,
+}
+
+impl Drop for Ticket {
+    fn drop(&mut self) {
+        // a request abandoned while waiting must not hold up the ones behind it
+        if let Some(queue) = self.queues.borrow_mut().get_mut(&self.provider) {
+            queue.waiting.retain(|ticket| *ticket != self.number);
+        }
+    }
+}
+
+impl Scheduler {
+    /// Take a slot right away if nobody is waiting for `provider` and its limits have room
+    pub fn try_acquire(&self, provider: &str, limits: RateLimits, tokens: u32) -> Option<Permit> {
+        let mut queues = self.queues.borrow_mut();
+        let queue = queues.entry(provider.to_string()).or_default();
+        let now = Utc::now().timestamp_millis();
+        if !queue.waiting.is_empty() || !queue.has_room(&limits, tokens, now) {
+            return None;
+        }
+        queue.in_flight += 1;
+        queue.sent.push_back((now, tokens));
+        Some(Permit {
+            provider: provider.to_string(),
+            queues: self.queues.clone(),
+        })
+    }
+
+    /// Wait until a request of about `tokens` tokens can be sent to `provider`
+    pub async fn acquire(&self, provider: &str, limits: RateLimits, tokens: u32) -> Permit {
+        let ticket = {
+            let mut queues = self.queues.borrow_mut();
+            let queue = queues.entry(provider.to_string()).or_default();
+            let number = queue.next_ticket;
+            queue.next_ticket += 1;
+            queue.waiting.push_back(number);
+            Ticket {
+                provider: provider.to_string(),
+                number,
+                queues: self.queues.clone(),
+            }
+        };
+        loop {
+            {
+                let mut queues = self.queues.borrow_mut();
+                let queue = queues.entry(provider.to_string()).or_default();
+                let now = Utc::now().timestamp_millis();
+                if queue.waiting.front() == Some(&ticket.number) && queue.has_room(&limits, tokens, now) {
+                    queue.waiting.pop_front();
+                    queue.in_flight += 1;
+                    queue.sent.push_back((now, tokens));
+                    return Permit {
+                        provider: provider.to_string(),
+                        queues: self.queues.clone(),
+                    };
+                }
+            }
+            sleep(POLL_INTERVAL).await;
+        }
+    }
+}
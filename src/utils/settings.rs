@@ -1,64 +1,263 @@
 use std::fmt::{Display, Formatter};
+use std::str::FromStr;
+use std::time::Duration;
+
 use serde::{Deserialize, Serialize};
 
+use crate::agents::AgentProvider;
+use crate::utils::auth::Auth;
+use crate::utils::models::ModelInfo;
+
 #[derive(Debug, Clone, Copy, PartialEq, Deserialize, Serialize)]
 pub enum GPTService {
     AzureOpenAI,
     OpenAI,
+    MiniMax,
+    Moonshot,
+    Zhipu,
 }
 
-#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
-pub enum OpenAIModel {
-    GPT35,
-    GPT35_16k,
-    GPT4,
-    GPT4_32k,
+/// How a provider wants the speaker of a message to be expressed
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum NameConvention {
+    /// The `name` field of messages is honored, so agents are told apart by name
+    NameField,
+    /// The `name` field is dropped, so the speaker is written into the content and
+    /// replies of other assistants are sent as user messages
+    InlineName,
 }
 
-impl OpenAIModel {
-    pub fn all_models() -> &'static [OpenAIModel] {
+impl GPTService {
+    pub fn all_services() -> &'static [GPTService] {
         &[
-            OpenAIModel::GPT35,
-            OpenAIModel::GPT35_16k,
-            OpenAIModel::GPT4,
-            OpenAIModel::GPT4_32k,
+            GPTService::AzureOpenAI,
+            GPTService::OpenAI,
+            GPTService::MiniMax,
+            GPTService::Moonshot,
+            GPTService::Zhipu,
         ]
     }
 
-    pub fn gpt35_models() -> &'static [OpenAIModel] {
-        &[
-            OpenAIModel::GPT35,
-            OpenAIModel::GPT35_16k,
-        ]
+    pub const fn default_api_base(&self) -> Option<&'static str> {
+        match self {
+            GPTService::AzureOpenAI | GPTService::OpenAI => None,
+            GPTService::MiniMax => Some("https://api.minimax.chat/v1"),
+            GPTService::Moonshot => Some("https://api.moonshot.cn/v1"),
+            GPTService::Zhipu => Some("https://open.bigmodel.cn/api/paas/v4"),
+        }
     }
 
-    pub fn gpt4_models() -> &'static [OpenAIModel] {
-        &[
-            OpenAIModel::GPT4,
-            OpenAIModel::GPT4_32k,
-        ]
+    pub const fn name_convention(&self) -> NameConvention {
+        match self {
+            GPTService::AzureOpenAI | GPTService::OpenAI | GPTService::MiniMax => NameConvention::NameField,
+            GPTService::Moonshot | GPTService::Zhipu => NameConvention::InlineName,
+        }
+    }
+
+    pub const fn value(&self) -> &'static str {
+        match self {
+            GPTService::AzureOpenAI => "AzureOpenAI",
+            GPTService::OpenAI => "OpenAI",
+            GPTService::MiniMax => "MiniMax",
+            GPTService::Moonshot => "Moonshot",
+            GPTService::Zhipu => "Zhipu",
+        }
     }
 }
 
-impl Display for OpenAIModel {
+impl Display for GPTService {
     fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
         write!(f, "{}", match self {
-            OpenAIModel::GPT35 => "gpt-3.5-turbo",
-            OpenAIModel::GPT35_16k => "gpt-3.5-turbo-16k",
-            OpenAIModel::GPT4 => "gpt-4",
-            OpenAIModel::GPT4_32k => "gpt-4-32k",
+            GPTService::AzureOpenAI => "Azure OpenAI",
+            GPTService::OpenAI => "OpenAI",
+            GPTService::MiniMax => "MiniMax",
+            GPTService::Moonshot => "Moonshot AI",
+            GPTService::Zhipu => "Zhipu AI",
         })
     }
 }
 
-impl PartialEq<str> for OpenAIModel {
-    fn eq(&self, other: &str) -> bool {
-        let other = other.trim().to_lowercase();
-        match self {
-            OpenAIModel::GPT35 => other == "gpt-3.5-turbo",
-            OpenAIModel::GPT35_16k => other == "gpt-3.5-turbo-16k",
-            OpenAIModel::GPT4 => other == "gpt-4",
-            OpenAIModel::GPT4_32k => other == "gpt-4-32k",
+impl FromStr for GPTService {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        GPTService::all_services()
+            .iter()
+            .find(|service| service.value() == s)
+            .copied()
+            .ok_or_else(|| format!("Unknown GPTService: {}", s))
+    }
+}
+
+/// Parameters of chat completion requests
+#[derive(Debug, Clone, Copy, PartialEq, Default, Serialize, Deserialize)]
+pub struct ProfileParameters {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_tokens: Option<u16>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub temperature: Option<f32>,
+}
+
+/// Named credentials of a provider along with its default model and parameters
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ProviderProfile {
+    pub name: String,
+    pub auth: Auth,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub model: Option<String>,
+    #[serde(default)]
+    pub parameters: ProfileParameters,
+    #[serde(default)]
+    pub limits: RateLimits,
+}
+
+impl ProviderProfile {
+    pub fn service(&self) -> GPTService {
+        self.auth.service()
+    }
+
+    /// The model to use when an agent does not name one
+    pub fn default_model(&self) -> String {
+        match (&self.model, &self.auth) {
+            (Some(model), _) => model.clone(),
+            // Azure OpenAI picks the model by deployment, so the deployment stands for the model
+            (None, Auth::AzureOpenAI { deployment_id, .. }) => deployment_id.clone(),
+            (None, auth) => ModelInfo::builtin(auth.service())
+                .into_iter()
+                .next()
+                .map(|m| m.id)
+                .unwrap_or_default(),
         }
     }
-}
\ No newline at end of file
+}
+
+/// Client-side limits of requests to a provider, so that a room of many agents does not trip its rate limits
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct RateLimits {
+    /// requests in flight at the same time
+    pub max_concurrent: u32,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub requests_per_minute: Option<u32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tokens_per_minute: Option<u32>,
+}
+
+impl Default for RateLimits {
+    fn default() -> Self {
+        Self {
+            max_concurrent: 2,
+            requests_per_minute: None,
+            tokens_per_minute: None,
+        }
+    }
+}
+
+/// How often and how patiently to retry failed requests
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct RetryPolicy {
+    pub max_retries: u32,
+    pub initial_backoff_ms: u64,
+    pub max_backoff_ms: u64,
+}
+
+impl RetryPolicy {
+    /// Exponential backoff before the retry of `attempt` (0-based),
+    /// waiting at least as long as the provider asks
+    pub fn backoff(&self, attempt: u32, retry_after: Option<Duration>) -> Duration {
+        let backoff = self.initial_backoff_ms
+            .saturating_mul(2_u64.saturating_pow(attempt))
+            .min(self.max_backoff_ms);
+        let backoff = Duration::from_millis(backoff);
+        match retry_after {
+            Some(retry_after) => backoff.max(retry_after),
+            None => backoff,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct RetryPolicies {
+    /// for failures of creating a stream
+    pub creation: RetryPolicy,
+    /// for streams that drop in the middle
+    pub mid_stream: RetryPolicy,
+}
+
+impl Default for RetryPolicies {
+    fn default() -> Self {
+        Self {
+            creation: RetryPolicy {
+                max_retries: 3,
+                initial_backoff_ms: 1000,
+                max_backoff_ms: 30000,
+            },
+            mid_stream: RetryPolicy {
+                max_retries: 1,
+                initial_backoff_ms: 1000,
+                max_backoff_ms: 10000,
+            },
+        }
+    }
+}
+
+/// Which model folds the oldest turns of agents into summaries, and when
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct SummarizerSettings {
+    pub enabled: bool,
+    #[serde(default, skip_serializing_if = "AgentProvider::is_default")]
+    pub provider: AgentProvider,
+    /// summarize once the context of an agent is fuller than this percentage of its budget
+    pub threshold_percentage: usize,
+    /// recent turns, each of a user message and the replies to it, that are left as they are
+    pub keep_recent_turns: usize,
+}
+
+impl Default for SummarizerSettings {
+    fn default() -> Self {
+        Self {
+            // summaries cost requests of their own, so they are only made when asked for
+            enabled: false,
+            provider: AgentProvider::default(),
+            threshold_percentage: 75,
+            keep_recent_turns: 6,
+        }
+    }
+}
+
+/// Where the request of an agent goes
+#[derive(Debug, Clone, PartialEq)]
+pub struct AgentRoute {
+    /// name of the profile
+    pub profile: Option<String>,
+    /// `None` means the shared client of the active profile
+    pub auth: Option<Auth>,
+    pub service: Option<GPTService>,
+    pub model: String,
+    pub parameters: ProfileParameters,
+    pub limits: RateLimits,
+    /// max number of tokens of prompt and completion together
+    pub context_window: u32,
+    /// whether the API keys are locked in the vault, so that nothing can be sent
+    pub locked: bool,
+}
+
+impl AgentRoute {
+    /// Which provider answers, for logs
+    pub fn label(&self) -> String {
+        match &self.profile {
+            Some(profile) => format!("{} · {}", profile, self.model),
+            None => self.model.clone(),
+        }
+    }
+
+    /// Whether requests can be sent through it, which takes a profile and API keys that are not locked
+    pub fn is_usable(&self) -> bool {
+        (self.auth.is_some() || self.service.is_some()) && !self.locked
+    }
+
+    pub fn name_convention(&self) -> NameConvention {
+        self.service
+            .map(|service| service.name_convention())
+            .unwrap_or(NameConvention::NameField)
+    }
+}
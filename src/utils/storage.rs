@@ -1,38 +1,406 @@
+use std::cell::RefCell;
 use std::collections::HashMap;
+use std::rc::Rc;
 
+use chrono::Utc;
 use serde::{Deserialize, Serialize};
+use uuid::Uuid;
 
 pub(crate) use schema::*;
 
-use crate::agents::{AgentConfig, AgentName};
-use crate::chat::Chat;
+use crate::agents::{AgentConfig, AgentName, AgentProvider, AgentType};
+use crate::chat::{Chat, MessageID};
 use crate::utils::auth::Auth;
 use crate::utils::customization::Customization;
-use crate::utils::settings::{GPTService, OpenAIModel};
+use crate::utils::models::ModelRegistry;
+use crate::utils::settings::{AgentRoute, ProviderProfile, RetryPolicies, SummarizerSettings};
+use crate::utils::storage::backend::{CHATS_STORE, Record, STATES_STORE, StorageHandle};
+use crate::utils::storage::migration::to_versioned_json;
+use crate::utils::tokens::DEFAULT_CONTEXT_WINDOW;
+use crate::utils::sync::SyncSettings;
+use crate::utils::usage::{SpendingCaps, Usage, UsageLedger};
+use crate::utils::vault::{Secrets, Vault};
 
 pub(crate) mod schema;
 pub(crate) mod conversion;
+pub(crate) mod migration;
+pub mod backup;
+pub mod backend;
+pub mod reload;
+pub mod remote;
 
 #[derive(Clone, Debug, PartialEq)]
 pub struct StoredStates {
     pub run_count: usize,
     pub customization: Customization,
     pub name_to_configs: HashMap<AgentName, AgentConfig>,
+    /// when each agent config last changed, in milliseconds since the epoch
+    pub configs_updated_at: HashMap<AgentName, i64>,
     pub chats: Vec<Chat>,
-    pub auth: Option<Auth>,
-    pub selected_service: Option<GPTService>,
-    pub openai_model: Option<OpenAIModel>,
+    pub profiles: Vec<ProviderProfile>,
+    /// name of the profile in use
+    pub active_profile: Option<String>,
+    /// name of the profile that new chats start with
+    pub default_profile: Option<String>,
+    pub model_registry: ModelRegistry,
+    pub retry_policies: RetryPolicies,
+    pub summarizer: SummarizerSettings,
+    pub usage_ledger: UsageLedger,
+    pub spending_caps: SpendingCaps,
+    /// where the API keys are kept encrypted, if they are
+    pub vault: Option<Vault>,
+    pub sync: SyncSettings,
+    /// where the states are saved
+    pub storage: StorageHandle,
+    saved: Rc<RefCell<SavedStates>>,
+}
+
+/// What was last written to the storage, so that a save writes only what changed since
+#[derive(Clone, Debug, PartialEq, Default)]
+struct SavedStates {
+    settings: String,
+    agent_configs: String,
+    chat_order: Vec<Uuid>,
+    /// revision of each chat when it was saved
+    chats: HashMap<Uuid, u64>,
 }
 
 
 impl StoredStates {
-    pub fn get_or_init() -> Self {
-        RawStoredStates::get_or_init()
+    /// Load the states from a storage.
+    ///
+    /// If the storage cannot be read, the session starts over in memory so that the stored states are not overwritten.
+    pub async fn get_or_init(storage: StorageHandle) -> Self {
+        match RawStoredStates::get_or_init(&storage).await {
+            Ok(raw) => raw.into_stored_states(storage),
+            Err(e) => {
+                log::error!("Error when loading from {:?}, nothing will be saved in this session: {}", storage, e);
+                RawStoredStates::init().into_stored_states(StorageHandle::in_memory_because(e))
+            }
+        }
     }
 
+    /// Write the settings, the agent configs, the order of chats and each chat if they changed since the last save
     pub fn save(&self) {
-        let saved_storage: RawStoredStates = self.clone().into();
-        saved_storage.save();
+        let (puts, deletes, saved) = match self.changes() {
+            Ok(changes) => changes,
+            Err(e) => {
+                log::error!("Error when serializing StoredStates: {}", e);
+                return;
+            }
+        };
+        if puts.is_empty() && deletes.is_empty() {
+            return;
+        }
+        *self.saved.borrow_mut() = saved;
+        let saved = self.saved.clone();
+        // forget what was saved on failures, so that the next save writes everything again
+        self.storage.write_in_background(puts, deletes, move || *saved.borrow_mut() = Default::default());
+    }
+
+    /// Take what is in memory as saved
+    fn mark_saved(&self) {
+        match self.changes() {
+            Ok((_, _, saved)) => *self.saved.borrow_mut() = saved,
+            Err(e) => log::error!("Error when serializing StoredStates: {}", e),
+        }
+    }
+
+    /// Records to put and delete to save the changes since the last save, and what is saved after them
+    fn changes(&self) -> Result<(Vec<Record>, Vec<(&'static str, String)>, SavedStates), String> {
+        let saved = self.saved.borrow();
+        let mut puts = vec![];
+        let settings = self.raw_app_settings()?.to_json()?;
+        if settings != saved.settings {
+            puts.push((STATES_STORE, RawAppSettings::STORE_KEY.to_string(), settings.clone()));
+        }
+        let agent_configs = self.raw_agent_configs().to_json()?;
+        if agent_configs != saved.agent_configs {
+            puts.push((STATES_STORE, RawAgentConfigs::STORE_KEY.to_string(), agent_configs.clone()));
+        }
+        let chat_order: Vec<Uuid> = self.chats.iter().map(|chat| chat.id).collect();
+        if chat_order != saved.chat_order {
+            let raw_chat_order = RawChatOrder { chat_ids: chat_order.clone() };
+            puts.push((STATES_STORE, RawChatOrder::STORE_KEY.to_string(), raw_chat_order.to_json()?));
+        }
+        for chat in self.chats.iter().filter(|chat| saved.chats.get(&chat.id) != Some(&chat.revision)) {
+            let raw_chat: RawChat = chat.clone().into();
+            puts.push((CHATS_STORE, chat.id.to_string(), to_versioned_json(raw_chat, RawChat::MIGRATIONS.len())?));
+        }
+        let deletes = saved.chats
+            .keys()
+            .filter(|id| !chat_order.contains(id))
+            .map(|id| (CHATS_STORE, id.to_string()))
+            .collect();
+        let saved = SavedStates {
+            settings,
+            agent_configs,
+            chat_order,
+            chats: self.chats.iter().map(|chat| (chat.id, chat.revision)).collect(),
+        };
+        Ok((puts, deletes, saved))
+    }
+
+    /// Whether the API keys are encrypted and not unlocked in this session
+    pub fn vault_locked(&self) -> bool {
+        self.vault.as_ref().is_some_and(Vault::is_locked)
+    }
+
+    /// Start keeping the API keys encrypted with a passphrase
+    pub fn enable_vault(&mut self, passphrase: &str, auto_lock_minutes: u32) -> Result<(), String> {
+        if self.vault.is_some() {
+            return Err("The API keys are encrypted already".to_string());
+        }
+        self.vault = Some(Vault::create(passphrase, auto_lock_minutes)?);
+        Ok(())
+    }
+
+    /// Keep the API keys in plaintext again, which needs the vault unlocked
+    pub fn disable_vault(&mut self) -> Result<(), String> {
+        if self.vault_locked() {
+            return Err("Unlock the API keys first".to_string());
+        }
+        self.vault = None;
+        Ok(())
+    }
+
+    /// Put the API keys in the vault back into the profiles, and the sync token back into the sync settings
+    pub fn unlock_vault(&mut self, passphrase: &str) -> Result<(), String> {
+        let Some(vault) = self.vault.as_mut() else {
+            return Ok(());
+        };
+        let secrets = vault.unlock(passphrase)?;
+        self.fill_secrets(&secrets);
+        Ok(())
+    }
+
+    fn fill_secrets(&mut self, secrets: &Secrets) {
+        for profile in self.profiles.iter_mut() {
+            if let Some(auth) = secrets.profiles.get(&profile.name) {
+                profile.auth = auth.clone();
+            }
+        }
+        if !secrets.sync_token.is_empty() {
+            self.sync.token = secrets.sync_token.clone();
+        }
+    }
+
+    /// Save what is pending, then forget the API keys and the sync token until the vault is unlocked again
+    pub fn lock_vault(&mut self) {
+        if self.vault.is_none() || self.vault_locked() {
+            return;
+        }
+        self.save();
+        self.profiles.iter_mut().for_each(|profile| profile.auth.clear_secret());
+        self.sync.token.clear();
+        if let Some(vault) = self.vault.as_mut() {
+            vault.lock();
+        }
+    }
+
+    /// Credentials of the profile in use, unless they are locked in the vault
+    pub fn active_auth(&self) -> Option<&Auth> {
+        if self.vault_locked() {
+            return None;
+        }
+        self.active_profile().map(|profile| &profile.auth)
+    }
+
+    pub fn find_profile(&self, name: &str) -> Option<&ProviderProfile> {
+        self.profiles.iter().find(|p| p.name == name)
+    }
+
+    pub fn find_profile_mut(&mut self, name: &str) -> Option<&mut ProviderProfile> {
+        self.profiles.iter_mut().find(|p| p.name == name)
+    }
+
+    pub fn active_profile(&self) -> Option<&ProviderProfile> {
+        self.active_profile
+            .as_ref()
+            .and_then(|name| self.find_profile(name))
+    }
+
+    /// Insert a profile, replacing the one with the same name if any
+    pub fn upsert_profile(&mut self, profile: ProviderProfile) {
+        match self.find_profile_mut(&profile.name) {
+            Some(existing) => *existing = profile,
+            None => self.profiles.push(profile),
+        }
+    }
+
+    /// Remove a profile, clearing the references to it
+    pub fn remove_profile(&mut self, name: &str) -> Option<ProviderProfile> {
+        let idx = self.profiles.iter().position(|p| p.name == name)?;
+        let removed = self.profiles.remove(idx);
+        if self.active_profile.as_deref() == Some(name) {
+            self.active_profile = None;
+        }
+        if self.default_profile.as_deref() == Some(name) {
+            self.default_profile = None;
+        }
+        self.chats
+            .iter_mut()
+            .filter(|chat| chat.profile.as_deref() == Some(name))
+            .for_each(|chat| {
+                chat.profile = None;
+                chat.touch();
+            });
+        self.model_registry.remove(name);
+        Some(removed)
+    }
+
+    /// Name of the profile that a chat talks to, which is its own if it has one or the one in use otherwise
+    pub fn chat_profile<'a>(&'a self, chat: &'a Chat) -> Option<&'a str> {
+        chat.profile
+            .as_deref()
+            .filter(|name| self.find_profile(name).is_some())
+            .or(self.active_profile.as_deref())
+    }
+
+    /// Resolve the routes of an agent in a chat in the order to try, the primary one first and then the fallbacks
+    pub fn agent_routes(&self, chat: &Chat, config: &AgentConfig) -> Vec<AgentRoute> {
+        std::iter::once(&config.provider)
+            .chain(config.fallbacks.iter())
+            .map(|provider| self.resolve_route(provider, chat.profile.as_deref(), &config.name))
+            .collect()
+    }
+
+    /// Whether any assistant of a chat has a route that requests can be sent through
+    pub fn has_usable_route(&self, chat_idx: usize) -> bool {
+        let chat = &self.chats[chat_idx];
+        chat.agents
+            .values()
+            .filter(|agent| matches!(agent.config.agent_type, AgentType::Assistant { .. }))
+            .flat_map(|agent| self.agent_routes(chat, &agent.config))
+            .any(|route| route.is_usable())
+    }
+
+    /// Resolve the route of the summarizer
+    pub fn summarizer_route(&self) -> AgentRoute {
+        self.resolve_route(&self.summarizer.provider, None, &AgentName::Named("Summarizer".to_string()))
+    }
+
+    /// Resolve the route of a provider, whose profile is that of the chat or else the one in use if it names none
+    fn resolve_route(&self, provider: &AgentProvider, chat_profile: Option<&str>, agent_name: &AgentName) -> AgentRoute {
+        let profile = provider.profile
+            .as_ref()
+            .and_then(|name| {
+                let profile = self.find_profile(name);
+                if profile.is_none() {
+                    log::warn!("Profile {} of agent {:?} not found, falling back to the profile of the chat", name, agent_name);
+                }
+                profile
+            })
+            .or_else(|| chat_profile.and_then(|name| self.find_profile(name)));
+        let context_window = |model: &str| self.model_registry
+            .info(model)
+            .context_window
+            .unwrap_or(DEFAULT_CONTEXT_WINDOW);
+        match profile {
+            Some(profile) => {
+                let model = provider.model.clone().unwrap_or_else(|| profile.default_model());
+                AgentRoute {
+                    profile: Some(profile.name.clone()),
+                    auth: Some(profile.auth.clone()),
+                    service: Some(profile.service()),
+                    context_window: context_window(&model),
+                    model,
+                    parameters: profile.parameters,
+                    limits: profile.limits,
+                    locked: self.vault_locked(),
+                }
+            }
+            None => {
+                let active_profile = self.active_profile();
+                let model = provider.model
+                    .clone()
+                    .or_else(|| active_profile.map(|p| p.default_model()))
+                    .unwrap_or_default();
+                AgentRoute {
+                    profile: self.active_profile.clone(),
+                    auth: None,
+                    service: active_profile.map(|p| p.service()),
+                    context_window: context_window(&model),
+                    model,
+                    parameters: active_profile
+                        .map(|p| p.parameters)
+                        .unwrap_or_default(),
+                    limits: active_profile
+                        .map(|p| p.limits)
+                        .unwrap_or_default(),
+                    locked: self.vault_locked(),
+                }
+            }
+        }
+    }
+
+    /// A chat to change, which is saved again with the next save
+    pub fn chat_mut(&mut self, chat_idx: usize) -> &mut Chat {
+        let chat = &mut self.chats[chat_idx];
+        chat.touch();
+        chat
+    }
+
+    /// Record the usage of a completion on a message and in the monthly totals
+    pub fn record_usage(&mut self, chat_idx: usize, id: &MessageID, usage: Usage) {
+        self.chat_mut(chat_idx).message_manager.add_usage(id, usage);
+        self.usage_ledger.record(usage);
+    }
+
+    /// Why no new round can be started in a chat because of the spending caps, if any
+    pub fn spending_cap_exceeded(&self, chat_idx: usize) -> Option<String> {
+        let chat = &self.chats[chat_idx];
+        if self.spending_caps.is_set() {
+            // the spending of models without prices is unknown, so the caps cannot be kept with them
+            let unpriced = chat.agents
+                .values()
+                .filter(|agent| matches!(agent.config.agent_type, AgentType::Assistant { .. }))
+                .flat_map(|agent| self.agent_routes(chat, &agent.config))
+                .filter(|route| !route.model.is_empty())
+                .map(|route| self.model_registry.info(&route.model))
+                .find(|model| model.prompt_price.is_none() || model.completion_price.is_none());
+            if let Some(model) = unpriced {
+                return Some(format!("The prices of {} are unknown, so its spending cannot be kept under the caps", model.id));
+            }
+        }
+        self.spending_caps.exceeded(&chat.total_usage(), &self.usage_ledger.this_month())
+    }
+
+    /// Update the config of an agent, including the copies held by agents in chats
+    pub fn update_agent_config(&mut self, config: AgentConfig) {
+        self.put_agent_config(config, Utc::now().timestamp_millis());
+    }
+
+    /// Put chats from elsewhere in place of those with the same IDs or after the others, and delete those that are `None`
+    /// except for the last chat, since there is always a chat to show.
+    ///
+    /// Returns the IDs of the chats that were put or deleted.
+    pub(crate) fn apply_chat_changes(&mut self, changes: Vec<(Uuid, Option<RawChat>)>) -> Vec<Uuid> {
+        let mut applied = vec![];
+        for (id, raw_chat) in changes {
+            let idx = self.chats.iter().position(|chat| chat.id == id);
+            match (idx, raw_chat) {
+                (Some(idx), Some(raw_chat)) => self.chats[idx] = raw_chat.into_chat(&self.name_to_configs),
+                (None, Some(raw_chat)) => self.chats.push(raw_chat.into_chat(&self.name_to_configs)),
+                (Some(idx), None) if self.chats.len() > 1 => {
+                    self.chats.remove(idx);
+                }
+                _ => continue,
+            }
+            applied.push(id);
+        }
+        applied
+    }
+
+    /// Update the config of an agent as it was changed at a time, like elsewhere
+    pub(crate) fn put_agent_config(&mut self, config: AgentConfig, updated_at: i64) {
+        self.configs_updated_at.insert(config.name.clone(), updated_at);
+        self.chats
+            .iter_mut()
+            .flat_map(|chat| chat.agents.values_mut())
+            .filter(|agent| agent.config.name == config.name)
+            .for_each(|agent| agent.config = config.clone());
+        self.name_to_configs.insert(config.name.clone(), config);
     }
 }
 
@@ -49,3 +417,45 @@ pub(crate) struct Announcement {
     pub(crate) author: String,
     pub(crate) content: String,
 }
+
+#[cfg(test)]
+mod tests {
+    use futures::executor::block_on;
+
+    use crate::utils::storage::backend::{FileSystem, Memory};
+
+    use super::*;
+
+    /// Change a chat, save it and load it again from the same storage
+    fn saves_and_loads(storage: StorageHandle) {
+        let mut states = block_on(StoredStates::get_or_init(storage.clone()));
+        assert_eq!(states.chats.len(), 1);
+        assert!(states.changes().unwrap().0.is_empty(), "what was just loaded is taken as saved");
+        states.chat_mut(0).topic = "Renamed".to_string();
+        states.run_count += 1;
+        let (puts, deletes, _) = states.changes().unwrap();
+        assert_eq!(puts.len(), 2, "only the settings and the changed chat are written");
+        assert!(deletes.is_empty());
+        states.save();
+        assert!(states.changes().unwrap().0.is_empty());
+        let loaded = block_on(StoredStates::get_or_init(storage));
+        assert_eq!(loaded.run_count, states.run_count);
+        assert_eq!(loaded.chats.len(), 1);
+        assert_eq!(loaded.chats[0].id, states.chats[0].id);
+        assert_eq!(loaded.chats[0].topic, "Renamed");
+        assert_eq!(loaded.chats[0].updated_at, states.chats[0].updated_at);
+        assert_eq!(loaded.name_to_configs, states.name_to_configs);
+    }
+
+    #[test]
+    fn saves_into_memory() {
+        saves_and_loads(StorageHandle::new(Memory::default()));
+    }
+
+    #[test]
+    fn saves_into_files() {
+        let root = std::env::temp_dir().join(format!("chitchai_test_{}", Uuid::new_v4()));
+        saves_and_loads(StorageHandle::new(FileSystem::new(root.clone())));
+        std::fs::remove_dir_all(root).unwrap();
+    }
+}
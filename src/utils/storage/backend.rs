@@ -0,0 +1,200 @@
+use std::cell::RefCell;
+use std::fmt::{Debug, Formatter};
+use std::rc::Rc;
+use std::sync::Arc;
+
+use futures::future::LocalBoxFuture;
+
+use crate::utils::tabs::{TabChannel, TabMessage};
+
+pub use file_system::FileSystem;
+pub use indexed_db::IndexedDb;
+pub use local_storage::LocalStorageBackend;
+pub use memory::Memory;
+
+mod file_system;
+mod indexed_db;
+mod local_storage;
+mod memory;
+
+/// store of the states other than chats, keyed by their store keys
+pub const STATES_STORE: &str = "states";
+/// store of chats, keyed by their IDs
+pub const CHATS_STORE: &str = "chats";
+
+/// A (store, key, value) record
+pub type Record = (&'static str, String, String);
+
+/// Where states are persisted, as JSON strings under keys of a few stores
+pub trait StorageBackend {
+    /// Name of the backend for logs
+    fn name(&self) -> &'static str;
+
+    fn get<'a>(&'a self, store: &'a str, key: &'a str) -> LocalBoxFuture<'a, Result<Option<String>, String>>;
+
+    /// All (key, value) records of a store
+    fn get_all<'a>(&'a self, store: &'a str) -> LocalBoxFuture<'a, Result<Vec<(String, String)>, String>>;
+
+    /// Put records and delete (store, key) records, all or none of them if the backend can
+    fn write(&self, puts: Vec<Record>, deletes: Vec<(&'static str, String)>) -> LocalBoxFuture<'_, Result<(), String>>;
+
+    /// Keep a record that cannot be loaded under a backup key, so that it is not overwritten by defaults
+    fn back_up<'a>(&'a self, key: &'a str, raw: String) -> LocalBoxFuture<'a, ()> {
+        Box::pin(async move {
+            let backup_key = format!("{}_backup_{}", key, chrono::Local::now().format("%Y%m%d%H%M%S"));
+            match self.write(vec![(STATES_STORE, backup_key.clone(), raw)], vec![]).await {
+                Ok(_) => log::warn!("Kept the unloadable record of {} under {}", key, backup_key),
+                Err(e) => log::error!("Error when backing up the record of {} under {}: {}", key, backup_key, e),
+            }
+        })
+    }
+}
+
+/// The backend selected at startup, which is shared by whatever saves states
+#[derive(Clone)]
+pub struct StorageHandle {
+    backend: Rc<dyn StorageBackend>,
+    /// where other tabs sharing the backend are told of writes
+    tabs: Option<TabChannel>,
+    health: Rc<RefCell<StorageHealth>>,
+}
+
+/// What went wrong with the storage, which the user is told of since changes may be lost
+#[derive(Default)]
+struct StorageHealth {
+    /// why the states are only kept in memory for the whole session
+    unavailable: Option<String>,
+    /// error of the last write, until a write succeeds again
+    write_error: Option<String>,
+    /// re-renders what shows the problem
+    listener: Option<Arc<dyn Fn() + Send + Sync>>,
+}
+
+impl StorageHealth {
+    fn set_write_error(&mut self, write_error: Option<String>) {
+        if self.write_error != write_error {
+            self.write_error = write_error;
+            if let Some(listener) = &self.listener {
+                listener();
+            }
+        }
+    }
+}
+
+impl StorageHandle {
+    pub fn new(backend: impl StorageBackend + 'static) -> Self {
+        Self {
+            backend: Rc::new(backend),
+            tabs: None,
+            health: Default::default(),
+        }
+    }
+
+    /// Keep the states in memory for a session where the storage cannot be used
+    pub fn in_memory_because(reason: String) -> Self {
+        let handle = Self::new(Memory::default());
+        handle.health.borrow_mut().unavailable = Some(reason);
+        handle
+    }
+
+    /// What to tell the user about the storage, if anything went wrong
+    pub fn problem(&self) -> Option<String> {
+        let health = self.health.borrow();
+        match (&health.unavailable, &health.write_error) {
+            (Some(reason), _) => Some(format!("Nothing is saved in this session, as the storage cannot be loaded: {}", reason)),
+            (None, Some(e)) => Some(format!("The latest changes could not be saved: {}", e)),
+            (None, None) => None,
+        }
+    }
+
+    /// Re-render a component when the problem with the storage changes
+    pub fn on_problem(&self, listener: Arc<dyn Fn() + Send + Sync>) {
+        self.health.borrow_mut().listener = Some(listener);
+    }
+
+    /// Tell other tabs of the browser what is written, so that they can reload it
+    #[cfg(target_arch = "wasm32")]
+    fn shared_with_tabs(mut self) -> Self {
+        match TabChannel::open() {
+            Ok(channel) => self.tabs = Some(channel),
+            Err(e) => log::error!("Error when opening a channel to other tabs, which will not see the changes: {}", e),
+        }
+        self
+    }
+
+    /// The backend of the platform: IndexedDB in browsers, or LocalStorage if it is not available, and files elsewhere
+    pub async fn select() -> Self {
+        #[cfg(target_arch = "wasm32")]
+        {
+            let handle = match IndexedDb::open().await {
+                Ok(db) => Self::new(db),
+                Err(e) => {
+                    log::error!("Error when opening IndexedDB, falling back to LocalStorage: {}", e);
+                    Self::new(LocalStorageBackend)
+                }
+            };
+            handle.shared_with_tabs()
+        }
+        #[cfg(not(target_arch = "wasm32"))]
+        {
+            Self::new(FileSystem::new(FileSystem::default_root()))
+        }
+    }
+
+    pub fn backend(&self) -> &dyn StorageBackend {
+        self.backend.as_ref()
+    }
+
+    /// The channel to other tabs sharing the backend, if any
+    pub fn tab_channel(&self) -> Option<&TabChannel> {
+        self.tabs.as_ref()
+    }
+
+    /// Write records without waiting for it, in the order of the calls, and tell other tabs once they are written
+    pub fn write_in_background(&self,
+                               puts: Vec<Record>,
+                               deletes: Vec<(&'static str, String)>,
+                               on_failure: impl FnOnce() + 'static) {
+        let backend = self.backend.clone();
+        let tabs = self.tabs.clone();
+        let health = self.health.clone();
+        // each write starts before yielding, so writes spawned in order are done in order
+        let write = async move {
+            let records: Vec<(String, String)> = puts
+                .iter()
+                .map(|(store, key, _)| (store.to_string(), key.clone()))
+                .chain(deletes.iter().map(|(store, key)| (store.to_string(), key.clone())))
+                .collect();
+            match backend.write(puts, deletes).await {
+                Ok(_) => {
+                    log::info!("Saved {} records of StoredStates into {}", records.len(), backend.name());
+                    health.borrow_mut().set_write_error(None);
+                    if let Some(tabs) = tabs {
+                        tabs.post(TabMessage::Saved { records });
+                    }
+                }
+                Err(e) => {
+                    log::error!("Error when saving StoredStates into {}: {}", backend.name(), e);
+                    health.borrow_mut().set_write_error(Some(e));
+                    on_failure();
+                }
+            }
+        };
+        #[cfg(target_arch = "wasm32")]
+        wasm_bindgen_futures::spawn_local(write);
+        #[cfg(not(target_arch = "wasm32"))]
+        futures::executor::block_on(write);
+    }
+}
+
+impl Debug for StorageHandle {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "StorageHandle({})", self.backend.name())
+    }
+}
+
+impl PartialEq for StorageHandle {
+    fn eq(&self, other: &Self) -> bool {
+        Rc::ptr_eq(&self.backend, &other.backend)
+    }
+}
@@ -0,0 +1,98 @@
+use std::fs;
+use std::io::ErrorKind;
+use std::path::PathBuf;
+
+use futures::future::LocalBoxFuture;
+
+use super::{Record, StorageBackend};
+
+/// JSON files under `<root>/<store>/<key>.json`, for native targets
+pub struct FileSystem {
+    root: PathBuf,
+}
+
+impl FileSystem {
+    pub fn new(root: impl Into<PathBuf>) -> Self {
+        Self {
+            root: root.into(),
+        }
+    }
+
+    /// `$CHITCHAI_DATA_DIR`, or `chitchai_data` in the working directory
+    pub fn default_root() -> PathBuf {
+        std::env::var_os("CHITCHAI_DATA_DIR")
+            .map(PathBuf::from)
+            .unwrap_or_else(|| PathBuf::from("chitchai_data"))
+    }
+
+    fn path(&self, store: &str, key: &str) -> PathBuf {
+        self.root.join(store).join(format!("{}.json", key))
+    }
+
+    fn put(&self, store: &str, key: &str, value: &str) -> std::io::Result<()> {
+        let path = self.path(store, key);
+        fs::create_dir_all(self.root.join(store))?;
+        // write aside then rename, so that a crash never leaves a half written file
+        let temp_path = path.with_extension("json.tmp");
+        fs::write(&temp_path, value)?;
+        fs::rename(temp_path, path)
+    }
+
+    fn delete(&self, store: &str, key: &str) -> std::io::Result<()> {
+        match fs::remove_file(self.path(store, key)) {
+            Err(e) if e.kind() != ErrorKind::NotFound => Err(e),
+            _ => Ok(()),
+        }
+    }
+}
+
+impl StorageBackend for FileSystem {
+    fn name(&self) -> &'static str {
+        "file system"
+    }
+
+    fn get<'a>(&'a self, store: &'a str, key: &'a str) -> LocalBoxFuture<'a, Result<Option<String>, String>> {
+        let value = match fs::read_to_string(self.path(store, key)) {
+            Ok(value) => Ok(Some(value)),
+            Err(e) if e.kind() == ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(e.to_string()),
+        };
+        Box::pin(async move { value })
+    }
+
+    fn get_all<'a>(&'a self, store: &'a str) -> LocalBoxFuture<'a, Result<Vec<(String, String)>, String>> {
+        let read = || -> std::io::Result<Vec<(String, String)>> {
+            let entries = match fs::read_dir(self.root.join(store)) {
+                Ok(entries) => entries,
+                Err(e) if e.kind() == ErrorKind::NotFound => return Ok(vec![]),
+                Err(e) => return Err(e),
+            };
+            let mut records = vec![];
+            for entry in entries {
+                let path = entry?.path();
+                let Some(key) = path
+                    .file_name()
+                    .and_then(|name| name.to_str())
+                    .and_then(|name| name.strip_suffix(".json")) else {
+                    continue;
+                };
+                records.push((key.to_string(), fs::read_to_string(&path)?));
+            }
+            Ok(records)
+        };
+        let records = read().map_err(|e| e.to_string());
+        Box::pin(async move { records })
+    }
+
+    /// Put and delete records file by file, stopping at the first failure
+    fn write(&self, puts: Vec<Record>, deletes: Vec<(&'static str, String)>) -> LocalBoxFuture<'_, Result<(), String>> {
+        let result = puts
+            .iter()
+            .try_for_each(|(store, key, value)| self.put(store, key, value))
+            .and_then(|_| deletes
+                .iter()
+                .try_for_each(|(store, key)| self.delete(store, key)))
+            .map_err(|e| e.to_string());
+        Box::pin(async move { result })
+    }
+}
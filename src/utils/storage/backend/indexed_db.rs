@@ -0,0 +1,96 @@
+use std::rc::Rc;
+
+use futures::future::LocalBoxFuture;
+use rexie::{ObjectStore, Rexie, TransactionMode};
+use wasm_bindgen::JsValue;
+
+use super::{CHATS_STORE, Record, STATES_STORE, StorageBackend};
+
+const DB_NAME: &str = "chitchai";
+const DB_VERSION: u32 = 1;
+
+/// The IndexedDB database of chitchai, whose records are JSON strings
+#[derive(Clone)]
+pub struct IndexedDb {
+    db: Rc<Rexie>,
+}
+
+impl IndexedDb {
+    pub async fn open() -> Result<Self, String> {
+        let db = Rexie::builder(DB_NAME)
+            .version(DB_VERSION)
+            .add_object_store(ObjectStore::new(STATES_STORE))
+            .add_object_store(ObjectStore::new(CHATS_STORE))
+            .build()
+            .await
+            .map_err(|e| e.to_string())?;
+        Ok(Self {
+            db: Rc::new(db),
+        })
+    }
+}
+
+impl StorageBackend for IndexedDb {
+    fn name(&self) -> &'static str {
+        "IndexedDB"
+    }
+
+    fn get<'a>(&'a self, store: &'a str, key: &'a str) -> LocalBoxFuture<'a, Result<Option<String>, String>> {
+        Box::pin(async move {
+            let transaction = self.db
+                .transaction(&[store], TransactionMode::ReadOnly)
+                .map_err(|e| e.to_string())?;
+            let value = transaction
+                .store(store)
+                .map_err(|e| e.to_string())?
+                .get(&JsValue::from_str(key))
+                .await
+                .map_err(|e| e.to_string())?;
+            Ok(value.as_string())
+        })
+    }
+
+    fn get_all<'a>(&'a self, store: &'a str) -> LocalBoxFuture<'a, Result<Vec<(String, String)>, String>> {
+        Box::pin(async move {
+            let transaction = self.db
+                .transaction(&[store], TransactionMode::ReadOnly)
+                .map_err(|e| e.to_string())?;
+            let records = transaction
+                .store(store)
+                .map_err(|e| e.to_string())?
+                .get_all(None, None, None, None)
+                .await
+                .map_err(|e| e.to_string())?;
+            Ok(records
+                .into_iter()
+                .filter_map(|(key, value)| Some((key.as_string()?, value.as_string()?)))
+                .collect())
+        })
+    }
+
+    /// Put and delete records in one transaction, so that either all or none of them are written
+    fn write(&self, puts: Vec<Record>, deletes: Vec<(&'static str, String)>) -> LocalBoxFuture<'_, Result<(), String>> {
+        // open the transaction right away, so that writes are done in the order of the calls
+        let transaction = self.db.transaction(&[STATES_STORE, CHATS_STORE], TransactionMode::ReadWrite);
+        Box::pin(async move {
+            let transaction = transaction.map_err(|e| e.to_string())?;
+            for (store, key, value) in puts {
+                transaction
+                    .store(store)
+                    .map_err(|e| e.to_string())?
+                    .put(&JsValue::from_str(&value), Some(&JsValue::from_str(&key)))
+                    .await
+                    .map_err(|e| e.to_string())?;
+            }
+            for (store, key) in deletes {
+                transaction
+                    .store(store)
+                    .map_err(|e| e.to_string())?
+                    .delete(&JsValue::from_str(&key))
+                    .await
+                    .map_err(|e| e.to_string())?;
+            }
+            transaction.done().await.map_err(|e| e.to_string())
+        })
+    }
+}
@@ -0,0 +1,56 @@
+use futures::future::LocalBoxFuture;
+use gloo_storage::{LocalStorage, Storage};
+
+use super::{Record, StorageBackend};
+
+/// LocalStorage of browsers, which keeps a record under `chitchai/<store>/<key>`.
+///
+/// It takes only a few megabytes, so it is only used where IndexedDB is not available.
+pub struct LocalStorageBackend;
+
+impl LocalStorageBackend {
+    fn storage_key(store: &str, key: &str) -> String {
+        format!("chitchai/{}/{}", store, key)
+    }
+}
+
+impl StorageBackend for LocalStorageBackend {
+    fn name(&self) -> &'static str {
+        "LocalStorage"
+    }
+
+    fn get<'a>(&'a self, store: &'a str, key: &'a str) -> LocalBoxFuture<'a, Result<Option<String>, String>> {
+        let value = LocalStorage::raw()
+            .get_item(&Self::storage_key(store, key))
+            .map_err(|e| format!("{:?}", e));
+        Box::pin(async move { value })
+    }
+
+    fn get_all<'a>(&'a self, store: &'a str) -> LocalBoxFuture<'a, Result<Vec<(String, String)>, String>> {
+        let prefix = Self::storage_key(store, "");
+        let storage = LocalStorage::raw();
+        let length = storage.length().unwrap_or(0);
+        let records = (0..length)
+            .filter_map(|i| storage.key(i).ok().flatten())
+            .filter_map(|storage_key| {
+                let key = storage_key.strip_prefix(&prefix)?.to_string();
+                let value = storage.get_item(&storage_key).ok().flatten()?;
+                Some((key, value))
+            })
+            .collect();
+        Box::pin(async move { Ok(records) })
+    }
+
+    /// Put and delete records one by one, stopping at the first failure like running out of the quota
+    fn write(&self, puts: Vec<Record>, deletes: Vec<(&'static str, String)>) -> LocalBoxFuture<'_, Result<(), String>> {
+        let storage = LocalStorage::raw();
+        let result = puts
+            .into_iter()
+            .try_for_each(|(store, key, value)| storage.set_item(&Self::storage_key(store, &key), &value))
+            .and_then(|_| deletes
+                .into_iter()
+                .try_for_each(|(store, key)| storage.remove_item(&Self::storage_key(store, &key))))
+            .map_err(|e| format!("{:?}", e));
+        Box::pin(async move { result })
+    }
+}
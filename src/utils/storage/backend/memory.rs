@@ -0,0 +1,47 @@
+use std::cell::RefCell;
+use std::collections::BTreeMap;
+
+use futures::future::LocalBoxFuture;
+
+use super::{Record, StorageBackend};
+
+/// Records kept in memory only, for tests and for sessions whose storage cannot be read
+#[derive(Default)]
+pub struct Memory {
+    records: RefCell<BTreeMap<(String, String), String>>,
+}
+
+impl StorageBackend for Memory {
+    fn name(&self) -> &'static str {
+        "memory"
+    }
+
+    fn get<'a>(&'a self, store: &'a str, key: &'a str) -> LocalBoxFuture<'a, Result<Option<String>, String>> {
+        let value = self.records
+            .borrow()
+            .get(&(store.to_string(), key.to_string()))
+            .cloned();
+        Box::pin(async move { Ok(value) })
+    }
+
+    fn get_all<'a>(&'a self, store: &'a str) -> LocalBoxFuture<'a, Result<Vec<(String, String)>, String>> {
+        let records = self.records
+            .borrow()
+            .iter()
+            .filter(|((s, _), _)| s == store)
+            .map(|((_, key), value)| (key.clone(), value.clone()))
+            .collect();
+        Box::pin(async move { Ok(records) })
+    }
+
+    fn write(&self, puts: Vec<Record>, deletes: Vec<(&'static str, String)>) -> LocalBoxFuture<'_, Result<(), String>> {
+        let mut records = self.records.borrow_mut();
+        for (store, key, value) in puts {
+            records.insert((store.to_string(), key), value);
+        }
+        for (store, key) in deletes {
+            records.remove(&(store.to_string(), key));
+        }
+        Box::pin(async { Ok(()) })
+    }
+}
@@ -0,0 +1,355 @@
+use std::collections::{HashMap, HashSet};
+use std::fmt::{Display, Formatter};
+
+use chrono::Utc;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use uuid::Uuid;
+
+use crate::agents::{AgentConfig, AgentName};
+use crate::chat::Chat;
+use crate::utils::datetime::DatetimeString;
+use crate::utils::storage::StoredStates;
+use crate::utils::storage::migration::{Migration, to_versioned_json, to_versioned_value, upgrade};
+use crate::utils::sync::ledger_key;
+use crate::utils::sync::protocol::ItemKind;
+
+use super::schema::*;
+
+/// Marker of backup files, which tells them apart from other JSON files
+const BACKUP_KIND: &str = "chitchai_backup";
+
+/// A backup of all states in a single JSON file.
+///
+/// The states are embedded as the blobs that are stored, each with its own version,
+/// so that backups of older versions are migrated on import like stored states are on load.
+#[derive(Serialize, Deserialize)]
+struct RawBackup {
+    #[serde(default)]
+    kind: String,
+    exported_at: DatetimeString,
+    /// whether the API keys of the profiles are in the backup
+    secrets_included: bool,
+    settings: Value,
+    agent_configs: Value,
+    chats: Vec<Value>,
+}
+
+impl RawBackup {
+    /// `MIGRATIONS[i]` upgrades a backup of version `i` to version `i + 1`, like `StoredState::MIGRATIONS`
+    const MIGRATIONS: &'static [Migration] = &[];
+
+    /// Parse a backup into the raw states, migrating them if they are of older versions
+    fn parse(raw: &str) -> Result<RawStoredStates, String> {
+        let backup: RawBackup = upgrade("backup", raw, Self::MIGRATIONS)?;
+        if backup.kind != BACKUP_KIND {
+            return Err("not a backup of chitchai".to_string());
+        }
+        log::info!("Importing a backup exported at {}", backup.exported_at.0);
+        let raw_app_settings = RawAppSettings::parse(&backup.settings.to_string())
+            .map_err(|e| format!("invalid settings: {}", e))?;
+        let raw_agent_configs = RawAgentConfigs::parse(&backup.agent_configs.to_string())
+            .map_err(|e| format!("invalid agent configs: {}", e))?;
+        let chats = backup.chats
+            .into_iter()
+            .map(|chat| {
+                let raw_chat: RawChat = upgrade("chat", &chat.to_string(), RawChat::MIGRATIONS)
+                    .map_err(|e| format!("invalid chat: {}", e))?;
+                // chats are loaded with the configs of their agents, which must come along
+                let lacks_config = |agent: &&RawAgentInstance| {
+                    let name: RawAgentName = agent.name.clone().into();
+                    !raw_agent_configs.name_to_configs.contains_key(&name)
+                };
+                match raw_chat.agents.values().find(lacks_config) {
+                    Some(agent) => Err(format!("chat {} has agent {} without a config", raw_chat.id, agent.name)),
+                    None => Ok(raw_chat),
+                }
+            })
+            .collect::<Result<Vec<_>, String>>()?;
+        let raw_chats = if chats.is_empty() {
+            RawChats::init()
+        } else {
+            RawChats { chats }
+        };
+        Ok(RawStoredStates {
+            raw_app_settings,
+            raw_chats,
+            raw_agent_configs,
+        })
+    }
+}
+
+/// How to import a backup
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Default)]
+pub enum ImportMode {
+    /// add the chats and agents of the backup to those in use
+    #[default]
+    Merge,
+    /// replace everything with the backup, except the API keys that the backup lacks
+    Replace,
+}
+
+/// What to do with a chat or an agent of a backup that has the ID or name of one in use when merging
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Default)]
+pub enum ConflictPolicy {
+    /// import it under a new ID, or a new name for agents
+    #[default]
+    KeepBoth,
+    /// replace the one in use with it
+    Overwrite,
+    /// keep the one in use and drop it
+    Skip,
+}
+
+/// What an import did
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Default)]
+pub struct ImportSummary {
+    pub chats: usize,
+    pub agents: usize,
+    pub conflicts: usize,
+}
+
+impl Display for ImportSummary {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Imported {} chats and {} agents", self.chats, self.agents)?;
+        if self.conflicts > 0 {
+            write!(f, ", with {} conflicts", self.conflicts)?;
+        }
+        Ok(())
+    }
+}
+
+impl StoredStates {
+    /// Export the settings, the agent configs and all chats into a backup
+    pub fn export_backup(&self, include_secrets: bool) -> Result<String, String> {
+        let mut raw_app_settings = self.raw_app_settings()?;
+        if !include_secrets {
+            raw_app_settings.profiles
+                .iter_mut()
+                .for_each(|profile| profile.auth.clear_secret());
+            raw_app_settings.vault = None;
+        }
+        // what this device synced, and with which server, is of no use elsewhere
+        raw_app_settings.sync = Default::default();
+        let chats = self.chats
+            .iter()
+            .map(|chat| {
+                let raw_chat: RawChat = chat.clone().into();
+                to_versioned_value(raw_chat, RawChat::MIGRATIONS.len())
+            })
+            .collect::<Result<_, _>>()?;
+        let backup = RawBackup {
+            kind: BACKUP_KIND.to_string(),
+            exported_at: DatetimeString::get_now(),
+            secrets_included: include_secrets,
+            settings: to_versioned_value(raw_app_settings, RawAppSettings::MIGRATIONS.len())?,
+            agent_configs: to_versioned_value(self.raw_agent_configs(), RawAgentConfigs::MIGRATIONS.len())?,
+            chats,
+        };
+        to_versioned_json(backup, RawBackup::MIGRATIONS.len())
+    }
+
+    /// Import a backup, which is saved on the next save
+    pub fn import_backup(&mut self, raw: &str, mode: ImportMode, policy: ConflictPolicy) -> Result<ImportSummary, String> {
+        let raw = RawBackup::parse(raw)?;
+        let summary = match mode {
+            ImportMode::Merge => self.merge(raw, policy),
+            ImportMode::Replace => self.replace_with(raw),
+        };
+        log::info!("{}", summary);
+        Ok(summary)
+    }
+
+    fn replace_with(&mut self, raw: RawStoredStates) -> ImportSummary {
+        let summary = ImportSummary {
+            chats: raw.raw_chats.chats.len(),
+            agents: raw.raw_agent_configs.name_to_configs.len(),
+            conflicts: 0,
+        };
+        let mut imported = raw.into_stored_states(self.storage.clone());
+        // keep the API keys in use for the profiles whose keys were left out of the backup
+        for profile in imported.profiles.iter_mut().filter(|profile| profile.auth.lacks_secret()) {
+            if let Some(in_use) = self.find_profile(&profile.name).filter(|p| p.service() == profile.service()) {
+                profile.auth = in_use.auth.clone();
+            }
+        }
+        imported.run_count = self.run_count;
+        imported.sync = self.sync.clone();
+        // what the backup brings changed on this device now, and chats left out of it were deleted now, as far as syncing goes
+        let now = Utc::now().timestamp_millis();
+        imported.chats.iter_mut().for_each(Chat::touch);
+        imported.configs_updated_at = imported.name_to_configs
+            .keys()
+            .map(|name| (name.clone(), now))
+            .collect();
+        for chat in self.chats.iter().filter(|chat| imported.chats.iter().all(|c| c.id != chat.id)) {
+            let ledger_key = ledger_key(ItemKind::Chat, &chat.id.to_string());
+            if imported.sync.synced.contains_key(&ledger_key) {
+                imported.sync.deleted_at.insert(ledger_key, now);
+            }
+        }
+        // the API keys stay encrypted here unless the backup brings a vault of its own
+        if imported.vault.is_none() {
+            imported.vault = self.vault.take();
+        }
+        // what is in the storage is still what was saved before the import
+        imported.saved = self.saved.clone();
+        *self = imported;
+        summary
+    }
+
+    fn merge(&mut self, raw: RawStoredStates, policy: ConflictPolicy) -> ImportSummary {
+        let RawStoredStates {
+            raw_app_settings,
+            mut raw_chats,
+            raw_agent_configs,
+        } = raw;
+        let mut summary = ImportSummary::default();
+        // profiles are only added, since they may hold API keys that the backup lacks
+        for profile in raw_app_settings.profiles {
+            if self.find_profile(&profile.name).is_none() {
+                self.profiles.push(profile);
+            }
+        }
+        let mut renames: HashMap<AgentName, AgentName> = HashMap::new();
+        for (name, config) in raw_agent_configs.name_to_configs {
+            let name: AgentName = name.into();
+            match self.name_to_configs.get(&name) {
+                None => {
+                    self.update_agent_config(config);
+                    summary.agents += 1;
+                }
+                Some(in_use) if *in_use == config => {}
+                Some(_) => {
+                    summary.conflicts += 1;
+                    match (policy, &name) {
+                        (ConflictPolicy::Overwrite, _) => {
+                            self.update_agent_config(config);
+                            summary.agents += 1;
+                        }
+                        (ConflictPolicy::KeepBoth, AgentName::Named(base)) => {
+                            let new_name = self.unused_agent_name(base);
+                            self.update_agent_config(AgentConfig {
+                                name: new_name.clone(),
+                                ..config
+                            });
+                            renames.insert(name, new_name);
+                            summary.agents += 1;
+                        }
+                        // the default user and assistant cannot be renamed, so imported chats use those in use
+                        (ConflictPolicy::KeepBoth, _) | (ConflictPolicy::Skip, _) => {}
+                    }
+                }
+            }
+        }
+        raw_chats.chats
+            .iter_mut()
+            .flat_map(|chat| chat.agents.values_mut())
+            .for_each(|agent| if let Some(new_name) = renames.get(&agent.name) {
+                agent.name = new_name.clone();
+            });
+        let chat_ids: HashSet<Uuid> = self.chats.iter().map(|chat| chat.id).collect();
+        let mut changes = vec![];
+        for mut raw_chat in raw_chats.chats {
+            if chat_ids.contains(&raw_chat.id) {
+                summary.conflicts += 1;
+                match policy {
+                    ConflictPolicy::KeepBoth => raw_chat.id = Uuid::new_v4(),
+                    ConflictPolicy::Overwrite => {}
+                    ConflictPolicy::Skip => continue,
+                }
+            }
+            changes.push((raw_chat.id, Some(raw_chat)));
+        }
+        // imported chats changed on this device, as far as syncing goes
+        for id in self.apply_chat_changes(changes) {
+            if let Some(chat) = self.chats.iter_mut().find(|chat| chat.id == id) {
+                chat.touch();
+            }
+            summary.chats += 1;
+        }
+        summary
+    }
+
+    /// `<name> (imported)`, numbered if that is taken too
+    fn unused_agent_name(&self, base: &str) -> AgentName {
+        (1..)
+            .map(|n| match n {
+                1 => AgentName::Named(format!("{} (imported)", base)),
+                n => AgentName::Named(format!("{} (imported {})", base, n)),
+            })
+            .find(|name| !self.name_to_configs.contains_key(name))
+            .unwrap()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::utils::storage::backend::{Memory, StorageHandle};
+
+    use super::*;
+
+    fn states() -> StoredStates {
+        RawStoredStates::init().into_stored_states(StorageHandle::new(Memory::default()))
+    }
+
+    /// States that differ from their backup in the topic of the chat and the description of Alice
+    fn changed_since_backup() -> (StoredStates, String) {
+        let mut states = states();
+        let backup = states.export_backup(false).unwrap();
+        states.chat_mut(0).topic = "Changed".to_string();
+        let alice = AgentName::Named("Alice".to_string());
+        let config = AgentConfig {
+            description: "Changed".to_string(),
+            ..states.name_to_configs[&alice].clone()
+        };
+        states.update_agent_config(config);
+        (states, backup)
+    }
+
+    #[test]
+    fn merges_keeping_both() {
+        let (mut states, backup) = changed_since_backup();
+        let summary = states.import_backup(&backup, ImportMode::Merge, ConflictPolicy::KeepBoth).unwrap();
+        assert_eq!(summary, ImportSummary { chats: 1, agents: 1, conflicts: 2 });
+        assert_eq!(states.chats.len(), 2);
+        assert_ne!(states.chats[0].id, states.chats[1].id);
+        assert_eq!(states.chats[0].topic, "Changed");
+        assert_ne!(states.chats[1].topic, "Changed");
+        assert!(states.name_to_configs.contains_key(&AgentName::Named("Alice (imported)".to_string())));
+    }
+
+    #[test]
+    fn merges_overwriting() {
+        let (mut states, backup) = changed_since_backup();
+        let summary = states.import_backup(&backup, ImportMode::Merge, ConflictPolicy::Overwrite).unwrap();
+        assert_eq!(summary, ImportSummary { chats: 1, agents: 1, conflicts: 2 });
+        assert_eq!(states.chats.len(), 1);
+        assert_ne!(states.chats[0].topic, "Changed");
+        assert_ne!(states.name_to_configs[&AgentName::Named("Alice".to_string())].description, "Changed");
+    }
+
+    #[test]
+    fn merges_skipping() {
+        let (mut states, backup) = changed_since_backup();
+        let summary = states.import_backup(&backup, ImportMode::Merge, ConflictPolicy::Skip).unwrap();
+        assert_eq!(summary, ImportSummary { chats: 0, agents: 0, conflicts: 2 });
+        assert_eq!(states.chats.len(), 1);
+        assert_eq!(states.chats[0].topic, "Changed");
+        assert_eq!(states.name_to_configs[&AgentName::Named("Alice".to_string())].description, "Changed");
+    }
+
+    #[test]
+    fn replaces() {
+        let exported = states();
+        let backup = exported.export_backup(false).unwrap();
+        let mut states = states();
+        states.run_count = 3;
+        let summary = states.import_backup(&backup, ImportMode::Replace, ConflictPolicy::default()).unwrap();
+        assert_eq!(summary.chats, 1);
+        assert_eq!(states.chats.len(), 1);
+        assert_eq!(states.chats[0].id, exported.chats[0].id);
+        assert_eq!(states.run_count, 3);
+        assert!(states.import_backup("{}", ImportMode::Replace, ConflictPolicy::default()).is_err());
+    }
+}
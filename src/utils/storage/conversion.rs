@@ -1,15 +1,18 @@
 use std::collections::HashMap;
 
+use transprompt::async_openai::types::{ChatCompletionRequestMessage, ChatCompletionRequestMessageContentPart, ChatCompletionRequestUserMessageContent};
 use uuid::Uuid;
 
 use crate::agents::{AgentConfig, AgentID, AgentInstance, AgentName};
-use crate::chat::{Chat, MessageID, MessageManager};
+use crate::chat::{Chat, ChatMessage, ContentPart, HistorySummary, Message, MessageID, MessageManager, Role, ToolCall};
 use crate::utils::storage::StoredStates;
+use crate::utils::storage::backend::StorageHandle;
+use crate::utils::vault::{Secrets, Vault};
 
 use super::schema::*;
 
-impl Into<StoredStates> for RawStoredStates {
-    fn into(self) -> StoredStates {
+impl RawStoredStates {
+    pub fn into_stored_states(self, storage: StorageHandle) -> StoredStates {
         let RawStoredStates {
             raw_app_settings,
             raw_chats,
@@ -18,52 +21,108 @@ impl Into<StoredStates> for RawStoredStates {
         let RawAppSettings {
             run_count,
             customization,
-            auth,
-            selected_service,
-            openai_model,
+            profiles,
+            active_profile,
+            default_profile,
+            model_registry,
+            retry_policies,
+            summarizer,
+            usage_ledger,
+            spending_caps,
+            vault,
+            sync,
+            ..
         } = raw_app_settings;
         let name_to_configs = raw_agent_configs.name_to_configs.into_iter().map(|(k, v)| (k.into(), v)).collect();
+        let configs_updated_at = raw_agent_configs.updated_at.into_iter().map(|(k, v)| (k.into(), v)).collect();
         let chats = raw_chats.chats.into_iter().map(|c| c.into_chat(&name_to_configs)).collect();
-        StoredStates {
+        let states = StoredStates {
             run_count,
             customization,
             name_to_configs,
+            configs_updated_at,
             chats,
-            auth,
-            selected_service,
-            openai_model,
-        }
+            profiles,
+            active_profile,
+            default_profile,
+            model_registry,
+            retry_policies,
+            summarizer,
+            usage_ledger,
+            spending_caps,
+            vault: vault.map(Vault::locked),
+            sync,
+            storage,
+            saved: Default::default(),
+        };
+        states.mark_saved();
+        states
     }
 }
 
-impl From<StoredStates> for RawStoredStates {
-    fn from(value: StoredStates) -> Self {
-        let StoredStates {
-            run_count,
-            customization,
-            name_to_configs,
-            chats,
-            auth,
-            selected_service,
-            openai_model
-        } = value;
-        let raw_app_settings = RawAppSettings {
-            run_count,
-            customization,
-            auth,
-            selected_service,
-            openai_model,
-        };
-        let raw_agent_configs = RawAgentConfigs {
-            name_to_configs: name_to_configs.into_iter().map(|(k, v)| (k.into(), v)).collect(),
-        };
+impl TryFrom<StoredStates> for RawStoredStates {
+    type Error = String;
+
+    fn try_from(value: StoredStates) -> Result<Self, String> {
+        let raw_app_settings = value.raw_app_settings()?;
+        let raw_agent_configs = value.raw_agent_configs();
         let raw_chats = RawChats {
-            chats: chats.into_iter().map(|c| c.into()).collect(),
+            chats: value.chats.into_iter().map(|c| c.into()).collect(),
         };
-        Self {
+        Ok(Self {
             raw_app_settings,
             raw_chats,
             raw_agent_configs,
+        })
+    }
+}
+
+impl StoredStates {
+    /// Settings to store, whose API keys are sealed into the vault instead of being kept in the profiles if there is one
+    pub(crate) fn raw_app_settings(&self) -> Result<RawAppSettings, String> {
+        let mut profiles = self.profiles.clone();
+        let mut sync = self.sync.clone();
+        let vault = match self.vault.as_ref() {
+            Some(vault) => {
+                let secrets = Secrets {
+                    profiles: profiles
+                        .iter()
+                        .filter(|profile| !profile.auth.lacks_secret())
+                        .map(|profile| (profile.name.clone(), profile.auth.clone()))
+                        .collect(),
+                    sync_token: std::mem::take(&mut sync.token),
+                };
+                profiles.iter_mut().for_each(|profile| profile.auth.clear_secret());
+                Some(vault.seal(secrets)?)
+            }
+            None => None,
+        };
+        Ok(RawAppSettings {
+            run_count: self.run_count,
+            customization: self.customization.clone(),
+            profiles,
+            active_profile: self.active_profile.clone(),
+            default_profile: self.default_profile.clone(),
+            model_registry: self.model_registry.clone(),
+            retry_policies: self.retry_policies,
+            summarizer: self.summarizer.clone(),
+            usage_ledger: self.usage_ledger.clone(),
+            spending_caps: self.spending_caps,
+            vault,
+            sync,
+        })
+    }
+
+    pub(crate) fn raw_agent_configs(&self) -> RawAgentConfigs {
+        RawAgentConfigs {
+            name_to_configs: self.name_to_configs
+                .iter()
+                .map(|(k, v)| (k.clone().into(), v.clone()))
+                .collect(),
+            updated_at: self.configs_updated_at
+                .iter()
+                .map(|(k, v)| (k.clone().into(), *v))
+                .collect(),
         }
     }
 }
@@ -72,16 +131,20 @@ impl From<StoredStates> for RawStoredStates {
 impl From<Chat> for RawChat {
     fn from(value: Chat) -> Self {
         let Chat {
-            id, message_manager, topic, date, agents
+            id, message_manager, topic, date, agents, profile, revision: _, updated_at
         } = value;
         let agents = agents.into_iter().map(|(k, v)| (k.into(), v.into())).collect();
-        let messages = message_manager.messages.into_iter().map(|(k, v)| (k.into(), v)).collect();
+        let messages = message_manager.messages.into_iter().map(|(k, v)| (k.into(), v.into())).collect();
+        let summaries = message_manager.summaries.into_iter().map(|(k, v)| (k.into(), v.into())).collect();
         Self {
             id,
             messages,
+            summaries,
             topic,
             date,
             agents,
+            profile,
+            updated_at,
         }
     }
 }
@@ -89,22 +152,32 @@ impl From<Chat> for RawChat {
 impl RawChat {
     pub fn into_chat(self, name_to_configs: &HashMap<AgentName, AgentConfig>) -> Chat {
         let RawChat {
-            id, messages, topic, date, agents
+            id, messages, summaries, topic, date, agents, profile, updated_at
         } = self;
         let agents = agents
             .into_iter()
             .map(|(k, v)| (k.into(), v.into_agent_instance(name_to_configs)))
             .collect();
-        let messages = messages.into_iter().map(|(k, v)| (k.into(), v)).collect();
+        let messages = messages
+            .into_iter()
+            .map(|(k, v)| (k.into(), v.into()))
+            .collect();
+        let summaries = summaries.into_iter().map(|(k, v)| (k.into(), v.into())).collect();
 
         Chat {
             id,
             message_manager: MessageManager {
                 messages,
+                errors: Default::default(),
+                waiting: Default::default(),
+                summaries,
             },
             topic,
             date,
             agents,
+            profile,
+            revision: Chat::new_revision(),
+            updated_at,
         }
     }
 }
@@ -138,6 +211,110 @@ impl From<RawMessageID> for MessageID {
     }
 }
 
+impl Into<RawMessage> for Message {
+    fn into(self) -> RawMessage {
+        let Message { chat_msg, meta } = self;
+        let ChatMessage { role, name, content, tool_calls, tool_call_id } = chat_msg;
+        RawMessage {
+            role,
+            name,
+            content,
+            tool_calls,
+            tool_call_id,
+            meta,
+        }
+    }
+}
+
+impl From<RawMessage> for Message {
+    fn from(raw: RawMessage) -> Self {
+        let RawMessage { role, name, content, tool_calls, tool_call_id, meta } = raw;
+        Self {
+            chat_msg: ChatMessage {
+                role,
+                name,
+                content,
+                tool_calls,
+                tool_call_id,
+            },
+            meta,
+        }
+    }
+}
+
+/// Messages of older versions stored the request types of providers
+impl From<ChatCompletionRequestMessage> for ChatMessage {
+    fn from(msg: ChatCompletionRequestMessage) -> Self {
+        let text = |text: String| vec![ContentPart::Text { text }];
+        let (role, name, content, tool_calls, tool_call_id) = match msg {
+            ChatCompletionRequestMessage::System(msg) => (Role::System, msg.name, text(msg.content), vec![], None),
+            ChatCompletionRequestMessage::User(msg) => {
+                let content = match msg.content {
+                    ChatCompletionRequestUserMessageContent::Text(content) => text(content),
+                    ChatCompletionRequestUserMessageContent::Array(parts) => parts
+                        .into_iter()
+                        .map(|part| match part {
+                            ChatCompletionRequestMessageContentPart::Text(part) => ContentPart::Text { text: part.text },
+                            ChatCompletionRequestMessageContentPart::Image(part) => ContentPart::Image {
+                                url: part.image_url.url,
+                                detail: serde_json::to_value(part.image_url.detail)
+                                    .ok()
+                                    .and_then(|detail| detail.as_str().map(str::to_string)),
+                            },
+                        })
+                        .collect(),
+                };
+                (Role::User, msg.name, content, vec![], None)
+            }
+            ChatCompletionRequestMessage::Assistant(msg) => {
+                let tool_calls = msg.tool_calls
+                    .unwrap_or_default()
+                    .into_iter()
+                    .map(|call| ToolCall {
+                        id: Some(call.id),
+                        name: call.function.name,
+                        arguments: call.function.arguments,
+                    })
+                    .chain(msg.function_call.map(|call| ToolCall {
+                        id: None,
+                        name: call.name,
+                        arguments: call.arguments,
+                    }))
+                    .collect();
+                (Role::Assistant, msg.name, msg.content.map(text).unwrap_or_default(), tool_calls, None)
+            }
+            ChatCompletionRequestMessage::Tool(msg) => (Role::Tool, None, text(msg.content), vec![], Some(msg.tool_call_id)),
+            ChatCompletionRequestMessage::Function(msg) =>
+                (Role::Function, Some(msg.name), msg.content.map(text).unwrap_or_default(), vec![], None),
+        };
+        Self {
+            role,
+            name,
+            content,
+            tool_calls,
+            tool_call_id,
+        }
+    }
+}
+
+impl Into<RawHistorySummary> for HistorySummary {
+    fn into(self) -> RawHistorySummary {
+        RawHistorySummary {
+            message_id: self.message_id.into(),
+            covered: self.covered,
+        }
+    }
+}
+
+impl From<RawHistorySummary> for HistorySummary {
+    fn from(raw: RawHistorySummary) -> Self {
+        Self {
+            message_id: raw.message_id.into(),
+            covered: raw.covered,
+        }
+    }
+}
+
 impl Into<RawAgentInstance> for AgentInstance {
     fn into(self) -> RawAgentInstance {
         let AgentInstance { id, config, history } = self;
@@ -189,3 +366,74 @@ impl From<RawAgentName> for AgentName {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use crate::chat::MessageMeta;
+
+    use super::*;
+
+    fn tool_call(id: Option<&str>, name: &str) -> ToolCall {
+        ToolCall {
+            id: id.map(str::to_string),
+            name: name.to_string(),
+            arguments: r#"{"city": "Paris"}"#.to_string(),
+        }
+    }
+
+    fn messages() -> Vec<ChatMessage> {
+        vec![
+            ChatMessage::text_of(Role::System, "Be brief".to_string(), None),
+            ChatMessage::text_of(Role::User, "Hello".to_string(), Some("Alice".to_string())),
+            ChatMessage {
+                role: Role::User,
+                name: None,
+                content: vec![
+                    ContentPart::Text { text: "What is this?".to_string() },
+                    ContentPart::Image { url: "https://example.com/cat.png".to_string(), detail: Some("high".to_string()) },
+                ],
+                tool_calls: vec![],
+                tool_call_id: None,
+            },
+            ChatMessage {
+                role: Role::Assistant,
+                name: None,
+                content: vec![ContentPart::Text { text: "Let me look".to_string() }],
+                // the call without an ID is the function call of the older API, which comes after the tool calls
+                tool_calls: vec![tool_call(Some("call_1"), "weather"), tool_call(None, "forecast")],
+                tool_call_id: None,
+            },
+            ChatMessage {
+                role: Role::Tool,
+                name: None,
+                content: vec![ContentPart::Text { text: "Sunny".to_string() }],
+                tool_calls: vec![],
+                tool_call_id: Some("call_1".to_string()),
+            },
+        ]
+    }
+
+    #[test]
+    fn stores_messages_as_they_are() {
+        for chat_msg in messages() {
+            let message = Message {
+                chat_msg,
+                meta: MessageMeta {
+                    model: Some("gpt-4".to_string()),
+                    ..Default::default()
+                },
+            };
+            let raw: RawMessage = message.clone().into();
+            let json = serde_json::to_string(&raw).unwrap();
+            let raw: RawMessage = serde_json::from_str(&json).unwrap();
+            assert_eq!(Message::from(raw), message);
+        }
+    }
+
+    #[test]
+    fn converts_requests_back() {
+        for chat_msg in messages() {
+            assert_eq!(ChatMessage::from(chat_msg.to_request()), chat_msg);
+        }
+    }
+}
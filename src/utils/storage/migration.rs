@@ -0,0 +1,81 @@
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use serde_json::Value;
+
+/// Field of stored blobs that holds their schema version, which is missing in blobs of version 0
+const VERSION_FIELD: &str = "version";
+
+/// Upgrade a stored blob by one version
+pub(crate) type Migration = fn(&mut Value) -> Result<(), String>;
+
+/// Blobs stored before versions were kept, which only lack the version
+pub(crate) fn from_unversioned(_value: &mut Value) -> Result<(), String> {
+    Ok(())
+}
+
+/// Parse a stored blob, running the migrations from its version on
+pub(crate) fn upgrade<T: DeserializeOwned>(key: &str, raw: &str, migrations: &[Migration]) -> Result<T, String> {
+    let mut value: Value = serde_json::from_str(raw).map_err(|e| format!("invalid JSON: {}", e))?;
+    let version = value
+        .get(VERSION_FIELD)
+        .and_then(Value::as_u64)
+        .unwrap_or(0) as usize;
+    if version > migrations.len() {
+        return Err(format!("version {} is newer than version {} that this build knows", version, migrations.len()));
+    }
+    for (from, migration) in migrations.iter().enumerate().skip(version) {
+        log::info!("Migrating {} from version {} to {}", key, from, from + 1);
+        migration(&mut value).map_err(|e| format!("migration from version {} failed: {}", from, e))?;
+    }
+    serde_json::from_value(value).map_err(|e| e.to_string())
+}
+
+/// Serialize a state into a blob of `version`
+pub(crate) fn to_versioned_json<T: Serialize>(state: T, version: usize) -> Result<String, String> {
+    let value = to_versioned_value(state, version)?;
+    serde_json::to_string(&value).map_err(|e| e.to_string())
+}
+
+/// Serialize a state into the JSON value of a blob of `version`, for blobs embedded in other blobs
+pub(crate) fn to_versioned_value<T: Serialize>(state: T, version: usize) -> Result<Value, String> {
+    let mut value = serde_json::to_value(state).map_err(|e| e.to_string())?;
+    if let Some(fields) = value.as_object_mut() {
+        fields.insert(VERSION_FIELD.to_string(), version.into());
+    }
+    Ok(value)
+}
+
+#[cfg(test)]
+mod tests {
+    use serde::Deserialize;
+
+    use super::*;
+
+    #[derive(Deserialize, Debug, PartialEq)]
+    struct State {
+        count: u64,
+    }
+
+    fn double(value: &mut Value) -> Result<(), String> {
+        let count = value.get("count").and_then(Value::as_u64).ok_or("no count")?;
+        value["count"] = (count * 2).into();
+        Ok(())
+    }
+
+    const MIGRATIONS: &[Migration] = &[from_unversioned, double];
+
+    #[test]
+    fn runs_the_migrations_from_the_version_on() {
+        assert_eq!(upgrade::<State>("state", r#"{"count": 1}"#, MIGRATIONS), Ok(State { count: 2 }));
+        assert_eq!(upgrade::<State>("state", r#"{"count": 1, "version": 1}"#, MIGRATIONS), Ok(State { count: 2 }));
+        let current = to_versioned_json(serde_json::json!({ "count": 1 }), MIGRATIONS.len()).unwrap();
+        assert_eq!(upgrade::<State>("state", &current, MIGRATIONS), Ok(State { count: 1 }));
+    }
+
+    #[test]
+    fn refuses_newer_versions() {
+        assert!(upgrade::<State>("state", r#"{"count": 1, "version": 3}"#, MIGRATIONS).is_err());
+        assert!(upgrade::<State>("state", "not json", MIGRATIONS).is_err());
+        assert!(upgrade::<State>("state", r#"{"version": 1}"#, MIGRATIONS).is_err());
+    }
+}
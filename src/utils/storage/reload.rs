@@ -0,0 +1,163 @@
+use std::collections::HashSet;
+
+use uuid::Uuid;
+
+use crate::agents::AgentName;
+use crate::utils::storage::StoredStates;
+use crate::utils::storage::backend::{CHATS_STORE, STATES_STORE, StorageHandle};
+use crate::utils::storage::migration::upgrade;
+use crate::utils::vault::Vault;
+
+use super::schema::*;
+
+/// Records that another tab wrote into the storage, as they are now
+#[derive(Default)]
+pub struct ChangedRecords {
+    settings: Option<RawAppSettings>,
+    agent_configs: Option<RawAgentConfigs>,
+    chat_order: Option<Vec<Uuid>>,
+    /// chats that were put, or `None` for those deleted
+    chats: Vec<(Uuid, Option<RawChat>)>,
+}
+
+impl ChangedRecords {
+    /// Read what is now stored under (store, key) records, skipping those that are not states like backups
+    pub async fn load(storage: &StorageHandle, records: Vec<(String, String)>) -> Result<Self, String> {
+        let backend = storage.backend();
+        let mut changed = Self::default();
+        for (store, key) in records {
+            let raw = backend.get(&store, &key).await?;
+            match (store.as_str(), raw) {
+                (CHATS_STORE, raw) => {
+                    let id = Uuid::parse_str(&key).map_err(|e| format!("Invalid chat ID {}: {}", key, e))?;
+                    let raw_chat = raw
+                        .map(|raw| upgrade(&key, &raw, RawChat::MIGRATIONS))
+                        .transpose()?;
+                    changed.chats.push((id, raw_chat));
+                }
+                (STATES_STORE, Some(raw)) if key == RawAppSettings::STORE_KEY => {
+                    changed.settings = Some(RawAppSettings::parse(&raw)?);
+                }
+                (STATES_STORE, Some(raw)) if key == RawAgentConfigs::STORE_KEY => {
+                    changed.agent_configs = Some(RawAgentConfigs::parse(&raw)?);
+                }
+                (STATES_STORE, Some(raw)) if key == RawChatOrder::STORE_KEY => {
+                    changed.chat_order = Some(RawChatOrder::parse(&raw)?.chat_ids);
+                }
+                _ => {}
+            }
+        }
+        Ok(changed)
+    }
+}
+
+impl StoredStates {
+    /// Take what another tab saved, except where this tab has changes of its own that are not saved yet or is replying,
+    /// which are then saved over it later, along with the messages of the other tab for chats changed in both.
+    ///
+    /// Returns whether the settings were reloaded, which may change the credentials in use.
+    pub fn reload(&mut self, changed: ChangedRecords, replying_in: &HashSet<Uuid>) -> Result<bool, String> {
+        let ChangedRecords {
+            settings,
+            agent_configs,
+            chat_order,
+            chats,
+        } = changed;
+        let mut saved = self.saved.borrow().clone();
+        let settings_unsaved = self.raw_app_settings()?.to_json()? != saved.settings;
+        let agent_configs_unsaved = self.raw_agent_configs().to_json()? != saved.agent_configs;
+        let chat_order_unsaved = self.chats.iter().map(|chat| chat.id).ne(saved.chat_order.iter().copied());
+        // agents first, whose configs the chats are loaded with
+        if let Some(agent_configs) = agent_configs.filter(|_| !agent_configs_unsaved) {
+            let RawAgentConfigs { name_to_configs, updated_at } = agent_configs;
+            for (name, config) in name_to_configs {
+                let updated_at = updated_at.get(&name).copied().unwrap_or_default();
+                let name: AgentName = name.into();
+                if self.name_to_configs.get(&name) != Some(&config) {
+                    self.put_agent_config(config, updated_at);
+                }
+            }
+            saved.agent_configs = self.raw_agent_configs().to_json()?;
+        }
+        let settings_reloaded = match settings.filter(|_| !settings_unsaved) {
+            Some(settings) => {
+                self.take_settings(settings);
+                saved.settings = self.raw_app_settings()?.to_json()?;
+                true
+            }
+            None => false,
+        };
+        let mut changes = vec![];
+        for (id, raw_chat) in chats {
+            let idx = self.chats.iter().position(|chat| chat.id == id);
+            let changed_here = |idx: &usize| saved.chats.get(&id) != Some(&self.chats[*idx].revision) || replying_in.contains(&id);
+            let Some(idx) = idx.filter(changed_here) else {
+                changes.push((id, raw_chat));
+                continue;
+            };
+            // the merge is saved over what the other tab saved, and a chat deleted there stays with the changes of this one
+            match raw_chat {
+                Some(raw_chat) => {
+                    let taken = self.chats[idx].merge_messages(raw_chat.into_chat(&self.name_to_configs));
+                    log::warn!("Chat {} changed in another tab and in this one, merged {} messages of the other tab", id, taken);
+                }
+                None => log::warn!("Chat {} was deleted in another tab and changed in this one, keeping it", id),
+            }
+        }
+        for id in self.apply_chat_changes(changes) {
+            match self.chats.iter().find(|chat| chat.id == id) {
+                Some(chat) => saved.chats.insert(id, chat.revision),
+                None => saved.chats.remove(&id),
+            };
+        }
+        if let Some(chat_order) = chat_order.filter(|_| !chat_order_unsaved) {
+            // chats that are not in the order yet, like those of a save to come, go last
+            self.chats.sort_by_key(|chat| chat_order
+                .iter()
+                .position(|id| *id == chat.id)
+                .unwrap_or(usize::MAX));
+            saved.chat_order = self.chats.iter().map(|chat| chat.id).collect();
+        }
+        *self.saved.borrow_mut() = saved;
+        Ok(settings_reloaded)
+    }
+
+    /// Take the settings another tab saved, keeping the API keys if they are sealed with the key unlocked here
+    fn take_settings(&mut self, settings: RawAppSettings) {
+        let RawAppSettings {
+            run_count,
+            customization,
+            profiles,
+            active_profile,
+            default_profile,
+            model_registry,
+            retry_policies,
+            summarizer,
+            usage_ledger,
+            spending_caps,
+            vault,
+            sync,
+        } = settings;
+        self.run_count = run_count;
+        self.customization = customization;
+        self.profiles = profiles;
+        self.active_profile = active_profile;
+        self.default_profile = default_profile;
+        self.model_registry = model_registry;
+        self.retry_policies = retry_policies;
+        self.summarizer = summarizer;
+        self.usage_ledger = usage_ledger;
+        self.spending_caps = spending_caps;
+        self.sync = sync;
+        self.vault = match (self.vault.take(), vault) {
+            (Some(mut in_use), Some(vault)) => {
+                in_use.reload(vault);
+                Some(in_use)
+            }
+            (_, vault) => vault.map(Vault::locked),
+        };
+        if let Some(secrets) = self.vault.as_ref().filter(|vault| !vault.is_locked()).map(Vault::secrets) {
+            self.fill_secrets(&secrets);
+        }
+    }
+}
@@ -0,0 +1,201 @@
+use std::collections::HashSet;
+
+use chrono::Utc;
+use serde_json::Value;
+use uuid::Uuid;
+
+use crate::agents::{AgentConfig, AgentName};
+use crate::chat::Chat;
+use crate::utils::storage::StoredStates;
+use crate::utils::storage::migration::{to_versioned_value, upgrade};
+use crate::utils::sync::{digest, ledger_key, parse_ledger_key};
+use crate::utils::sync::protocol::{ItemKind, SyncItem, SyncRequest, SyncResponse};
+
+use super::schema::*;
+
+/// A sync on its way to the server
+pub struct PendingSync {
+    pub request: SyncRequest,
+    /// ledger key and digest of each pushed item, or `None` for deleted chats, which are synced once the server has them
+    pushed: Vec<(String, Option<u64>)>,
+}
+
+impl StoredStates {
+    /// The items changed since the last sync, or none for a sync that only pulls
+    pub fn sync_request(&self, pull_only: bool) -> Result<PendingSync, String> {
+        if self.vault_locked() {
+            return Err("Unlock the API keys to sync, since the sync token is kept with them".to_string());
+        }
+        let mut items = vec![];
+        let mut pushed = vec![];
+        if !pull_only {
+            let device = self.sync.device;
+            let mut local = HashSet::new();
+            for (kind, key, value, updated_at) in self.sync_items()? {
+                let ledger_key = ledger_key(kind, &key);
+                let digest = digest(&value);
+                if self.sync.synced.get(&ledger_key) != Some(&digest) {
+                    pushed.push((ledger_key.clone(), Some(digest)));
+                    items.push(SyncItem { kind, key, value: Some(value), updated_at, device, revision: 0 });
+                }
+                local.insert(ledger_key);
+            }
+            // chats synced before and gone since were deleted here, while agents are never deleted
+            for ledger_key in self.sync.synced.keys().filter(|ledger_key| !local.contains(*ledger_key)) {
+                if let Some((ItemKind::Chat, key)) = parse_ledger_key(ledger_key) {
+                    // chats deleted before the time was kept count as deleted now
+                    let updated_at = self.sync.deleted_at
+                        .get(ledger_key)
+                        .copied()
+                        .unwrap_or_else(|| Utc::now().timestamp_millis());
+                    pushed.push((ledger_key.clone(), None));
+                    items.push(SyncItem { kind: ItemKind::Chat, key: key.to_string(), value: None, updated_at, device, revision: 0 });
+                }
+            }
+        }
+        Ok(PendingSync {
+            request: SyncRequest {
+                since: self.sync.revision,
+                items,
+            },
+            pushed,
+        })
+    }
+
+    /// Take what the server has, except for items changed here while syncing and chats replied in here,
+    /// which are pushed next time.
+    ///
+    /// Returns how many items were pulled.
+    pub fn apply_sync(&mut self, pending: PendingSync, response: SyncResponse, replying_in: &HashSet<Uuid>) -> Result<usize, String> {
+        for (ledger_key, digest) in pending.pushed {
+            match digest {
+                Some(digest) => self.sync.synced.insert(ledger_key, digest),
+                None => {
+                    self.sync.deleted_at.remove(&ledger_key);
+                    self.sync.synced.remove(&ledger_key)
+                }
+            };
+        }
+        // agents first, whose configs the chats are loaded with
+        let (agent_configs, chats): (Vec<SyncItem>, Vec<SyncItem>) = response.items
+            .into_iter()
+            .partition(|item| item.kind == ItemKind::AgentConfig);
+        let mut pulled = 0;
+        for item in agent_configs {
+            let Some(value) = item.value else {
+                continue;
+            };
+            let name: AgentName = item.key.clone().into();
+            let ledger_key = ledger_key(ItemKind::AgentConfig, &item.key);
+            if let Some(config) = self.name_to_configs.get(&name) {
+                if self.changed_since_synced(&ledger_key, &config_value(config)?) {
+                    log::warn!("Agent {} changed while syncing, keeping the changes of this device", item.key);
+                    continue;
+                }
+            }
+            let config: AgentConfig = match serde_json::from_value(value) {
+                Ok(config) => config,
+                Err(e) => {
+                    log::error!("Error when loading agent {} from the sync server: {}", item.key, e);
+                    continue;
+                }
+            };
+            self.sync.synced.insert(ledger_key, digest(&config_value(&config)?));
+            self.put_agent_config(config, item.updated_at);
+            pulled += 1;
+        }
+        let mut changes = vec![];
+        for item in chats {
+            let Ok(id) = Uuid::parse_str(&item.key) else {
+                log::error!("Invalid chat ID {} from the sync server", item.key);
+                continue;
+            };
+            let ledger_key = ledger_key(ItemKind::Chat, &item.key);
+            if let Some(chat) = self.chats.iter().find(|chat| chat.id == id) {
+                if replying_in.contains(&id) || self.changed_since_synced(&ledger_key, &chat_value(chat)?) {
+                    log::warn!("Chat {} changed while syncing, keeping the changes of this device", item.key);
+                    continue;
+                }
+            }
+            let Some(value) = item.value else {
+                changes.push((id, None));
+                continue;
+            };
+            let raw_chat: RawChat = match upgrade(&item.key, &value.to_string(), RawChat::MIGRATIONS) {
+                Ok(raw_chat) => raw_chat,
+                Err(e) => {
+                    log::error!("Error when loading chat {} from the sync server: {}", item.key, e);
+                    continue;
+                }
+            };
+            // chats are loaded with the configs of their agents, which may not have been pulled
+            let lacks_config = |agent: &RawAgentInstance| !self.name_to_configs.contains_key(&agent.name);
+            if let Some(agent) = raw_chat.agents.values().find(|agent| lacks_config(agent)) {
+                log::error!("Chat {} from the sync server has agent {} without a config", item.key, agent.name);
+                continue;
+            }
+            changes.push((id, Some(raw_chat)));
+        }
+        let deleted: Vec<Uuid> = changes
+            .iter()
+            .filter(|(_, raw_chat)| raw_chat.is_none())
+            .map(|(id, _)| *id)
+            .collect();
+        let applied = self.apply_chat_changes(changes);
+        // the last chat is kept, which then changes now so that it wins over the deletion when it is pushed back
+        for id in deleted.iter().filter(|id| !applied.contains(id)) {
+            if let Some(chat) = self.chats.iter_mut().find(|chat| chat.id == *id) {
+                log::warn!("Chat {} was deleted on the sync server but is the last chat here, pushing it back", id);
+                chat.touch();
+                self.sync.synced.remove(&ledger_key(ItemKind::Chat, &id.to_string()));
+            }
+        }
+        for id in applied {
+            let ledger_key = ledger_key(ItemKind::Chat, &id.to_string());
+            match self.chats.iter().find(|chat| chat.id == id) {
+                Some(chat) => {
+                    let digest = digest(&chat_value(chat)?);
+                    self.sync.synced.insert(ledger_key, digest);
+                }
+                None => {
+                    self.sync.synced.remove(&ledger_key);
+                }
+            }
+            pulled += 1;
+        }
+        self.sync.revision = response.revision;
+        Ok(pulled)
+    }
+
+    /// Whether an item changed after it was last synced, unlike items never synced, which the server has the say on
+    fn changed_since_synced(&self, ledger_key: &str, value: &Value) -> bool {
+        self.sync.synced
+            .get(ledger_key)
+            .is_some_and(|synced| *synced != digest(value))
+    }
+
+    /// (kind, key, value, when it last changed) of the chats and agent configs as they are synced
+    fn sync_items(&self) -> Result<Vec<(ItemKind, String, Value, i64)>, String> {
+        let mut items = vec![];
+        for (name, config) in self.name_to_configs.iter() {
+            let updated_at = self.configs_updated_at.get(name).copied().unwrap_or_default();
+            let name: RawAgentName = name.clone().into();
+            items.push((ItemKind::AgentConfig, name, config_value(config)?, updated_at));
+        }
+        for chat in self.chats.iter() {
+            items.push((ItemKind::Chat, chat.id.to_string(), chat_value(chat)?, chat.updated_at));
+        }
+        Ok(items)
+    }
+}
+
+/// An agent config as it is synced, which is not versioned on its own but as a part of `RawAgentConfigs`
+fn config_value(config: &AgentConfig) -> Result<Value, String> {
+    serde_json::to_value(config).map_err(|e| e.to_string())
+}
+
+/// A chat as it is synced, which is the versioned blob it is stored as
+fn chat_value(chat: &Chat) -> Result<Value, String> {
+    let raw_chat: RawChat = chat.clone().into();
+    to_versioned_value(raw_chat, RawChat::MIGRATIONS.len())
+}
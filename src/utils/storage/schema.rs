@@ -3,16 +3,23 @@ use std::collections::HashMap;
 use gloo_storage::{LocalStorage, Storage};
 use serde::{Deserialize, Serialize};
 use serde::de::DeserializeOwned;
-use transprompt::utils::llm::openai::ChatMsg;
+use serde_json::{Map, Value};
+use transprompt::async_openai::types::ChatCompletionRequestMessage;
 use uuid::Uuid;
 
 use crate::agents::{AgentConfig, AgentID, AgentName};
-use crate::chat::Chat;
+use crate::chat::{Chat, ContentPart, Message, MessageMeta, Role, ToolCall};
 use crate::utils::auth::Auth;
 use crate::utils::customization::Customization;
 use crate::utils::datetime::DatetimeString;
-use crate::utils::settings::{GPTService, OpenAIModel};
+use crate::utils::models::ModelRegistry;
+use crate::utils::settings::{GPTService, ProviderProfile, RetryPolicies, SummarizerSettings};
 use crate::utils::storage::StoredStates;
+use crate::utils::storage::backend::{CHATS_STORE, Record, STATES_STORE, StorageBackend, StorageHandle};
+use crate::utils::storage::migration::{from_unversioned, Migration, to_versioned_json, upgrade};
+use crate::utils::usage::{SpendingCaps, Usage, UsageLedger};
+use crate::utils::sync::SyncSettings;
+use crate::utils::vault::SealedVault;
 
 pub(crate) type UUIDKey = String;
 pub(crate) type UUIDString = String;
@@ -25,10 +32,44 @@ pub(crate) type RawAgentName = String;
 #[derive(Serialize, Deserialize, Clone)]
 pub(crate) struct RawChat {
     pub(crate) id: Uuid,
-    pub messages: HashMap<RawMessageID, ChatMsg>,
+    pub messages: HashMap<RawMessageID, RawMessage>,
+    #[serde(default, skip_serializing_if = "HashMap::is_empty")]
+    pub summaries: HashMap<RawAgentID, RawHistorySummary>,
     pub topic: String,
     pub date: DatetimeString,
     pub agents: HashMap<RawAgentID, RawAgentInstance>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub profile: Option<String>,
+    /// milliseconds since the epoch, 0 for chats stored before it was kept
+    #[serde(default)]
+    pub updated_at: i64,
+}
+
+/// A stored message.
+///
+/// Messages are stored in a schema of chitchai so that saved chats do not break when the request types of providers change.
+/// Older versions stored the request types directly, which `messages_into_schema` converts.
+#[derive(Serialize, Deserialize, Clone)]
+pub(crate) struct RawMessage {
+    pub role: Role,
+    /// name of the author as sent to providers, `None` for the default user and assistant
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub name: Option<String>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub content: Vec<ContentPart>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub tool_calls: Vec<ToolCall>,
+    /// the call that a tool message answers
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tool_call_id: Option<String>,
+    #[serde(default)]
+    pub meta: MessageMeta,
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+pub(crate) struct RawHistorySummary {
+    pub message_id: RawMessageID,
+    pub covered: usize,
 }
 
 #[derive(Serialize, Deserialize, Clone)]
@@ -38,14 +79,35 @@ pub(crate) struct RawAgentInstance {
     pub history: RawLinkedChatHistory,
 }
 
-pub(crate) trait StoredState: Serialize + DeserializeOwned {
+pub(crate) trait StoredState: Serialize + DeserializeOwned + Clone {
     const STORE_KEY: &'static str;
-    fn get_or_init() -> Self;
-    fn save(self) {
-        match LocalStorage::set(Self::STORE_KEY, self) {
-            Ok(_) => log::info!("Saved StoredState with key {}", Self::STORE_KEY),
-            Err(e) => log::error!("Error when saving StoredState with key {}: {}",  Self::STORE_KEY, e),
+    /// `MIGRATIONS[i]` upgrades a blob of version `i` to version `i + 1`, so the current version is their count
+    const MIGRATIONS: &'static [Migration];
+
+    /// The state to start with when nothing is stored
+    fn init() -> Self;
+
+    /// Parse a stored blob, migrating it if it is of an older version
+    fn parse(raw: &str) -> Result<Self, String> {
+        upgrade(Self::STORE_KEY, raw, Self::MIGRATIONS)
+    }
+
+    /// Serialize into a blob of the current version
+    fn to_json(self) -> Result<String, String> {
+        to_versioned_json(self, Self::MIGRATIONS.len())
+    }
+
+    /// The blob that older versions kept under the store key in LocalStorage, if any
+    fn read_legacy() -> Option<String> {
+        if !cfg!(target_arch = "wasm32") {
+            return None;
         }
+        LocalStorage::raw()
+            .get_item(Self::STORE_KEY)
+            .unwrap_or_else(|e| {
+                log::error!("Error when reading StoredState with key {} from LocalStorage: {:?}", Self::STORE_KEY, e);
+                None
+            })
     }
 }
 
@@ -53,32 +115,95 @@ pub(crate) trait StoredState: Serialize + DeserializeOwned {
 pub(crate) struct RawAppSettings {
     pub run_count: usize,
     pub customization: Customization,
-    #[serde(skip_serializing_if = "Option::is_none")]
-    pub auth: Option<Auth>,
-    #[serde(skip_serializing_if = "Option::is_none")]
-    pub selected_service: Option<GPTService>,
-    #[serde(skip_serializing_if = "Option::is_none")]
-    pub openai_model: Option<OpenAIModel>,
+    #[serde(default)]
+    pub profiles: Vec<ProviderProfile>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub active_profile: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub default_profile: Option<String>,
+    #[serde(default)]
+    pub model_registry: ModelRegistry,
+    #[serde(default)]
+    pub retry_policies: RetryPolicies,
+    #[serde(default)]
+    pub summarizer: SummarizerSettings,
+    #[serde(default)]
+    pub usage_ledger: UsageLedger,
+    #[serde(default)]
+    pub spending_caps: SpendingCaps,
+    /// API keys of the profiles encrypted with a passphrase, which are then left out of `profiles`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub vault: Option<SealedVault>,
+    #[serde(default)]
+    pub sync: SyncSettings,
+}
+
+/// Settings of older versions had a single service instead of profiles, which becomes the first profile
+fn profiles_from_legacy_auth(settings: &mut Value) -> Result<(), String> {
+    let settings = settings.as_object_mut().ok_or("settings are not an object")?;
+    let openai_model = settings.remove("openai_model");
+    let provider_model = settings.remove("provider_model");
+    let Some(auth) = settings.remove("auth").filter(|auth| !auth.is_null()) else {
+        return Ok(());
+    };
+    let auth: Auth = serde_json::from_value(auth).map_err(|e| format!("invalid auth: {}", e))?;
+    let service = auth.service();
+    let name = service.to_string();
+    let model_of = |model: Option<Value>| model.and_then(|m| m.as_str().map(str::to_string));
+    let model = match service {
+        GPTService::AzureOpenAI => None,
+        // the model used to be stored as a variant of the removed `OpenAIModel` enum
+        GPTService::OpenAI => model_of(openai_model).map(|m| match m.as_str() {
+            "GPT35" => "gpt-3.5-turbo".to_string(),
+            "GPT35_16k" => "gpt-3.5-turbo-16k".to_string(),
+            "GPT4" => "gpt-4".to_string(),
+            "GPT4_32k" => "gpt-4-32k".to_string(),
+            _ => m,
+        }),
+        _ => model_of(provider_model),
+    };
+    log::info!("Migrating the settings of {} into a profile", name);
+    for key in ["active_profile", "default_profile"] {
+        if settings.get(key).map_or(true, Value::is_null) {
+            settings.insert(key.to_string(), name.clone().into());
+        }
+    }
+    let profiles = settings
+        .entry("profiles")
+        .or_insert_with(|| Value::Array(vec![]))
+        .as_array_mut()
+        .ok_or("profiles are not an array")?;
+    if profiles.iter().all(|p| p.get("name").and_then(Value::as_str) != Some(name.as_str())) {
+        let profile = ProviderProfile {
+            name,
+            auth,
+            model,
+            parameters: Default::default(),
+            limits: Default::default(),
+        };
+        profiles.push(serde_json::to_value(profile).map_err(|e| e.to_string())?);
+    }
+    Ok(())
 }
 
 impl StoredState for RawAppSettings {
     const STORE_KEY: &'static str = "chitchai_settings";
+    const MIGRATIONS: &'static [Migration] = &[from_unversioned, profiles_from_legacy_auth];
 
-    fn get_or_init() -> Self {
-        match LocalStorage::get::<RawAppSettings>(Self::STORE_KEY) {
-            Ok(settings) => settings,
-            Err(e) => {
-                log::error!("error on init RawAppSettings: {}", e);
-                let raw_app_settings = RawAppSettings {
-                    run_count: 0,
-                    customization: Default::default(),
-                    auth: None,
-                    selected_service: None,
-                    openai_model: None,
-                };
-                raw_app_settings.clone().save();
-                raw_app_settings
-            }
+    fn init() -> Self {
+        RawAppSettings {
+            run_count: 0,
+            customization: Default::default(),
+            profiles: vec![],
+            active_profile: None,
+            default_profile: None,
+            model_registry: Default::default(),
+            retry_policies: Default::default(),
+            summarizer: Default::default(),
+            usage_ledger: Default::default(),
+            spending_caps: Default::default(),
+            vault: None,
+            sync: Default::default(),
         }
     }
 }
@@ -90,45 +215,140 @@ pub(crate) struct RawChats {
 
 impl StoredState for RawChats {
     const STORE_KEY: &'static str = "chitchai_chats";
-    fn get_or_init() -> Self {
-        match LocalStorage::get::<RawChats>(Self::STORE_KEY) {
-            Ok(value) => value,
-            Err(e) => {
-                log::error!("error on init RawChats: {}", e);
-                let (mut default_chat, _name_to_configs) = Chat::default_chat_and_configs();
-                default_chat.topic = "Default Chat".to_string();
-                let raw_chats = vec![default_chat.into()];
-                let raw_chats = RawChats { chats: raw_chats };
-                raw_chats.clone().save();
-                raw_chats
+    const MIGRATIONS: &'static [Migration] = &[from_unversioned, chats_messages_into_schema];
+
+    fn init() -> Self {
+        let (mut default_chat, _name_to_configs) = Chat::default_chat_and_configs();
+        default_chat.topic = "Default Chat".to_string();
+        RawChats { chats: vec![default_chat.into()] }
+    }
+}
+
+impl RawChat {
+    /// Migrations of the records of single chats in IndexedDB, like `StoredState::MIGRATIONS`
+    pub(crate) const MIGRATIONS: &'static [Migration] = &[from_unversioned, messages_into_schema];
+}
+
+/// Chats of older versions stored the request types of providers as messages, either bare or in an envelope with their metadata,
+/// and kept the models and usages of replies beside them
+fn messages_into_schema(chat: &mut Value) -> Result<(), String> {
+    let chat = chat.as_object_mut().ok_or("a chat is not an object")?;
+    let mut answered_by: HashMap<RawMessageID, String> = take_legacy(chat, "answered_by")?;
+    let mut usages: HashMap<RawMessageID, Usage> = take_legacy(chat, "usages")?;
+    let Some(messages) = chat.get_mut("messages").and_then(Value::as_object_mut) else {
+        return Ok(());
+    };
+    for (id, message) in messages.iter_mut() {
+        let stored = message.get("role").is_some();
+        let mut raw = match stored {
+            true => serde_json::from_value::<RawMessage>(message.take()).map_err(|e| e.to_string()),
+            false => legacy_message(message.take()),
+        }.map_err(|e| format!("invalid message {}: {}", id, e))?;
+        // "profile · model" or "model"
+        if let Some(label) = answered_by.remove(id) {
+            match label.split_once(" · ") {
+                Some((profile, model)) => {
+                    raw.meta.profile = Some(profile.to_string());
+                    raw.meta.model = Some(model.to_string());
+                }
+                None => raw.meta.model = Some(label),
             }
         }
+        if let Some(usage) = usages.remove(id) {
+            raw.meta.usage = Some(usage);
+        }
+        *message = serde_json::to_value(raw).map_err(|e| e.to_string())?;
+    }
+    Ok(())
+}
+
+/// A message of older versions, which is `{chat_msg, meta}` or the bare `chat_msg`, whose `msg` is the request type
+fn legacy_message(mut message: Value) -> Result<RawMessage, String> {
+    let meta = match message.get_mut("meta").map(Value::take) {
+        Some(meta) => serde_json::from_value(meta).map_err(|e| e.to_string())?,
+        None => MessageMeta::default(),
+    };
+    let chat_msg = match message.get_mut("chat_msg") {
+        Some(chat_msg) => chat_msg.take(),
+        None => message,
+    };
+    let msg: ChatCompletionRequestMessage = serde_json::from_value(chat_msg.get("msg").cloned().unwrap_or_default())
+        .map_err(|e| e.to_string())?;
+    Ok(Message { chat_msg: msg.into(), meta }.into())
+}
+
+fn take_legacy<T: DeserializeOwned + Default>(fields: &mut Map<String, Value>, key: &str) -> Result<T, String> {
+    match fields.remove(key) {
+        Some(value) => serde_json::from_value(value).map_err(|e| format!("invalid {}: {}", key, e)),
+        None => Ok(T::default()),
+    }
+}
+
+/// Chats of older versions, which were kept in a single blob, see `messages_into_schema`
+fn chats_messages_into_schema(chats: &mut Value) -> Result<(), String> {
+    chats
+        .get_mut("chats")
+        .and_then(Value::as_array_mut)
+        .ok_or("chats are not an array")?
+        .iter_mut()
+        .try_for_each(messages_into_schema)
+}
+
+/// Order of the chats, whose records are kept apart in IndexedDB
+#[derive(Serialize, Deserialize, Clone)]
+pub(crate) struct RawChatOrder {
+    pub chat_ids: Vec<Uuid>,
+}
+
+impl StoredState for RawChatOrder {
+    const STORE_KEY: &'static str = "chitchai_chat_order";
+    const MIGRATIONS: &'static [Migration] = &[];
+
+    fn init() -> Self {
+        RawChatOrder { chat_ids: vec![] }
     }
 }
 
 #[derive(Serialize, Deserialize, Clone)]
 pub(crate) struct RawAgentConfigs {
     pub name_to_configs: HashMap<RawAgentName, AgentConfig>,
+    /// when each config last changed in milliseconds since the epoch, which decides between its copies on the sync server
+    #[serde(default, skip_serializing_if = "HashMap::is_empty")]
+    pub updated_at: HashMap<RawAgentName, i64>,
 }
 
 impl StoredState for RawAgentConfigs {
     const STORE_KEY: &'static str = "chitchai_agent_configs";
-    fn get_or_init() -> Self {
-        match LocalStorage::get::<RawAgentConfigs>(Self::STORE_KEY) {
-            Ok(configs) => configs,
-            Err(e) => {
-                log::error!("error on init RawAgentConfigs: {}", e);
-                let (_default_chat, name_to_configs) = Chat::default_chat_and_configs();
-                let name_to_configs = name_to_configs.into_iter().map(|(k, v)| (k.into(), v)).collect();
-                let raw_agent_configs = RawAgentConfigs { name_to_configs };
-                raw_agent_configs.clone().save();
-                raw_agent_configs
-            }
+    const MIGRATIONS: &'static [Migration] = &[from_unversioned];
+
+    fn init() -> Self {
+        let (_default_chat, name_to_configs) = Chat::default_chat_and_configs();
+        let name_to_configs = name_to_configs.into_iter().map(|(k, v)| (k.into(), v)).collect();
+        RawAgentConfigs {
+            name_to_configs,
+            updated_at: HashMap::new(),
         }
     }
 }
 
+/// Blobs that older versions kept in LocalStorage under the store keys, all states in three blobs
+pub(crate) struct LegacyBlobs {
+    pub settings: Option<String>,
+    pub chats: Option<String>,
+    pub agent_configs: Option<String>,
+}
+
+impl LegacyBlobs {
+    fn read() -> Self {
+        Self {
+            settings: RawAppSettings::read_legacy(),
+            chats: RawChats::read_legacy(),
+            agent_configs: RawAgentConfigs::read_legacy(),
+        }
+    }
+}
 
+#[derive(Clone)]
 pub(crate) struct RawStoredStates {
     pub raw_app_settings: RawAppSettings,
     pub raw_chats: RawChats,
@@ -136,38 +356,180 @@ pub(crate) struct RawStoredStates {
 }
 
 impl RawStoredStates {
-    pub fn get_or_init() -> StoredStates {
-        let raw_app_settings = RawAppSettings::get_or_init();
-        let raw_chats = RawChats::get_or_init();
-        let raw_agent_configs = RawAgentConfigs::get_or_init();
-        let name_to_configs = raw_agent_configs.name_to_configs.into_iter().map(|(k, v)| (k.into(), v)).collect();
-        let chats = raw_chats.chats.into_iter().map(|c| c.into_chat(&name_to_configs)).collect();
-        let RawAppSettings {
-            run_count,
-            customization,
-            auth,
-            selected_service,
-            openai_model,
-        } = raw_app_settings;
-        StoredStates {
-            run_count,
-            customization,
-            name_to_configs,
-            chats,
-            auth,
-            selected_service,
-            openai_model,
+    pub fn init() -> Self {
+        RawStoredStates {
+            raw_app_settings: RawAppSettings::init(),
+            raw_chats: RawChats::init(),
+            raw_agent_configs: RawAgentConfigs::init(),
+        }
+    }
+
+    /// Load the states from a storage, moving them over from the LocalStorage keys of older versions on the first run
+    pub async fn get_or_init(storage: &StorageHandle) -> Result<Self, String> {
+        let backend = storage.backend();
+        if let Some(raw) = Self::load_from(backend).await? {
+            return Ok(raw);
+        }
+        let legacy = LegacyBlobs::read();
+        let moving = legacy.settings.is_some() || legacy.chats.is_some() || legacy.agent_configs.is_some();
+        let raw = Self::init_from(backend, legacy).await?;
+        if moving {
+            log::info!("Moved the states from LocalStorage into {}", backend.name());
+            [RawAppSettings::STORE_KEY, RawChats::STORE_KEY, RawAgentConfigs::STORE_KEY]
+                .iter()
+                .for_each(|key| LocalStorage::delete(key));
+        }
+        Ok(raw)
+    }
+
+    /// Start the states in a backend that holds none yet, from the blobs of older versions if there are any
+    pub(crate) async fn init_from(backend: &dyn StorageBackend, legacy: LegacyBlobs) -> Result<Self, String> {
+        let raw = RawStoredStates {
+            raw_app_settings: parse_record(backend, legacy.settings).await,
+            raw_chats: parse_record(backend, legacy.chats).await,
+            raw_agent_configs: parse_record(backend, legacy.agent_configs).await,
+        };
+        backend.write(raw.clone().into_records()?, vec![]).await?;
+        Ok(raw)
+    }
+
+    /// Load the states from a backend, or `None` if nothing is stored there yet
+    async fn load_from(backend: &dyn StorageBackend) -> Result<Option<Self>, String> {
+        let Some(settings) = backend.get(STATES_STORE, RawAppSettings::STORE_KEY).await? else {
+            return Ok(None);
+        };
+        let raw_app_settings: RawAppSettings = parse_record(backend, Some(settings)).await;
+        let agent_configs = backend.get(STATES_STORE, RawAgentConfigs::STORE_KEY).await?;
+        let raw_agent_configs: RawAgentConfigs = parse_record(backend, agent_configs).await;
+        let chat_order = backend.get(STATES_STORE, RawChatOrder::STORE_KEY).await?;
+        let chat_order: RawChatOrder = parse_record(backend, chat_order).await;
+        let mut records: HashMap<String, String> = backend.get_all(CHATS_STORE).await?.into_iter().collect();
+        let mut chats = Vec::with_capacity(chat_order.chat_ids.len());
+        for id in chat_order.chat_ids {
+            let key = id.to_string();
+            let Some(raw) = records.remove(&key) else {
+                log::warn!("Chat {} is missing in {}", key, backend.name());
+                continue;
+            };
+            match upgrade(&key, &raw, RawChat::MIGRATIONS) {
+                Ok(chat) => chats.push(chat),
+                Err(e) => {
+                    log::error!("Error when loading chat {}: {}", key, e);
+                    backend.back_up(&key, raw).await;
+                }
+            }
         }
+        let raw_chats = if chats.is_empty() {
+            RawChats::init()
+        } else {
+            RawChats { chats }
+        };
+        Ok(Some(RawStoredStates {
+            raw_app_settings,
+            raw_chats,
+            raw_agent_configs,
+        }))
     }
 
-    pub fn save(self) {
+    /// Records of the states in a backend
+    fn into_records(self) -> Result<Vec<Record>, String> {
         let RawStoredStates {
             raw_app_settings,
             raw_chats,
             raw_agent_configs,
         } = self;
-        raw_app_settings.save();
-        raw_chats.save();
-        raw_agent_configs.save();
+        let chat_order = RawChatOrder {
+            chat_ids: raw_chats.chats.iter().map(|chat| chat.id).collect(),
+        };
+        let mut records = vec![
+            (STATES_STORE, RawAppSettings::STORE_KEY.to_string(), raw_app_settings.to_json()?),
+            (STATES_STORE, RawAgentConfigs::STORE_KEY.to_string(), raw_agent_configs.to_json()?),
+            (STATES_STORE, RawChatOrder::STORE_KEY.to_string(), chat_order.to_json()?),
+        ];
+        for chat in raw_chats.chats {
+            records.push((CHATS_STORE, chat.id.to_string(), to_versioned_json(chat, RawChat::MIGRATIONS.len())?));
+        }
+        Ok(records)
+    }
+}
+
+/// Parse a stored record, backing it up and starting over if it cannot be loaded
+async fn parse_record<T: StoredState>(backend: &dyn StorageBackend, raw: Option<String>) -> T {
+    let Some(raw) = raw else {
+        return T::init();
+    };
+    match T::parse(&raw) {
+        Ok(state) => state,
+        Err(e) => {
+            log::error!("Error when loading StoredState with key {}: {}", T::STORE_KEY, e);
+            backend.back_up(T::STORE_KEY, raw).await;
+            T::init()
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use futures::executor::block_on;
+    use serde_json::json;
+
+    use crate::utils::storage::backend::Memory;
+
+    use super::*;
+
+    /// The settings of older versions, which had a single service and kept no version
+    fn legacy_settings() -> String {
+        let mut settings = serde_json::to_value(RawAppSettings::init()).unwrap();
+        settings["auth"] = json!({ "OpenAI": { "api_key": "sk-test" } });
+        settings["openai_model"] = json!("GPT4");
+        settings.to_string()
+    }
+
+    /// Chats of older versions, whose messages were the request types of providers
+    fn legacy_chats(message_id: &str) -> String {
+        let mut chats = serde_json::to_value(RawChats::init()).unwrap();
+        let chat = &mut chats["chats"][0];
+        chat["messages"] = json!({ message_id: { "msg": { "role": "user", "content": "Hello" } } });
+        chat["answered_by"] = json!({ message_id: "OpenAI · gpt-4" });
+        chats.to_string()
+    }
+
+    #[test]
+    fn moves_legacy_blobs() {
+        let backend = Memory::default();
+        let message_id = Uuid::new_v4().to_string();
+        let legacy = LegacyBlobs {
+            settings: Some(legacy_settings()),
+            chats: Some(legacy_chats(&message_id)),
+            agent_configs: None,
+        };
+        let raw = block_on(RawStoredStates::init_from(&backend, legacy)).unwrap();
+        let settings = &raw.raw_app_settings;
+        assert_eq!(settings.profiles.len(), 1);
+        assert_eq!(settings.profiles[0].name, GPTService::OpenAI.to_string());
+        assert_eq!(settings.profiles[0].model.as_deref(), Some("gpt-4"));
+        assert_eq!(settings.active_profile.as_deref(), Some(settings.profiles[0].name.as_str()));
+        let message = &raw.raw_chats.chats[0].messages[&message_id];
+        assert_eq!(message.role, Role::User);
+        assert_eq!(message.content, vec![ContentPart::Text { text: "Hello".to_string() }]);
+        assert_eq!(message.meta.profile.as_deref(), Some("OpenAI"));
+        assert_eq!(message.meta.model.as_deref(), Some("gpt-4"));
+        assert_eq!(raw.raw_agent_configs.name_to_configs.len(), RawAgentConfigs::init().name_to_configs.len());
+        // the states are now in the backend, in the current versions
+        let loaded = block_on(RawStoredStates::load_from(&backend)).unwrap().unwrap();
+        assert_eq!(loaded.raw_app_settings.profiles.len(), 1);
+        assert_eq!(loaded.raw_chats.chats[0].id, raw.raw_chats.chats[0].id);
+        assert!(loaded.raw_chats.chats[0].messages.contains_key(&message_id));
+    }
+
+    #[test]
+    fn backs_up_unloadable_records() {
+        let backend = Memory::default();
+        let configs: RawAgentConfigs = block_on(parse_record(&backend, Some("{ not json".to_string())));
+        assert_eq!(configs.name_to_configs.len(), RawAgentConfigs::init().name_to_configs.len());
+        let records = block_on(backend.get_all(STATES_STORE)).unwrap();
+        assert_eq!(records.len(), 1);
+        assert!(records[0].0.starts_with(&format!("{}_backup_", RawAgentConfigs::STORE_KEY)));
+        assert_eq!(records[0].1, "{ not json");
     }
 }
@@ -0,0 +1,146 @@
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::rc::Rc;
+use std::sync::Arc;
+use std::time::Duration;
+
+use async_std::task::sleep;
+use chrono::Utc;
+use dioxus::prelude::ScopeId;
+
+use crate::chat::MessageID;
+
+/// at most how often a streaming reply re-renders, which is 20 frames a second
+const FRAME_INTERVAL_MS: i64 = 50;
+
+type Update = Arc<dyn Fn() + Send + Sync>;
+
+/// Contents of replies while they stream.
+///
+/// Deltas are buffered here rather than written into `StoredStates` on every token,
+/// which would re-render the whole chat and the sidebars, and only the cards showing a reply are re-rendered, at a bounded frame rate.
+#[derive(Clone, Default)]
+pub struct StreamingReplies {
+    inner: Rc<RefCell<Inner>>,
+}
+
+#[derive(Default)]
+struct Inner {
+    buffers: HashMap<MessageID, Buffer>,
+    /// components to re-render when a reply changes
+    subscribers: HashMap<MessageID, HashMap<ScopeId, Update>>,
+}
+
+#[derive(Default)]
+struct Buffer {
+    content: String,
+    rendered_at: i64,
+    /// whether a render is due at the end of the frame, for deltas that came in too soon after the last one
+    flush_pending: bool,
+}
+
+impl Inner {
+    fn notify(&self, id: &MessageID) {
+        self.subscribers
+            .get(id)
+            .into_iter()
+            .flat_map(|subscribers| subscribers.values())
+            .for_each(|update| update());
+    }
+}
+
+impl StreamingReplies {
+    /// Re-render a component when the reply changes
+    pub fn subscribe(&self, id: MessageID, scope: ScopeId, update: Update) {
+        self.inner
+            .borrow_mut()
+            .subscribers
+            .entry(id)
+            .or_default()
+            .insert(scope, update);
+    }
+
+    /// Stop re-rendering a component, when it is gone
+    pub fn unsubscribe(&self, scope: ScopeId) {
+        self.inner
+            .borrow_mut()
+            .subscribers
+            .retain(|_, subscribers| {
+                subscribers.remove(&scope);
+                !subscribers.is_empty()
+            });
+    }
+
+    /// Start streaming a reply after what it already has, like the part of an interrupted reply to continue
+    pub fn start(&self, id: MessageID, prefix: String) {
+        self.inner.borrow_mut().buffers.insert(id, Buffer {
+            content: prefix,
+            ..Default::default()
+        });
+    }
+
+    /// Append a delta to a reply, re-rendering it if the last frame is old enough or at the end of the frame otherwise
+    pub fn push(&self, id: &MessageID, delta: &str) {
+        let mut inner = self.inner.borrow_mut();
+        let Some(buffer) = inner.buffers.get_mut(id) else {
+            return;
+        };
+        buffer.content.push_str(delta);
+        let now = Utc::now().timestamp_millis();
+        let wait_ms = buffer.rendered_at + FRAME_INTERVAL_MS - now;
+        if wait_ms <= 0 {
+            buffer.rendered_at = now;
+            buffer.flush_pending = false;
+            inner.notify(id);
+        } else if !buffer.flush_pending {
+            buffer.flush_pending = true;
+            drop(inner);
+            self.flush_after(*id, wait_ms);
+        }
+    }
+
+    /// Re-render a reply after a while, so that the last deltas before a pause of the stream are not left unshown
+    fn flush_after(&self, id: MessageID, wait_ms: i64) {
+        let replies = self.clone();
+        let flush = async move {
+            sleep(Duration::from_millis(wait_ms as u64)).await;
+            let mut inner = replies.inner.borrow_mut();
+            let Some(buffer) = inner.buffers.get_mut(&id).filter(|buffer| buffer.flush_pending) else {
+                return;
+            };
+            buffer.rendered_at = Utc::now().timestamp_millis();
+            buffer.flush_pending = false;
+            inner.notify(&id);
+        };
+        #[cfg(target_arch = "wasm32")]
+        wasm_bindgen_futures::spawn_local(flush);
+        // without a local executor to wait on, the reply is re-rendered right away
+        #[cfg(not(target_arch = "wasm32"))]
+        {
+            drop(flush);
+            let mut inner = self.inner.borrow_mut();
+            if let Some(buffer) = inner.buffers.get_mut(&id) {
+                buffer.rendered_at = Utc::now().timestamp_millis();
+                buffer.flush_pending = false;
+                inner.notify(&id);
+            }
+        }
+    }
+
+    /// What a reply has streamed so far, if it is streaming
+    pub fn content(&self, id: &MessageID) -> Option<String> {
+        self.inner
+            .borrow()
+            .buffers
+            .get(id)
+            .map(|buffer| buffer.content.clone())
+    }
+
+    /// Stop streaming a reply, returning all its content
+    pub fn finish(&self, id: &MessageID) -> Option<String> {
+        let mut inner = self.inner.borrow_mut();
+        let buffer = inner.buffers.remove(id)?;
+        inner.notify(id);
+        Some(buffer.content)
+    }
+}
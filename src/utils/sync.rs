@@ -0,0 +1,105 @@
+use std::collections::HashMap;
+
+use gloo_net::http::Request;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use uuid::Uuid;
+
+use crate::utils::sync::protocol::{ItemKind, SYNC_PATH, SyncRequest, SyncResponse};
+
+pub mod protocol;
+
+/// Where chats and agent configs are synced across devices, if they are
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct SyncSettings {
+    /// base URL of the sync server, which is empty if nothing is synced
+    #[serde(default)]
+    pub server_url: String,
+    /// token that the server is started with, if any, which is stored in the vault instead if there is one
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    pub token: String,
+    /// ID of this device, which breaks ties between changes made at the same time
+    #[serde(default = "Uuid::new_v4")]
+    pub device: Uuid,
+    /// the latest revision pulled from the server, 0 before the first sync
+    #[serde(default)]
+    pub revision: u64,
+    /// digest of each item as it was last synced, by its ledger key, to tell which items changed since
+    #[serde(default)]
+    pub synced: HashMap<String, u64>,
+    /// when synced chats were deleted on this device, by ledger key, until the server has the deletions
+    #[serde(default, skip_serializing_if = "HashMap::is_empty")]
+    pub deleted_at: HashMap<String, i64>,
+}
+
+impl Default for SyncSettings {
+    fn default() -> Self {
+        Self {
+            server_url: String::new(),
+            token: String::new(),
+            device: Uuid::new_v4(),
+            revision: 0,
+            synced: HashMap::new(),
+            deleted_at: HashMap::new(),
+        }
+    }
+}
+
+impl SyncSettings {
+    pub fn is_enabled(&self) -> bool {
+        !self.server_url.is_empty()
+    }
+
+    /// Sync with another server, or with none, from scratch
+    pub fn set_server(&mut self, server_url: String, token: String) {
+        if server_url != self.server_url {
+            self.revision = 0;
+            self.synced.clear();
+            self.deleted_at.clear();
+        }
+        self.server_url = server_url;
+        self.token = token;
+    }
+}
+
+/// Key of an item in the ledger of what was synced
+pub(crate) fn ledger_key(kind: ItemKind, key: &str) -> String {
+    format!("{}/{}", kind.as_str(), key)
+}
+
+/// Kind and key of an item from its ledger key
+pub(crate) fn parse_ledger_key(ledger_key: &str) -> Option<(ItemKind, &str)> {
+    let (kind, key) = ledger_key.split_once('/')?;
+    [ItemKind::Chat, ItemKind::AgentConfig]
+        .into_iter()
+        .find(|k| k.as_str() == kind)
+        .map(|kind| (kind, key))
+}
+
+/// 64-bit FNV-1a of a JSON value, which unlike `DefaultHasher` stays the same across builds
+pub(crate) fn digest(value: &Value) -> u64 {
+    value
+        .to_string()
+        .bytes()
+        .fold(0xcbf29ce484222325, |hash, byte| (hash ^ u64::from(byte)).wrapping_mul(0x100000001b3))
+}
+
+/// Push changed items to a sync server and pull those changed elsewhere
+pub async fn push_and_pull(settings: &SyncSettings, request: &SyncRequest) -> Result<SyncResponse, String> {
+    let url = format!("{}{}", settings.server_url.trim_end_matches('/'), SYNC_PATH);
+    let mut builder = Request::post(&url);
+    if !settings.token.is_empty() {
+        builder = builder.header("Authorization", &format!("Bearer {}", settings.token));
+    }
+    let response = builder
+        .json(request)
+        .map_err(|e| e.to_string())?
+        .send()
+        .await
+        .map_err(|e| e.to_string())?;
+    match response.status() {
+        200 => response.json().await.map_err(|e| e.to_string()),
+        401 => Err("The sync server takes another token".to_string()),
+        status => Err(format!("Syncing failed with status {}: {}", status, response.text().await.unwrap_or_default())),
+    }
+}
@@ -0,0 +1,73 @@
+//! The HTTP protocol between chitchai and its sync server.
+//!
+//! A device posts a `SyncRequest` as JSON to `SYNC_PATH`, with the items it changed since it last synced and the
+//! latest revision it pulled, and gets a `SyncResponse` with the items changed since by other devices.
+//! Items are whole chats and agent configs, and the last change to an item wins.
+//! If the server is started with a token, requests carry it as `Authorization: Bearer <token>`.
+
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use uuid::Uuid;
+
+pub const SYNC_PATH: &str = "/v1/sync";
+/// answers `{"protocol": PROTOCOL_VERSION}`, to tell whether a sync server is there
+pub const HEALTH_PATH: &str = "/v1/health";
+pub const PROTOCOL_VERSION: u32 = 1;
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq, Hash)]
+#[serde(rename_all = "snake_case")]
+pub enum ItemKind {
+    Chat,
+    AgentConfig,
+}
+
+impl ItemKind {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ItemKind::Chat => "chats",
+            ItemKind::AgentConfig => "agent_configs",
+        }
+    }
+}
+
+/// A version of a chat or an agent config
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct SyncItem {
+    pub kind: ItemKind,
+    /// ID of a chat or name of an agent
+    pub key: String,
+    /// the chat as its versioned blob in storage or the agent config, or `None` for a deleted chat
+    pub value: Option<Value>,
+    /// when it was changed, in milliseconds since the epoch by the clock of the device that changed it
+    pub updated_at: i64,
+    /// the device that changed it
+    pub device: Uuid,
+    /// order in which the server took it, which is 0 in requests
+    #[serde(default)]
+    pub revision: u64,
+}
+
+impl SyncItem {
+    /// Whether it replaces another version of the same item, which it does if it is changed later,
+    /// with ties broken by device IDs so that every device picks the same version
+    pub fn wins_over(&self, other: &SyncItem) -> bool {
+        (self.updated_at, self.device) > (other.updated_at, other.device)
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct SyncRequest {
+    /// the latest revision the device pulled, 0 if it never did
+    pub since: u64,
+    /// items the device changed since it last synced
+    pub items: Vec<SyncItem>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct SyncResponse {
+    /// the latest revision of the server, to pull from next time
+    pub revision: u64,
+    /// the items changed after `since` by others, and those of the request that lost to later changes, in the
+    /// order the server took them
+    pub items: Vec<SyncItem>,
+}
@@ -0,0 +1,179 @@
+use std::collections::{HashMap, HashSet};
+use std::rc::Rc;
+use std::time::Duration;
+
+use chrono::Utc;
+use futures::channel::mpsc::{unbounded, UnboundedReceiver};
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+use wasm_bindgen::closure::Closure;
+use wasm_bindgen::JsCast;
+use web_sys::{BroadcastChannel, MessageEvent};
+
+const CHANNEL_NAME: &str = "chitchai";
+/// how often a tab claims again the chats it is streaming into
+pub const CLAIM_HEARTBEAT: Duration = Duration::from_secs(5);
+/// how long a claim holds without being claimed again, after which its tab is taken as closed
+const CLAIM_TTL_MS: i64 = 15_000;
+
+/// What a tab tells the other tabs of the same storage
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+#[serde(tag = "type")]
+pub enum TabMessage {
+    /// (store, key) of records that were put or deleted
+    Saved { records: Vec<(String, String)> },
+    /// the tab is streaming replies into a chat
+    Claimed { chat: Uuid },
+    /// the tab is done with a chat
+    Released { chat: Uuid },
+}
+
+#[derive(Serialize, Deserialize)]
+struct Envelope {
+    tab: Uuid,
+    message: TabMessage,
+}
+
+/// A broadcast channel between the tabs of chitchai in a browser
+#[derive(Clone)]
+pub struct TabChannel {
+    tab: Uuid,
+    channel: Rc<BroadcastChannel>,
+}
+
+impl TabChannel {
+    pub fn open() -> Result<Self, String> {
+        let channel = BroadcastChannel::new(CHANNEL_NAME).map_err(|e| format!("{:?}", e))?;
+        Ok(Self {
+            tab: Uuid::new_v4(),
+            channel: Rc::new(channel),
+        })
+    }
+
+    /// Tell the other tabs, which do not include this one
+    pub fn post(&self, message: TabMessage) {
+        let envelope = Envelope { tab: self.tab, message };
+        let result = serde_json::to_string(&envelope)
+            .map_err(|e| e.to_string())
+            .and_then(|data| self.channel.post_message(&data.into()).map_err(|e| format!("{:?}", e)));
+        if let Err(e) = result {
+            log::error!("Error when posting {:?} to other tabs: {}", envelope.message, e);
+        }
+    }
+
+    /// (tab, message) of what the other tabs tell, which replaces the stream of an earlier call
+    pub fn listen(&self) -> UnboundedReceiver<(Uuid, TabMessage)> {
+        let (tx, rx) = unbounded();
+        let on_message = Closure::<dyn FnMut(MessageEvent)>::new(move |event: MessageEvent| {
+            let Some(data) = event.data().as_string() else {
+                return;
+            };
+            match serde_json::from_str::<Envelope>(&data) {
+                Ok(Envelope { tab, message }) => {
+                    let _ = tx.unbounded_send((tab, message));
+                }
+                Err(e) => log::warn!("Unknown message from another tab: {}", e),
+            }
+        });
+        self.channel.set_onmessage(Some(on_message.as_ref().unchecked_ref()));
+        // the handler lives as long as the channel, which is the whole session
+        on_message.forget();
+        rx
+    }
+}
+
+/// Chats that tabs are streaming replies into, so that no two tabs stream into the same chat.
+///
+/// A claim reaches other tabs in moments, so tabs can still race when they start replying in the same chat at once.
+#[derive(Clone, Default)]
+pub struct ChatLocks {
+    channel: Option<TabChannel>,
+    /// chats this tab is streaming into
+    held: HashSet<Uuid>,
+    /// chats other tabs are streaming into, with the tab and when it last claimed them
+    held_elsewhere: HashMap<Uuid, (Uuid, i64)>,
+}
+
+impl ChatLocks {
+    pub fn new(channel: Option<TabChannel>) -> Self {
+        Self {
+            channel,
+            ..Default::default()
+        }
+    }
+
+    /// Whether another tab is streaming into a chat
+    pub fn is_held_elsewhere(&self, chat: &Uuid) -> bool {
+        self.held_elsewhere
+            .get(chat)
+            .is_some_and(|(_, claimed_at)| Utc::now().timestamp_millis() - claimed_at < CLAIM_TTL_MS)
+    }
+
+    /// Claim a chat before streaming into it, unless another tab is streaming into it
+    pub fn acquire(&mut self, chat: Uuid) -> Result<(), String> {
+        if self.is_held_elsewhere(&chat) {
+            return Err("Another tab is replying in this chat".to_string());
+        }
+        self.held.insert(chat);
+        self.post(TabMessage::Claimed { chat });
+        Ok(())
+    }
+
+    /// Chats this tab is streaming into
+    pub fn held(&self) -> &HashSet<Uuid> {
+        &self.held
+    }
+
+    pub fn release(&mut self, chat: Uuid) {
+        if self.held.remove(&chat) {
+            self.post(TabMessage::Released { chat });
+        }
+    }
+
+    /// Claim again the chats this tab is streaming into, so that their claims do not expire
+    pub fn heartbeat(&self) {
+        self.held
+            .iter()
+            .for_each(|chat| self.post(TabMessage::Claimed { chat: *chat }));
+    }
+
+    /// Whether a message from another tab changes which chats are held, as opposed to claiming a held chat again
+    pub fn is_changed_by(&self, tab: Uuid, message: &TabMessage) -> bool {
+        match message {
+            TabMessage::Claimed { chat } => !self.is_held_elsewhere(chat),
+            TabMessage::Released { chat } => self.held_elsewhere.get(chat).is_some_and(|(holder, _)| *holder == tab),
+            TabMessage::Saved { .. } => false,
+        }
+    }
+
+    /// Take the claims from a message of another tab
+    pub fn receive(&mut self, tab: Uuid, message: &TabMessage) {
+        match message {
+            TabMessage::Claimed { chat } => {
+                self.held_elsewhere.insert(*chat, (tab, Utc::now().timestamp_millis()));
+            }
+            TabMessage::Released { chat } => {
+                if self.held_elsewhere.get(chat).is_some_and(|(holder, _)| *holder == tab) {
+                    self.held_elsewhere.remove(chat);
+                }
+            }
+            TabMessage::Saved { .. } => {}
+        }
+    }
+
+    /// Whether some claims of other tabs expired, like those of tabs closed while streaming
+    pub fn has_expired(&self) -> bool {
+        self.held_elsewhere.keys().any(|chat| !self.is_held_elsewhere(chat))
+    }
+
+    pub fn remove_expired(&mut self) {
+        let now = Utc::now().timestamp_millis();
+        self.held_elsewhere.retain(|_, (_, claimed_at)| now - *claimed_at < CLAIM_TTL_MS);
+    }
+
+    fn post(&self, message: TabMessage) {
+        if let Some(channel) = self.channel.as_ref() {
+            channel.post(message);
+        }
+    }
+}
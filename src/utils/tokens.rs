@@ -0,0 +1,113 @@
+use transprompt::async_openai::types::{ChatCompletionRequestMessage, ChatCompletionRequestUserMessageContent};
+
+use crate::agents::TruncationPolicy;
+
+/// tokens that every message takes for its role and separators
+const TOKENS_PER_MESSAGE: usize = 4;
+/// tokens that prime the reply
+const TOKENS_PER_REPLY: usize = 3;
+/// tokens kept for the reply when the max tokens are not set
+const DEFAULT_REPLY_RESERVE: usize = 1024;
+/// context window assumed for models that chitchai does not know
+pub const DEFAULT_CONTEXT_WINDOW: u32 = 4096;
+
+/// Count the tokens of a text offline.
+///
+/// This approximates BPE tokenizers without shipping their vocabularies:
+/// ASCII text takes about 4 characters a token, while CJK and other scripts take about a token a character.
+pub fn count_text(text: &str) -> usize {
+    let (ascii, others) = text
+        .chars()
+        .fold((0, 0), |(ascii, others), c| if c.is_ascii() { (ascii + 1, others) } else { (ascii, others + 1) });
+    (ascii + 3) / 4 + others
+}
+
+pub fn count_message(msg: &ChatCompletionRequestMessage) -> usize {
+    let content_tokens = match msg {
+        ChatCompletionRequestMessage::System(sys_msg) => count_text(&sys_msg.content),
+        ChatCompletionRequestMessage::User(user_msg) => {
+            let name_tokens = user_msg.name.as_deref().map_or(0, count_text);
+            name_tokens + match &user_msg.content {
+                ChatCompletionRequestUserMessageContent::Text(text) => count_text(text),
+                parts => count_text(&serde_json::to_string(parts).unwrap_or_default()),
+            }
+        }
+        ChatCompletionRequestMessage::Assistant(assistant_msg) => {
+            let name_tokens = assistant_msg.name.as_deref().map_or(0, count_text);
+            name_tokens + assistant_msg.content.as_deref().map_or(0, count_text)
+        }
+        msg => count_text(&serde_json::to_string(msg).unwrap_or_default()),
+    };
+    TOKENS_PER_MESSAGE + content_tokens
+}
+
+/// Count the prompt tokens of a request
+pub fn count_messages(messages: &[ChatCompletionRequestMessage]) -> usize {
+    messages.iter().map(count_message).sum::<usize>() + TOKENS_PER_REPLY
+}
+
+/// Tokens left for the prompt in a context window after keeping room for the reply
+pub fn prompt_budget(context_window: u32, max_tokens: Option<u16>) -> usize {
+    let context_window = context_window as usize;
+    let reply_reserve = max_tokens
+        .map(|t| t as usize)
+        .unwrap_or(DEFAULT_REPLY_RESERVE.min(context_window / 2));
+    context_window.saturating_sub(reply_reserve)
+}
+
+/// Cut messages down by a truncation policy so that they fit in `budget` tokens,
+/// returning the messages kept and the number of messages dropped.
+///
+/// The leading system messages and the last message are always kept.
+pub fn fit_context(messages: Vec<ChatCompletionRequestMessage>,
+                   policy: TruncationPolicy,
+                   budget: usize) -> (Vec<ChatCompletionRequestMessage>, usize) {
+    let total = messages.len();
+    let system_len = messages
+        .iter()
+        .take_while(|msg| matches!(msg, ChatCompletionRequestMessage::System(_)))
+        .count();
+    let mut turns = messages;
+    let mut kept: Vec<ChatCompletionRequestMessage> = turns.drain(..system_len).collect();
+    if let TruncationPolicy::KeepRecent(n) = policy {
+        // a turn starts at a message of the user and runs until the next one
+        let start = turns
+            .iter()
+            .enumerate()
+            .rev()
+            .filter(|(_, msg)| matches!(msg, ChatCompletionRequestMessage::User(_)))
+            .nth(n.max(1) - 1)
+            .map_or(0, |(idx, _)| idx);
+        turns.drain(..start);
+    }
+    let system_tokens = count_messages(&kept);
+    let mut turn_tokens: usize = turns.iter().map(count_message).sum();
+    let mut start = 0;
+    while system_tokens + turn_tokens > budget && turns.len() - start > 1 {
+        turn_tokens -= count_message(&turns[start]);
+        start += 1;
+    }
+    kept.extend(turns.drain(start..));
+    let dropped = total - kept.len();
+    (kept, dropped)
+}
+
+/// How full the context of an agent is
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ContextUsage {
+    /// prompt tokens that are sent
+    pub used: usize,
+    /// prompt tokens that the model takes
+    pub budget: usize,
+    /// messages dropped to fit in
+    pub dropped: usize,
+}
+
+impl ContextUsage {
+    pub fn percentage(&self) -> usize {
+        if self.budget == 0 {
+            return 100;
+        }
+        (self.used * 100 / self.budget).min(100)
+    }
+}
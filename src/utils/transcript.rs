@@ -0,0 +1,309 @@
+use std::fmt::Write;
+
+use pulldown_cmark::{Event, html, Options, Parser};
+use serde::Serialize;
+
+use crate::agents::AgentType;
+use crate::chat::{Chat, ContentPart, Message, Role};
+use crate::utils::datetime::DatetimeString;
+
+/// Format of an exported transcript
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Default)]
+pub enum TranscriptFormat {
+    #[default]
+    Markdown,
+    /// a self-contained page that looks like the chat
+    Html,
+    Json,
+}
+
+impl TranscriptFormat {
+    pub const fn all() -> [Self; 3] {
+        [Self::Markdown, Self::Html, Self::Json]
+    }
+
+    pub const fn label(&self) -> &'static str {
+        match self {
+            Self::Markdown => "Markdown",
+            Self::Html => "HTML",
+            Self::Json => "JSON",
+        }
+    }
+
+    pub const fn extension(&self) -> &'static str {
+        match self {
+            Self::Markdown => "md",
+            Self::Html => "html",
+            Self::Json => "json",
+        }
+    }
+
+    pub const fn mime(&self) -> &'static str {
+        match self {
+            Self::Markdown => "text/markdown",
+            Self::Html => "text/html",
+            Self::Json => "application/json",
+        }
+    }
+}
+
+/// The timeline of the user in a chat, as it is exported
+#[derive(Serialize, Clone, Debug, PartialEq)]
+pub struct Transcript {
+    topic: String,
+    started_at: String,
+    exported_at: String,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    system_prompts: Vec<TranscriptEntry>,
+    messages: Vec<TranscriptEntry>,
+}
+
+#[derive(Serialize, Clone, Debug, PartialEq)]
+struct TranscriptEntry {
+    author: String,
+    role: &'static str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    created_at: Option<String>,
+    /// "profile · model" that produced the message
+    #[serde(skip_serializing_if = "Option::is_none")]
+    produced_by: Option<String>,
+    parts: Vec<TranscriptPart>,
+}
+
+#[derive(Serialize, Clone, Debug, PartialEq)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum TranscriptPart {
+    Text { text: String },
+    Image { url: String },
+    ToolCall { name: String, arguments: String },
+}
+
+impl Transcript {
+    /// The transcript of a chat, with the system prompts of its assistants if asked for
+    pub fn of(chat: &Chat, include_system_prompts: bool) -> Self {
+        let user = chat.agents
+            .values()
+            .find(|agent| agent.config.agent_type == AgentType::User);
+        let messages = user
+            .into_iter()
+            .flat_map(|user| user.history.iter())
+            .filter_map(|id| chat.message_manager.message(id))
+            .map(|message| TranscriptEntry::of(chat, message))
+            .filter(|entry| !entry.parts.is_empty())
+            .collect();
+        let mut system_prompts: Vec<TranscriptEntry> = if include_system_prompts {
+            chat.agents
+                .values()
+                .filter(|agent| matches!(agent.config.agent_type, AgentType::Assistant { .. }))
+                .filter_map(|agent| {
+                    let message = chat.message_manager.message(agent.history.first()?)?;
+                    if message.chat_msg.role != Role::System {
+                        return None;
+                    }
+                    Some(TranscriptEntry {
+                        author: agent.get_name().to_string(),
+                        ..TranscriptEntry::of(chat, message)
+                    })
+                })
+                .collect()
+        } else {
+            vec![]
+        };
+        system_prompts.sort_by(|e1, e2| e1.author.cmp(&e2.author));
+        Self {
+            topic: chat.topic.clone(),
+            started_at: chat.date.formatted(),
+            exported_at: DatetimeString::get_now().formatted(),
+            system_prompts,
+            messages,
+        }
+    }
+
+    pub fn render(&self, format: TranscriptFormat) -> Result<String, String> {
+        match format {
+            TranscriptFormat::Markdown => Ok(self.to_markdown()),
+            TranscriptFormat::Html => Ok(self.to_html()),
+            TranscriptFormat::Json => serde_json::to_string_pretty(self).map_err(|e| e.to_string()),
+        }
+    }
+
+    fn to_markdown(&self) -> String {
+        let mut markdown = format!("# {}\n\nStarted at {} · exported at {}\n", self.topic, self.started_at, self.exported_at);
+        if !self.system_prompts.is_empty() {
+            markdown.push_str("\n## System prompts\n");
+            for entry in self.system_prompts.iter() {
+                let _ = write!(markdown, "\n### {}\n\n{}\n", entry.author, entry.markdown_body());
+            }
+            markdown.push_str("\n## Messages\n");
+        }
+        for entry in self.messages.iter() {
+            let _ = write!(markdown, "\n---\n\n**{}**", entry.author);
+            for detail in [&entry.created_at, &entry.produced_by].into_iter().flatten() {
+                let _ = write!(markdown, " · {}", detail);
+            }
+            let _ = write!(markdown, "\n\n{}\n", entry.markdown_body());
+        }
+        markdown
+    }
+
+    fn to_html(&self) -> String {
+        let mut body = String::new();
+        if !self.system_prompts.is_empty() {
+            body.push_str("<details><summary>System prompts</summary>");
+            for entry in self.system_prompts.iter() {
+                body.push_str(&entry.html_card());
+            }
+            body.push_str("</details>");
+        }
+        for entry in self.messages.iter() {
+            body.push_str(&entry.html_card());
+        }
+        format!(
+            "<!DOCTYPE html>\n<html>\n<head>\n<meta charset=\"utf-8\">\n<title>{topic}</title>\n<style>{style}</style>\n</head>\n<body>\n<h1>{topic}</h1>\n<p class=\"meta\">Started at {started_at} · exported at {exported_at}</p>\n{body}\n</body>\n</html>\n",
+            topic = escape_html(&self.topic),
+            style = HTML_STYLE,
+            started_at = escape_html(&self.started_at),
+            exported_at = escape_html(&self.exported_at),
+            body = body,
+        )
+    }
+}
+
+impl TranscriptEntry {
+    fn of(chat: &Chat, message: &Message) -> Self {
+        let author_name = message.meta.author
+            .and_then(|id| chat.agents.get(&id))
+            .map(|agent| agent.get_name().to_string());
+        let chat_msg = &message.chat_msg;
+        let role = match chat_msg.role {
+            Role::System => "system",
+            Role::User => "user",
+            Role::Assistant => "assistant",
+            Role::Tool | Role::Function => "tool",
+        };
+        let parts = chat_msg.content
+            .iter()
+            .flat_map(|part| match part {
+                ContentPart::Text { text } => text_parts(text),
+                ContentPart::Image { url, .. } => vec![TranscriptPart::Image { url: url.clone() }],
+            })
+            .chain(chat_msg.tool_calls
+                .iter()
+                .map(|call| TranscriptPart::ToolCall {
+                    name: call.name.clone(),
+                    arguments: call.arguments.clone(),
+                }))
+            .collect();
+        // system prompts and results of tool calls carry no author name of their own
+        let name = match chat_msg.role {
+            Role::User | Role::Assistant | Role::Function => chat_msg.name.clone(),
+            Role::System | Role::Tool => None,
+        };
+        let default_author = match role {
+            "system" => "System",
+            "user" => "User",
+            "assistant" => "Assistant",
+            _ => "Tool",
+        };
+        Self {
+            author: author_name.or(name).unwrap_or_else(|| default_author.to_string()),
+            role,
+            created_at: message.meta.created_at.as_ref().map(|t| t.formatted()),
+            produced_by: message.meta.producer(),
+            parts,
+        }
+    }
+
+    /// Parts in Markdown, each closing the code block it leaves open so that the next part is not swallowed into it
+    fn markdown_body(&self) -> String {
+        self.parts
+            .iter()
+            .map(|part| match part {
+                TranscriptPart::Text { text } => close_code_fences(text),
+                TranscriptPart::Image { url } => format!("![image]({})", url),
+                TranscriptPart::ToolCall { name, arguments } => format!("Called `{}` with\n\n```json\n{}\n```", name, arguments),
+            })
+            .collect::<Vec<_>>()
+            .join("\n\n")
+    }
+
+    /// A card like the message cards of the chat
+    fn html_card(&self) -> String {
+        let side = if self.role == "assistant" { "left" } else { "right" };
+        let initial = self.author.chars().next().unwrap_or('?');
+        let mut content = String::new();
+        // raw HTML in messages is shown as text rather than run in the page
+        let events = Parser::new_ext(&self.markdown_body(), Options::all())
+            .map(|event| match event {
+                Event::Html(html) | Event::InlineHtml(html) => Event::Text(html),
+                event => event,
+            });
+        html::push_html(&mut content, events);
+        let meta = [Some(&self.author), self.created_at.as_ref(), self.produced_by.as_ref()]
+            .into_iter()
+            .flatten()
+            .map(|detail| escape_html(detail))
+            .collect::<Vec<_>>()
+            .join(" · ");
+        format!(
+            "<div class=\"card {side}\"><div class=\"avatar {role}\">{initial}</div><div class=\"box\">{content}</div></div>\n<p class=\"meta {side}\">{meta}</p>\n",
+            side = side,
+            role = self.role,
+            initial = escape_html(&initial.to_string()),
+            content = content,
+            meta = meta,
+        )
+    }
+}
+
+fn text_parts(text: &str) -> Vec<TranscriptPart> {
+    if text.is_empty() {
+        return vec![];
+    }
+    vec![TranscriptPart::Text { text: text.to_string() }]
+}
+
+/// Close a code block that a text leaves open, like a reply cut off in the middle of some code
+fn close_code_fences(text: &str) -> String {
+    let fences = text
+        .lines()
+        .filter(|line| line.trim_start().starts_with("```"))
+        .count();
+    if fences % 2 == 1 {
+        format!("{}\n```", text)
+    } else {
+        text.to_string()
+    }
+}
+
+fn escape_html(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+/// The look of the message cards of the chat, without Tailwind
+const HTML_STYLE: &str = "\
+body { margin: 0 auto; max-width: 56rem; padding: 1.5rem; background: #e2e8f0; color: #0f172a; font-family: ui-sans-serif, system-ui, sans-serif; line-height: 1.75; }
+h1 { font-size: 1.25rem; }
+details { margin: 1rem 0; }
+summary { cursor: pointer; color: #64748b; }
+.card { display: flex; align-items: flex-start; padding: 1.25rem 1.25rem 0; }
+.card.right { flex-direction: row-reverse; }
+.avatar { flex: none; width: 2rem; height: 2rem; border-radius: 9999px; color: #fff; background: #363536; text-align: center; line-height: 2rem; }
+.avatar.user, .avatar.system { background: #354ea1; }
+.card.left .avatar { margin-right: 0.5rem; }
+.card.right .avatar { margin-left: 0.5rem; }
+.box { min-width: 0; max-width: 85%; border-radius: 0.75rem; padding: 0 1rem; background: #f8fafc; overflow-wrap: anywhere; }
+.box pre { overflow-x: auto; border-radius: 0.5rem; padding: 0.75rem; background: #1e293b; color: #e2e8f0; }
+.box code { font-family: ui-monospace, monospace; font-size: 0.875em; }
+.box img { max-width: 100%; }
+.meta { margin: 0.25rem 0 0; padding: 0 4rem; color: #64748b; font-size: 0.75rem; }
+.meta.right { text-align: right; }
+@media (prefers-color-scheme: dark) {
+    body { background: #0f172a; color: #cbd5e1; }
+    .box { background: #1e293b; }
+    .box pre { background: #020617; }
+}
+";
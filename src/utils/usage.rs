@@ -0,0 +1,137 @@
+use std::collections::BTreeMap;
+use std::ops::AddAssign;
+
+use chrono::Local;
+use serde::{Deserialize, Serialize};
+
+use crate::utils::models::ModelInfo;
+
+/// Tokens and cost of a completion
+#[derive(Debug, Clone, Copy, PartialEq, Default, Serialize, Deserialize)]
+pub struct Usage {
+    pub prompt_tokens: usize,
+    pub completion_tokens: usize,
+    /// USD, `None` if the prices of the model are unknown
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub cost: Option<f64>,
+    /// whether the tokens are counted locally rather than reported by the provider
+    #[serde(default)]
+    pub estimated: bool,
+}
+
+impl Usage {
+    pub fn new(prompt_tokens: usize, completion_tokens: usize, model: &ModelInfo, estimated: bool) -> Self {
+        let cost = match (model.prompt_price, model.completion_price) {
+            (Some(prompt_price), Some(completion_price)) =>
+                Some(prompt_tokens as f64 / 1000.0 * prompt_price + completion_tokens as f64 / 1000.0 * completion_price),
+            _ => None,
+        };
+        Self {
+            prompt_tokens,
+            completion_tokens,
+            cost,
+            estimated,
+        }
+    }
+
+    pub fn total_tokens(&self) -> usize {
+        self.prompt_tokens + self.completion_tokens
+    }
+
+    pub fn summary(&self) -> String {
+        let mut summary = match self.cost {
+            Some(cost) => format!("{} tokens · {}", format_tokens(self.total_tokens()), format_cost(cost)),
+            None => format!("{} tokens", format_tokens(self.total_tokens())),
+        };
+        if self.estimated {
+            summary.push_str(" (estimated)");
+        }
+        summary
+    }
+}
+
+impl AddAssign for Usage {
+    fn add_assign(&mut self, rhs: Self) {
+        self.prompt_tokens += rhs.prompt_tokens;
+        self.completion_tokens += rhs.completion_tokens;
+        self.cost = match (self.cost, rhs.cost) {
+            (Some(a), Some(b)) => Some(a + b),
+            (a, b) => a.or(b),
+        };
+        self.estimated |= rhs.estimated;
+    }
+}
+
+impl<'a> std::iter::Sum<&'a Usage> for Usage {
+    fn sum<I: Iterator<Item=&'a Usage>>(iter: I) -> Self {
+        iter.fold(Usage::default(), |mut total, usage| {
+            total += *usage;
+            total
+        })
+    }
+}
+
+/// Usage of every month, which outlives the chats
+#[derive(Debug, Clone, PartialEq, Default, Serialize, Deserialize)]
+pub struct UsageLedger {
+    /// keyed by `YYYY-MM`
+    pub monthly: BTreeMap<String, Usage>,
+}
+
+impl UsageLedger {
+    fn this_month_key() -> String {
+        Local::now().format("%Y-%m").to_string()
+    }
+
+    pub fn record(&mut self, usage: Usage) {
+        *self.monthly.entry(Self::this_month_key()).or_default() += usage;
+    }
+
+    pub fn this_month(&self) -> Usage {
+        self.monthly
+            .get(&Self::this_month_key())
+            .copied()
+            .unwrap_or_default()
+    }
+}
+
+/// Spending caps in USD, beyond which no new rounds are started
+#[derive(Debug, Clone, Copy, PartialEq, Default, Serialize, Deserialize)]
+pub struct SpendingCaps {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub per_chat: Option<f64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub monthly: Option<f64>,
+}
+
+impl SpendingCaps {
+    pub fn is_set(&self) -> bool {
+        self.per_chat.is_some() || self.monthly.is_some()
+    }
+
+    /// Which cap is exceeded by the spending of a chat and this month, if any
+    pub fn exceeded(&self, chat_usage: &Usage, monthly_usage: &Usage) -> Option<String> {
+        let over = |cap: Option<f64>, usage: &Usage| cap.zip(usage.cost).filter(|(cap, cost)| cost >= cap).map(|(cap, _)| cap);
+        if let Some(cap) = over(self.per_chat, chat_usage) {
+            return Some(format!("This chat has reached its spending cap of {}", format_cost(cap)));
+        }
+        over(self.monthly, monthly_usage)
+            .map(|cap| format!("This month has reached its spending cap of {}", format_cost(cap)))
+    }
+}
+
+pub fn format_tokens(tokens: usize) -> String {
+    if tokens >= 1000 {
+        format!("{:.1}k", tokens as f64 / 1000.0)
+    } else {
+        tokens.to_string()
+    }
+}
+
+pub fn format_cost(cost: f64) -> String {
+    if cost < 0.01 {
+        format!("${:.4}", cost)
+    } else {
+        format!("${:.2}", cost)
+    }
+}
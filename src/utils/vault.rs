@@ -0,0 +1,262 @@
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::fmt::{Debug, Formatter};
+use std::rc::Rc;
+
+use aes_gcm::{Aes256Gcm, Key, KeyInit, Nonce};
+use aes_gcm::aead::Aead;
+use argon2::{Algorithm, Argon2, Params, Version};
+use base64::Engine;
+use base64::engine::general_purpose::STANDARD as BASE64;
+use serde::{Deserialize, Serialize};
+
+use crate::utils::auth::Auth;
+
+const KEY_LEN: usize = 32;
+const SALT_LEN: usize = 16;
+const NONCE_LEN: usize = 12;
+/// minutes without any activity after which an unlocked vault locks itself
+pub const DEFAULT_AUTO_LOCK_MINUTES: u32 = 15;
+
+/// What the vault keeps encrypted
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq)]
+pub struct Secrets {
+    /// API keys of profiles by profile name
+    pub profiles: HashMap<String, Auth>,
+    /// token of the sync server
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    pub sync_token: String,
+}
+
+/// The vault as it is stored, which holds the secrets encrypted with a key derived from a passphrase
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct SealedVault {
+    /// 0 for never
+    pub auto_lock_minutes: u32,
+    /// Argon2id parameters, which are kept so that later versions can raise them without breaking older vaults
+    m_cost: u32,
+    t_cost: u32,
+    p_cost: u32,
+    salt: String,
+    nonce: String,
+    /// AES-256-GCM encrypted JSON of `Secrets`
+    ciphertext: String,
+}
+
+/// A vault of API secrets, which keeps only the key derived from the passphrase in memory, and only while unlocked
+#[derive(Clone)]
+pub struct Vault {
+    pub auto_lock_minutes: u32,
+    key: Option<[u8; KEY_LEN]>,
+    /// what was last sealed and the secrets it holds, so that the secrets are sealed again only when they change
+    sealed: Rc<RefCell<(SealedVault, Secrets)>>,
+}
+
+impl Vault {
+    /// A new unlocked vault holding no secrets yet
+    pub fn create(passphrase: &str, auto_lock_minutes: u32) -> Result<Self, String> {
+        if passphrase.is_empty() {
+            return Err("The passphrase is empty".to_string());
+        }
+        let params = Params::default();
+        let salt = random_bytes::<SALT_LEN>()?;
+        let key = derive_key(passphrase, &salt, &params)?;
+        let mut sealed = SealedVault {
+            auto_lock_minutes,
+            m_cost: params.m_cost(),
+            t_cost: params.t_cost(),
+            p_cost: params.p_cost(),
+            salt: BASE64.encode(salt),
+            nonce: String::new(),
+            ciphertext: String::new(),
+        };
+        seal(&key, &mut sealed, &Secrets::default())?;
+        Ok(Self {
+            auto_lock_minutes,
+            key: Some(key),
+            sealed: Rc::new(RefCell::new((sealed, Secrets::default()))),
+        })
+    }
+
+    /// A vault as it is loaded, which is locked
+    pub fn locked(sealed: SealedVault) -> Self {
+        Self {
+            auto_lock_minutes: sealed.auto_lock_minutes,
+            key: None,
+            sealed: Rc::new(RefCell::new((sealed, Secrets::default()))),
+        }
+    }
+
+    pub fn is_locked(&self) -> bool {
+        self.key.is_none()
+    }
+
+    /// Derive the key from a passphrase and decrypt the secrets with it
+    pub fn unlock(&mut self, passphrase: &str) -> Result<Secrets, String> {
+        let mut sealed = self.sealed.borrow_mut();
+        let vault = &sealed.0;
+        let params = Params::new(vault.m_cost, vault.t_cost, vault.p_cost, Some(KEY_LEN))
+            .map_err(|e| format!("Invalid key derivation parameters: {}", e))?;
+        let key = derive_key(passphrase, &decode(&vault.salt)?, &params)?;
+        let secrets = open(&key, vault)?;
+        sealed.1 = secrets.clone();
+        self.key = Some(key);
+        Ok(secrets)
+    }
+
+    /// Take a vault sealed elsewhere, like in another tab, staying unlocked if it is sealed with the same key
+    pub fn reload(&mut self, vault: SealedVault) {
+        self.auto_lock_minutes = vault.auto_lock_minutes;
+        let mut sealed = self.sealed.borrow_mut();
+        let same_key = (&sealed.0.salt, sealed.0.m_cost, sealed.0.t_cost, sealed.0.p_cost)
+            == (&vault.salt, vault.m_cost, vault.t_cost, vault.p_cost);
+        let secrets = self.key
+            .as_ref()
+            .filter(|_| same_key)
+            .and_then(|key| open(key, &vault).ok());
+        if secrets.is_none() {
+            self.key = None;
+        }
+        *sealed = (vault, secrets.unwrap_or_default());
+    }
+
+    /// The secrets it holds, which are empty while it is locked
+    pub fn secrets(&self) -> Secrets {
+        self.sealed.borrow().1.clone()
+    }
+
+    /// Forget the key and the secrets, keeping only what is sealed
+    pub fn lock(&mut self) {
+        self.key = None;
+        self.sealed.borrow_mut().1 = Secrets::default();
+    }
+
+    /// The vault to store, holding `secrets` if it is unlocked, or what it already holds if it is locked
+    pub fn seal(&self, secrets: Secrets) -> Result<SealedVault, String> {
+        let mut sealed = self.sealed.borrow_mut();
+        sealed.0.auto_lock_minutes = self.auto_lock_minutes;
+        if let Some(key) = self.key.as_ref() {
+            if sealed.1 != secrets {
+                seal(key, &mut sealed.0, &secrets)?;
+                sealed.1 = secrets;
+            }
+        }
+        Ok(sealed.0.clone())
+    }
+}
+
+impl Debug for Vault {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Vault")
+            .field("auto_lock_minutes", &self.auto_lock_minutes)
+            .field("locked", &self.is_locked())
+            .finish()
+    }
+}
+
+impl PartialEq for Vault {
+    fn eq(&self, other: &Self) -> bool {
+        self.auto_lock_minutes == other.auto_lock_minutes
+            && self.is_locked() == other.is_locked()
+            && self.sealed.borrow().0 == other.sealed.borrow().0
+    }
+}
+
+/// Encrypt secrets into a vault under a fresh nonce
+fn seal(key: &[u8; KEY_LEN], vault: &mut SealedVault, secrets: &Secrets) -> Result<(), String> {
+    let plaintext = serde_json::to_vec(secrets).map_err(|e| e.to_string())?;
+    let nonce = random_bytes::<NONCE_LEN>()?;
+    let ciphertext = Aes256Gcm::new(Key::<Aes256Gcm>::from_slice(key))
+        .encrypt(Nonce::from_slice(&nonce), plaintext.as_slice())
+        .map_err(|e| format!("Error when encrypting secrets: {}", e))?;
+    vault.nonce = BASE64.encode(nonce);
+    vault.ciphertext = BASE64.encode(ciphertext);
+    Ok(())
+}
+
+/// Decrypt the secrets in a vault
+fn open(key: &[u8; KEY_LEN], vault: &SealedVault) -> Result<Secrets, String> {
+    let plaintext = Aes256Gcm::new(Key::<Aes256Gcm>::from_slice(key))
+        .decrypt(Nonce::from_slice(&decode(&vault.nonce)?), decode(&vault.ciphertext)?.as_slice())
+        // authenticated encryption fails the same on a wrong passphrase and on tampering
+        .map_err(|_| "Wrong passphrase".to_string())?;
+    serde_json::from_slice(&plaintext)
+        // vaults sealed before the sync token was kept in them hold only the API keys of profiles
+        .or_else(|e| serde_json::from_slice(&plaintext)
+            .map(|profiles| Secrets { profiles, ..Default::default() })
+            .map_err(|_| e.to_string()))
+}
+
+fn derive_key(passphrase: &str, salt: &[u8], params: &Params) -> Result<[u8; KEY_LEN], String> {
+    let mut key = [0_u8; KEY_LEN];
+    Argon2::new(Algorithm::Argon2id, Version::V0x13, params.clone())
+        .hash_password_into(passphrase.as_bytes(), salt, &mut key)
+        .map_err(|e| format!("Error when deriving the key: {}", e))?;
+    Ok(key)
+}
+
+fn random_bytes<const N: usize>() -> Result<[u8; N], String> {
+    let mut bytes = [0_u8; N];
+    getrandom::getrandom(&mut bytes).map_err(|e| format!("Error when getting random bytes: {}", e))?;
+    Ok(bytes)
+}
+
+fn decode(encoded: &str) -> Result<Vec<u8>, String> {
+    BASE64.decode(encoded).map_err(|e| format!("Invalid vault: {}", e))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn secrets() -> Secrets {
+        let auth = Auth::OpenAI {
+            api_key: "sk-test".to_string(),
+            org_id: None,
+            api_base: None,
+        };
+        Secrets {
+            profiles: HashMap::from([("OpenAI".to_string(), auth)]),
+            sync_token: "token".to_string(),
+        }
+    }
+
+    #[test]
+    fn unlocks_what_is_sealed() {
+        let vault = Vault::create("passphrase", DEFAULT_AUTO_LOCK_MINUTES).unwrap();
+        let sealed = vault.seal(secrets()).unwrap();
+        let mut loaded = Vault::locked(sealed.clone());
+        assert!(loaded.is_locked());
+        assert_eq!(loaded.secrets(), Secrets::default());
+        // a locked vault keeps what is sealed in it
+        assert_eq!(loaded.seal(Secrets::default()).unwrap(), sealed);
+        assert_eq!(loaded.unlock("passphrase").unwrap(), secrets());
+        assert!(!loaded.is_locked());
+        assert_eq!(loaded.secrets(), secrets());
+    }
+
+    #[test]
+    fn refuses_a_wrong_passphrase() {
+        let vault = Vault::create("passphrase", 0).unwrap();
+        let mut loaded = Vault::locked(vault.seal(secrets()).unwrap());
+        assert_eq!(loaded.unlock("wrong").unwrap_err(), "Wrong passphrase");
+        assert!(loaded.is_locked());
+        assert!(Vault::create("", 0).is_err());
+    }
+
+    #[test]
+    fn opens_vaults_of_bare_profiles() {
+        let key = [7_u8; KEY_LEN];
+        let mut sealed = Vault::create("passphrase", 0).unwrap().seal(Secrets::default()).unwrap();
+        let plaintext = serde_json::to_vec(&secrets().profiles).unwrap();
+        let nonce = [0_u8; NONCE_LEN];
+        let ciphertext = Aes256Gcm::new(Key::<Aes256Gcm>::from_slice(&key))
+            .encrypt(Nonce::from_slice(&nonce), plaintext.as_slice())
+            .unwrap();
+        sealed.nonce = BASE64.encode(nonce);
+        sealed.ciphertext = BASE64.encode(ciphertext);
+        let opened = open(&key, &sealed).unwrap();
+        assert_eq!(opened.profiles, secrets().profiles);
+        assert!(opened.sync_token.is_empty());
+    }
+}